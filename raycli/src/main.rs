@@ -1,7 +1,9 @@
 use std::sync::{Arc, Mutex};
 
-use image::{Rgba, RgbaImage};
-use raylib::{random_scene, Image, Rand, Raytracer, RaytracerOptions};
+use image::{Rgb, Rgb32FImage, Rgba, RgbaImage};
+use raylib::{
+    random_scene, Aov, Denoiser, EnvironmentMap, Image, Rand, Raytracer, RaytracerOptions,
+};
 
 use rayon::prelude::*;
 
@@ -11,26 +13,44 @@ fn main() {
     let height = 800;
     let aspect_ratio = width as f64 / height as f64;
     let mut rand = Rand::new();
-    let scene = random_scene(&mut rand);
+    let mut scene = random_scene(&mut rand);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // Recording AOVs slows rendering, so they and the denoised image they
+    // guide are only made when asked for.
+    let aovs = args.iter().any(|arg| arg == "--aovs");
+    // An equirectangular .hdr or .exr file can light the scene.
+    if let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) {
+        let environment = image::open(path).unwrap().into_rgb32f();
+        scene.set_background(Box::new(EnvironmentMap::from_rgb32f(
+            environment.width() as usize,
+            environment.height() as usize,
+            environment.as_raw(),
+        )));
+    }
     let image_mutex = Mutex::new(Image::new(width, height));
     let raytracer = Arc::new(Raytracer::new(
         scene,
         &RaytracerOptions {
             image_width: width,
             aspect_ratio,
-            max_depth: 50,
             samples_per_pixel: 1,
+            aovs,
+            ..Default::default()
         },
     ));
 
     (0..height).into_par_iter().for_each(|line_number| {
         let mut rand_seed: [u8; 16] = [0; 16];
-        getrandom::getrandom(&mut rand_seed);
+        getrandom::getrandom(&mut rand_seed).unwrap();
         let mut rand = Rand::new_with_seed(u128::from_le_bytes(rand_seed));
-        let line = raytracer.trace_line(line_number, &mut rand);
-        let mut image = image_mutex.lock().unwrap();
-        image.set_line(line_number, line);
+        raytracer.trace_line(line_number, &mut rand);
     });
+    // Light paths reach the camera across the whole image, so lines are
+    // only finished once all of them are traced.
+    for line_number in 0..height {
+        let mut image = image_mutex.lock().unwrap();
+        image.set_line(line_number, raytracer.developed_line(line_number));
+    }
 
     let mut image = RgbaImage::new(width, height);
     let ray_img = image_mutex.lock().unwrap();
@@ -44,4 +64,47 @@ fn main() {
     image
         .save_with_format("./output-draft.png", image::ImageFormat::Png)
         .unwrap();
+
+    if aovs {
+        save_aovs(&raytracer, width, height);
+    }
+}
+
+/// Writes a denoised image and each AOV beside the image.
+fn save_aovs(raytracer: &Raytracer, width: u32, height: u32) {
+    // The denoised image goes beside the noisy one.
+    let denoiser = Denoiser::new();
+    let denoised: Vec<Vec<u8>> = (0..height)
+        .into_par_iter()
+        .map(|line_number| raytracer.denoised_line(&denoiser, line_number))
+        .collect();
+    let mut image = RgbaImage::new(width, height);
+    for (j, line) in denoised.iter().enumerate() {
+        for i in 0..width as usize {
+            let rgba = [
+                line[4 * i],
+                line[4 * i + 1],
+                line[4 * i + 2],
+                line[4 * i + 3],
+            ];
+            image.put_pixel(i as u32, height - j as u32 - 1, Rgba(rgba));
+        }
+    }
+    image
+        .save_with_format("./output-denoised.png", image::ImageFormat::Png)
+        .unwrap();
+
+    // Each AOV goes to its own linear EXR beside the image.
+    for aov in Aov::ALL {
+        let mut buffer = Rgb32FImage::new(width, height);
+        for j in 0..height {
+            for (i, c) in raytracer.aov_line(aov, j).unwrap().iter().enumerate() {
+                let pixel = Rgb([c.x() as f32, c.y() as f32, c.z() as f32]);
+                buffer.put_pixel(i as u32, height - j - 1, pixel);
+            }
+        }
+        buffer
+            .save(format!("./output-draft-{}.exr", aov.name()))
+            .unwrap();
+    }
 }
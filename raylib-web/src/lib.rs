@@ -52,8 +52,8 @@ pub fn raytrace() {
         &RaytracerOptions {
             image_width: width,
             aspect_ratio,
-            max_depth: 50,
             samples_per_pixel: 1,
+            ..Default::default()
         },
     ));
 
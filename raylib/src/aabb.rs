@@ -0,0 +1,94 @@
+use crate::{ray::Ray, vec3::Point3, Vec3};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    minimum: Point3,
+    maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(minimum: Point3, maximum: Point3) -> Self {
+        Aabb { minimum, maximum }
+    }
+
+    pub fn min(&self) -> &Point3 {
+        &self.minimum
+    }
+
+    pub fn max(&self) -> &Point3 {
+        &self.maximum
+    }
+
+    /// Returns the parametric interval over which `r` is inside the box,
+    /// clipped to `t_min..t_max`.
+    pub fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let mut ta = (self.minimum[a] - r.origin()[a]) * inv_d;
+            let mut tb = (self.maximum[a] - r.origin()[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut ta, &mut tb);
+            }
+            t0 = if ta > t0 { ta } else { t0 };
+            t1 = if tb < t1 { tb } else { t1 };
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point3::new(
+                self.minimum.x().min(other.minimum.x()),
+                self.minimum.y().min(other.minimum.y()),
+                self.minimum.z().min(other.minimum.z()),
+            ),
+            Point3::new(
+                self.maximum.x().max(other.maximum.x()),
+                self.maximum.y().max(other.maximum.y()),
+                self.maximum.z().max(other.maximum.z()),
+            ),
+        )
+    }
+
+    /// The overlap of two boxes, which may be empty.
+    pub fn overlap(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point3::new(
+                self.minimum.x().max(other.minimum.x()),
+                self.minimum.y().max(other.minimum.y()),
+                self.minimum.z().max(other.minimum.z()),
+            ),
+            Point3::new(
+                self.maximum.x().min(other.maximum.x()),
+                self.maximum.y().min(other.maximum.y()),
+                self.maximum.z().min(other.maximum.z()),
+            ),
+        )
+    }
+
+    /// Bounds a disk of `radius` centered at `center` and facing the unit
+    /// vector `axis`.
+    pub fn disk(center: &Point3, axis: &Vec3, radius: f64) -> Aabb {
+        let e = Vec3::new(
+            radius * (1.0 - axis.x() * axis.x()).max(0.0).sqrt(),
+            radius * (1.0 - axis.y() * axis.y()).max(0.0).sqrt(),
+            radius * (1.0 - axis.z() * axis.z()).max(0.0).sqrt(),
+        );
+        Aabb::new(center - e, center + e)
+    }
+
+    /// Maps a world-space point to `[0, 1]` coordinates within the box.
+    pub fn to_local(&self, p: &Point3) -> Point3 {
+        let extent = self.maximum - self.minimum;
+        let d = p - self.minimum;
+        Point3::new(d.x() / extent.x(), d.y() / extent.y(), d.z() / extent.z())
+    }
+}
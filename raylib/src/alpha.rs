@@ -0,0 +1,171 @@
+use crate::{
+    hittable::HitRecord,
+    material::{InteriorEvent, Material, ScatterRecord},
+    rand::Rand,
+    ray::Ray,
+    texture::TextureRef,
+    vec3::Color,
+    Vec3,
+};
+
+/// How an `AlphaMask` turns alpha into a cut.
+#[derive(Debug, Clone, Copy)]
+pub enum AlphaMode {
+    /// Hard edges: the surface is cut away wherever alpha is below the
+    /// threshold.
+    Threshold(f64),
+    /// Partial coverage: rays pass through with probability `1 - alpha`,
+    /// which averages out to soft edges over many samples.
+    Stochastic,
+}
+
+/// Cutout transparency for leaves, fences and decals. Wherever the first
+/// channel of the `alpha` texture masks the surface out, the hit is
+/// rejected and rays carry on to whatever lies behind it.
+pub struct AlphaMask {
+    material: Box<dyn Material + Send + Sync>,
+    alpha: TextureRef,
+    mode: AlphaMode,
+}
+
+impl AlphaMask {
+    pub fn new(material: Box<dyn Material + Send + Sync>, alpha: TextureRef) -> Self {
+        AlphaMask {
+            material,
+            alpha,
+            mode: AlphaMode::Threshold(0.5),
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: AlphaMode) {
+        self.mode = mode
+    }
+}
+
+impl Material for AlphaMask {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        self.material.sample(r_in, rec, rand)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.material.eval(r_in, rec, wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        self.material.pdf(r_in, rec, wi)
+    }
+
+    fn sample_interior(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> InteriorEvent {
+        self.material.sample_interior(r_in, rec, rand)
+    }
+
+    fn is_cut_out(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        let alpha = self.alpha.value(rec.u, rec.v, &rec.p).x();
+        match self.mode {
+            AlphaMode::Threshold(threshold) => alpha < threshold,
+            AlphaMode::Stochastic => alpha < hash_hit(r_in, rec.t),
+        }
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.material.shading_normal(r_in, rec)
+    }
+
+    fn non_specular_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        self.material.non_specular_probability(r_in, rec)
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.material.albedo(r_in, rec)
+    }
+}
+
+/// A uniform value in `0..1` determined by the ray and hit distance.
+/// Intersection has no random state, so the same hit always gets the same
+/// answer while different samples decorrelate.
+fn hash_hit(r: &Ray, t: f64) -> f64 {
+    let (o, d) = (r.origin(), r.direction());
+    let mut h = 0x9e37_79b9_7f4a_7c15_u64;
+    for x in [o.x(), o.y(), o.z(), d.x(), d.y(), d.z(), t] {
+        // splitmix64 finalizer
+        h = (h ^ x.to_bits()).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{DidHit, Hittable},
+        material::Lambertian,
+        scene::Scene,
+        sphere::Sphere,
+        texture::SolidColor,
+        vec3::Point3,
+    };
+
+    fn masked(alpha: f64, mode: AlphaMode) -> AlphaMask {
+        let mut mask = AlphaMask::new(
+            Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            SolidColor::scalar(alpha),
+        );
+        mask.set_mode(mode);
+        mask
+    }
+
+    #[test]
+    fn threshold_cuts_below_it() {
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let rec = HitRecord::default();
+        assert!(masked(0.3, AlphaMode::Threshold(0.5)).is_cut_out(&r, &rec));
+        assert!(!masked(0.7, AlphaMode::Threshold(0.5)).is_cut_out(&r, &rec));
+    }
+
+    #[test]
+    fn stochastic_covers_the_alpha_fraction() {
+        let mask = masked(0.3, AlphaMode::Stochastic);
+        let rec = HitRecord {
+            t: 1.0,
+            ..Default::default()
+        };
+        let n = 10_000;
+        let covered = (0..n)
+            .filter(|&i| {
+                let r = Ray::new(Point3::new(i as f64, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+                !mask.is_cut_out(&r, &rec)
+            })
+            .count();
+        assert!((covered as f64 / n as f64 - 0.3).abs() < 0.02);
+
+        // The same hit always gets the same answer.
+        let r = Ray::new(Point3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(mask.is_cut_out(&r, &rec), mask.is_cut_out(&r, &rec));
+    }
+
+    #[test]
+    fn scene_sees_through_cut_out_surfaces() {
+        let mut scene = Scene::new();
+        let hole = scene.add_material(Box::new(masked(0.0, AlphaMode::Threshold(0.5))));
+        let solid = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        scene.add_object(Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, hole)));
+        scene.add_object(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, 5.0),
+            1.0,
+            solid,
+        )));
+
+        // Both the near and the far side of the cut out sphere are skipped.
+        let r = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        match scene.hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                assert_eq!(rec.material_id(), solid);
+                assert!((rec.t - 9.0).abs() < 1e-9);
+            }
+            DidHit::Miss => panic!("missed the solid sphere"),
+        }
+    }
+}
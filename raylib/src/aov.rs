@@ -0,0 +1,291 @@
+//! Arbitrary output variables: images of the first surface seen through
+//! each pixel and of the light reaching the camera split by how it got
+//! there, for compositing and denoising.
+
+use std::sync::Mutex;
+
+use crate::{ray::Ray, scene::Scene, vec3::Color};
+
+/// An image recorded alongside the render when the options' `aovs` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Color of the first surface seen, as its material's `albedo` gives
+    /// it.
+    Albedo,
+    /// World space shading normal of the first surface seen, facing the
+    /// camera.
+    Normal,
+    /// Distance from the camera to the first surface seen, or zero where
+    /// rays escape.
+    Depth,
+    /// World space position of the first surface seen.
+    Position,
+    /// One more than the index of the object seen, in the order objects
+    /// were added to the scene, or zero where rays escape. Taken from the
+    /// first sample through each pixel that hits anything, so edges are
+    /// not blended.
+    ObjectId,
+    /// One more than the id of the material seen, or zero where rays
+    /// escape, from the same sample as `ObjectId`.
+    MaterialId,
+    /// Light scattered to the camera by a diffuse lobe straight from a
+    /// light or the background.
+    DirectDiffuse,
+    /// Light scattered to the camera by a diffuse lobe after other
+    /// scattering.
+    IndirectDiffuse,
+    /// Light scattered to the camera by a glossy or specular lobe straight
+    /// from a light or the background.
+    DirectSpecular,
+    /// Light scattered to the camera by a glossy or specular lobe after
+    /// other scattering.
+    IndirectSpecular,
+    /// Light reaching the camera without scattering, from the background
+    /// and glowing media.
+    Emission,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 11] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::DirectDiffuse,
+        Aov::IndirectDiffuse,
+        Aov::DirectSpecular,
+        Aov::IndirectSpecular,
+        Aov::Emission,
+    ];
+
+    /// A short lowercase name, for file or layer names.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::IndirectDiffuse => "indirect_diffuse",
+            Aov::DirectSpecular => "direct_specular",
+            Aov::IndirectSpecular => "indirect_specular",
+            Aov::Emission => "emission",
+        }
+    }
+}
+
+/// The light a path brought to the camera, split as the light AOVs are.
+/// Light from media scattering first counts as diffuse.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LightPasses {
+    emission: Color,
+    direct_diffuse: Color,
+    indirect_diffuse: Color,
+    direct_specular: Color,
+    indirect_specular: Color,
+}
+
+impl Default for LightPasses {
+    fn default() -> Self {
+        let black = Color::new(0.0, 0.0, 0.0);
+        LightPasses {
+            emission: black,
+            direct_diffuse: black,
+            indirect_diffuse: black,
+            direct_specular: black,
+            indirect_specular: black,
+        }
+    }
+}
+
+impl LightPasses {
+    /// Adds `light` that reached the camera after `scatters` scattering
+    /// events, the first of which was by a diffuse lobe if `diffuse`.
+    pub(crate) fn add(&mut self, scatters: usize, diffuse: bool, light: Color) {
+        let pass = match (scatters, diffuse) {
+            (0, _) => &mut self.emission,
+            (1, true) => &mut self.direct_diffuse,
+            (1, false) => &mut self.direct_specular,
+            (_, true) => &mut self.indirect_diffuse,
+            (_, false) => &mut self.indirect_specular,
+        };
+        *pass += light;
+    }
+
+    /// All the light, as rendered.
+    pub(crate) fn total(&self) -> Color {
+        self.emission
+            + self.direct_diffuse
+            + self.indirect_diffuse
+            + self.direct_specular
+            + self.indirect_specular
+    }
+}
+
+/// Sums of the AOVs of the samples taken through one pixel.
+pub(crate) struct AovPixel {
+    sums: [Color; Aov::ALL.len()],
+    samples: u32,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        AovPixel {
+            sums: [Color::new(0.0, 0.0, 0.0); Aov::ALL.len()],
+            samples: 0,
+        }
+    }
+}
+
+impl AovPixel {
+    /// Records the AOVs of the camera ray `r`, whose light was split into
+    /// `passes`.
+    pub(crate) fn add_sample(&mut self, r: &Ray, scene: &Scene, passes: &LightPasses) {
+        self.samples += 1;
+        let sums = &mut self.sums;
+        sums[Aov::DirectDiffuse as usize] += passes.direct_diffuse;
+        sums[Aov::IndirectDiffuse as usize] += passes.indirect_diffuse;
+        sums[Aov::DirectSpecular as usize] += passes.direct_specular;
+        sums[Aov::IndirectSpecular as usize] += passes.indirect_specular;
+        sums[Aov::Emission as usize] += passes.emission;
+
+        let (object, rec) = match scene.hit_object(r, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return,
+        };
+        let material = scene.get_material(rec.material_id());
+        sums[Aov::Albedo as usize] += material.albedo(r, &rec);
+        sums[Aov::Normal as usize] += material.shading_normal(r, &rec);
+        let depth = rec.t * r.direction().length();
+        sums[Aov::Depth as usize] += Color::new(depth, depth, depth);
+        sums[Aov::Position as usize] += rec.p;
+        // Ids start at one, so zero means no sample has hit yet.
+        if sums[Aov::ObjectId as usize].x() == 0.0 {
+            let object = (object + 1) as f64;
+            let material = (rec.material_id() + 1) as f64;
+            sums[Aov::ObjectId as usize] = Color::new(object, object, object);
+            sums[Aov::MaterialId as usize] = Color::new(material, material, material);
+        }
+    }
+
+    /// The AOVs of the pixel, averaged over its samples.
+    pub(crate) fn average(&self) -> [Color; Aov::ALL.len()] {
+        let samples = self.samples.max(1) as f64;
+        let mut average = self.sums;
+        for aov in Aov::ALL {
+            if aov != Aov::ObjectId && aov != Aov::MaterialId {
+                average[aov as usize] /= samples;
+            }
+        }
+        average
+    }
+}
+
+/// Every AOV of every pixel, row by row.
+pub(crate) struct AovBuffers {
+    width: usize,
+    pixels: Mutex<Vec<[Color; Aov::ALL.len()]>>,
+}
+
+impl AovBuffers {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        let black = [Color::new(0.0, 0.0, 0.0); Aov::ALL.len()];
+        AovBuffers {
+            width: width as usize,
+            pixels: Mutex::new(vec![black; (width * height) as usize]),
+        }
+    }
+
+    /// Records the AOVs of line `y`.
+    pub(crate) fn set_line(&self, y: u32, line: &[[Color; Aov::ALL.len()]]) {
+        let start = y as usize * self.width;
+        self.pixels.lock().unwrap()[start..start + self.width].copy_from_slice(line);
+    }
+
+    /// `aov` for each pixel of line `y`.
+    pub(crate) fn line(&self, aov: Aov, y: u32) -> Vec<Color> {
+        let start = y as usize * self.width;
+        self.pixels.lock().unwrap()[start..start + self.width]
+            .iter()
+            .map(|pixel| pixel[aov as usize])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, sphere::Sphere, vec3::Point3, Vec3};
+
+    #[test]
+    fn light_is_split_by_scatters_and_lobe() {
+        let mut passes = LightPasses::default();
+        passes.add(0, true, Color::new(1.0, 0.0, 0.0));
+        passes.add(1, true, Color::new(0.0, 1.0, 0.0));
+        passes.add(1, false, Color::new(0.0, 0.0, 1.0));
+        passes.add(3, true, Color::new(2.0, 0.0, 0.0));
+        passes.add(2, false, Color::new(0.0, 2.0, 0.0));
+        assert_eq!(passes.emission.x(), 1.0);
+        assert_eq!(passes.direct_diffuse.y(), 1.0);
+        assert_eq!(passes.direct_specular.z(), 1.0);
+        assert_eq!(passes.indirect_diffuse.x(), 2.0);
+        assert_eq!(passes.indirect_specular.y(), 2.0);
+        assert!((passes.total() - Color::new(3.0, 3.0, 1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn pixels_average_all_but_the_ids() {
+        let mut scene = Scene::new();
+        let grey = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let red = scene.add_material(Box::new(Lambertian::new(Color::new(0.8, 0.2, 0.2))));
+        scene.add_object(Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, grey)));
+        scene.add_object(Box::new(Sphere::new(Point3::new(3.0, 0.0, 0.0), 1.0, red)));
+
+        let origin = Point3::new(3.0, 0.0, -5.0);
+        let miss = Ray::new(origin, Vec3::new(0.0, 1.0, 0.0));
+        let hit = Ray::new(origin, Vec3::new(0.0, 0.0, 1.0));
+        let passes = LightPasses::default();
+        let mut pixel = AovPixel::default();
+        pixel.add_sample(&miss, &scene, &passes);
+        pixel.add_sample(&hit, &scene, &passes);
+        let average = pixel.average();
+
+        // Half the samples hit the second sphere at distance 4.
+        assert!((average[Aov::Depth as usize].x() - 2.0).abs() < 1e-9);
+        let normal = average[Aov::Normal as usize];
+        assert!((normal - Vec3::new(0.0, 0.0, -0.5)).length() < 1e-9);
+        let albedo = average[Aov::Albedo as usize];
+        assert!((albedo - Color::new(0.4, 0.1, 0.1)).length() < 1e-9);
+        // Ids are the hit's own, not averaged with the miss.
+        assert_eq!(average[Aov::ObjectId as usize].x(), 2.0);
+        assert_eq!(average[Aov::MaterialId as usize].x(), (red + 1) as f64);
+    }
+
+    #[test]
+    fn ids_come_from_the_first_hit() {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        scene.add_object(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            material,
+        )));
+        scene.add_object(Box::new(Sphere::new(
+            Point3::new(3.0, 0.0, 0.0),
+            1.0,
+            material,
+        )));
+
+        let passes = LightPasses::default();
+        let mut pixel = AovPixel::default();
+        for x in [3.0, 0.0] {
+            let r = Ray::new(Point3::new(x, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+            pixel.add_sample(&r, &scene, &passes);
+        }
+        assert_eq!(pixel.average()[Aov::ObjectId as usize].x(), 2.0);
+    }
+}
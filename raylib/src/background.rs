@@ -0,0 +1,276 @@
+use crate::{
+    distribution::Distribution2D,
+    light::{BoundingSphere, EmissionSample},
+    onb::Onb,
+    rand::Rand,
+    ray::Ray,
+    util::{degrees_to_radians, PI},
+    vec3::{luminance, random_cone_direction, unit_vector, Color},
+    Vec3,
+};
+
+/// A direction towards the background sampled for direct lighting.
+#[derive(Debug, Clone, Copy)]
+pub struct BackgroundSample {
+    pub direction: Vec3,
+    pub radiance: Color,
+    /// Solid angle density of `direction`.
+    pub pdf: f64,
+}
+
+/// Light arriving from infinitely far away, seen by rays that leave the
+/// scene.
+pub trait Background {
+    /// Radiance arriving along the unit vector `direction`, pointing away
+    /// from the scene.
+    fn radiance(&self, direction: &Vec3) -> Color;
+
+    /// Samples a direction to gather light from, or `None` if the
+    /// background is only found by rays that happen to escape.
+    fn sample(&self, _rand: &mut Rand) -> Option<BackgroundSample> {
+        None
+    }
+
+    /// Density with which `sample` produces `direction`.
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+/// A direction for light to leave `background` towards, for paths traced
+/// from the lights. Directions are drawn half the time from the
+/// background's own sampling and half the time uniformly, so backgrounds
+/// that cannot be sampled still are.
+pub(crate) fn sample_emission_direction(
+    background: &(dyn Background + Send + Sync),
+    rand: &mut Rand,
+) -> Option<Vec3> {
+    if rand.random_double() < 0.5 {
+        background.sample(rand).map(|s| s.direction)
+    } else {
+        Some(random_cone_direction(-1.0, rand))
+    }
+}
+
+/// Density with which `sample_emission_direction` produces `direction`.
+pub(crate) fn emission_pdf(background: &(dyn Background + Send + Sync), direction: &Vec3) -> f64 {
+    0.5 * background.pdf(direction) + 0.5 / (4.0 * PI)
+}
+
+/// A ray of light from `background` into the scene within `bounds`,
+/// leaving a disk facing it just outside the scene.
+pub(crate) fn sample_emission(
+    background: &(dyn Background + Send + Sync),
+    bounds: &BoundingSphere,
+    rand: &mut Rand,
+) -> Option<EmissionSample> {
+    let towards = sample_emission_direction(background, rand)?;
+    let frame = Onb::build_from_w(&-towards);
+    let radius = bounds.radius;
+    let disk = radius * Vec3::random_in_unit_disk(rand);
+    let origin = bounds.centre + frame.local(&Vec3::new(disk.x(), disk.y(), -radius));
+    Some(EmissionSample {
+        ray: Ray::new(origin, -towards),
+        radiance: background.radiance(&towards),
+        pdf_position: 1.0 / (PI * radius * radius),
+        pdf_direction: emission_pdf(background, &towards),
+    })
+}
+
+/// A sky blending from one color at the horizon to another overhead.
+pub struct SkyGradient {
+    horizon: Color,
+    zenith: Color,
+}
+
+impl SkyGradient {
+    pub fn new(horizon: Color, zenith: Color) -> Self {
+        SkyGradient { horizon, zenith }
+    }
+}
+
+impl Default for SkyGradient {
+    fn default() -> Self {
+        SkyGradient::new(Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for SkyGradient {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let t = 0.5 * (direction.y() + 1.0);
+        (1.0 - t) * self.horizon + t * self.zenith
+    }
+}
+
+/// An equirectangular (latitude-longitude) image of the surroundings in
+/// linear radiance, with +y up and the centre of the image looking down
+/// -z. Directions are importance sampled in proportion to the luminance of
+/// each texel.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    /// Linear radiance, row by row from the top of the image.
+    data: Vec<Color>,
+    distribution: Distribution2D,
+    /// Rotation about +y in radians.
+    rotation: f64,
+    intensity: f64,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, data: Vec<Color>) -> Self {
+        assert_eq!(data.len(), width * height);
+        // Rows near the poles cover less solid angle than those at the
+        // horizon.
+        let weights: Vec<f64> = data
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+                luminance(c).max(0.0) * theta.sin()
+            })
+            .collect();
+        EnvironmentMap {
+            width,
+            height,
+            distribution: Distribution2D::new(&weights, width, height),
+            data,
+            rotation: 0.0,
+            intensity: 1.0,
+        }
+    }
+
+    /// Builds a map from packed 32-bit float RGB pixels, as decoded from
+    /// HDR and EXR files.
+    pub fn from_rgb32f(width: usize, height: usize, pixels: &[f32]) -> Self {
+        let data = pixels
+            .chunks_exact(3)
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        EnvironmentMap::new(width, height, data)
+    }
+
+    /// Turns the environment about the vertical axis.
+    pub fn set_rotation(&mut self, degrees: f64) {
+        self.rotation = degrees_to_radians(degrees)
+    }
+
+    /// Scales the radiance of the whole map.
+    pub fn set_intensity(&mut self, intensity: f64) {
+        self.intensity = intensity
+    }
+
+    /// Image coordinates in `0..1` of `direction`, from the top left.
+    fn image_point(&self, direction: &Vec3) -> (f64, f64) {
+        let phi = direction.x().atan2(-direction.z()) - self.rotation;
+        let theta = direction.y().clamp(-1.0, 1.0).acos();
+        ((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+    }
+
+    fn direction_at(&self, x: f64, y: f64) -> Vec3 {
+        let phi = (x - 0.5) * 2.0 * PI + self.rotation;
+        let theta = y * PI;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    fn texel(&self, x: f64, y: f64) -> Color {
+        let column = ((x * self.width as f64) as usize).min(self.width - 1);
+        let row = ((y * self.height as f64) as usize).min(self.height - 1);
+        self.intensity * self.data[row * self.width + column]
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let (x, y) = self.image_point(&unit_vector(direction));
+        self.texel(x, y)
+    }
+
+    fn sample(&self, rand: &mut Rand) -> Option<BackgroundSample> {
+        let ((x, y), pdf) = self
+            .distribution
+            .sample(rand.random_double(), rand.random_double());
+        let sin_theta = (y * PI).sin();
+        if pdf == 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some(BackgroundSample {
+            direction: self.direction_at(x, y),
+            radiance: self.texel(x, y),
+            pdf: pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let (x, y) = self.image_point(&unit_vector(direction));
+        let sin_theta = (y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(x, y) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::integrate_sphere;
+
+    /// A dim 8 by 4 map with one bright texel above the horizon.
+    fn map() -> EnvironmentMap {
+        let mut data = vec![Color::new(0.1, 0.1, 0.1); 32];
+        data[8 + 5] = Color::new(50.0, 40.0, 30.0);
+        let mut map = EnvironmentMap::new(8, 4, data);
+        map.set_rotation(30.0);
+        map
+    }
+
+    #[test]
+    fn directions_round_trip_through_the_image() {
+        let map = map();
+        for (x, y) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            let (x2, y2) = map.image_point(&map.direction_at(x, y));
+            assert!((x - x2).abs() < 1e-9 && (y - y2).abs() < 1e-9);
+        }
+        // Without rotation the centre of the image looks down -z.
+        let unrotated = EnvironmentMap::new(1, 1, vec![Color::new(1.0, 1.0, 1.0)]);
+        let centre = unrotated.direction_at(0.5, 0.5);
+        assert!((centre - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn samples_match_pdf_and_radiance() {
+        let map = map();
+        let mut rand = Rand::new_with_seed(1);
+        let mut bright = 0;
+        let n = 2000;
+        for _ in 0..n {
+            let sample = map.sample(&mut rand).unwrap();
+            let pdf = map.pdf(&sample.direction);
+            assert!(
+                (sample.pdf - pdf).abs() <= 1e-6 * pdf,
+                "{} {}",
+                sample.pdf,
+                pdf
+            );
+            let radiance = map.radiance(&sample.direction);
+            assert!((sample.radiance - radiance).length() < 1e-9);
+            if radiance.x() > 1.0 {
+                bright += 1;
+            }
+        }
+        // The bright texel is found far more often than its size suggests.
+        assert!(bright > n / 2, "{}", bright);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let map = map();
+        let integral = integrate_sphere(|direction| map.pdf(direction));
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+    }
+}
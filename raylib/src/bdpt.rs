@@ -0,0 +1,743 @@
+//! Bidirectional path tracing, after Veach's thesis and pbrt: a path from
+//! the camera and a path from a light are joined at every pair of their
+//! vertices, and each resulting path is weighted by the balance heuristic
+//! against all the other ways it could have been sampled.
+
+use crate::{
+    background,
+    camera::Camera,
+    hittable::{DidHit, HitRecord, Hittable},
+    light::BoundingSphere,
+    rand::Rand,
+    ray::Ray,
+    raytracer::{max_component, roulette, walk_interior, Bounces, RaytracerOptions},
+    scene::Scene,
+    util::PI,
+    vec3::{dot, unit_vector, Color, Point3},
+    Vec3,
+};
+
+/// Where the light of a light path comes from.
+#[derive(Clone, Copy)]
+enum Emitter {
+    Background,
+    /// The scene's light of this index.
+    Light(usize),
+}
+
+#[derive(Clone, Copy)]
+enum VertexKind {
+    Camera,
+    Light(Emitter),
+    /// A surface reached along `r_in`.
+    Surface {
+        rec: HitRecord,
+        r_in: Ray,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3,
+    /// Throughput of the subpath arriving at this vertex.
+    beta: Color,
+    /// Whether the path scattered specularly here, so it cannot be joined.
+    delta: bool,
+    /// Area density of this vertex when sampled along its own subpath, or
+    /// solid angle density for vertices infinitely far away.
+    pdf_fwd: f64,
+    /// Density of this vertex had it been sampled from the other end.
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, p: Point3, beta: Color) -> Self {
+        Vertex {
+            kind,
+            p,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+
+    fn normal(&self) -> Option<Vec3> {
+        match &self.kind {
+            VertexKind::Surface { rec, .. } => Some(rec.normal),
+            _ => None,
+        }
+    }
+
+    fn is_surface(&self) -> bool {
+        matches!(self.kind, VertexKind::Surface { .. })
+    }
+}
+
+/// `rec` with its normal turned to face against `r`, as if it had been hit
+/// by `r`.
+fn facing(rec: &HitRecord, r: &Ray) -> HitRecord {
+    let mut rec = *rec;
+    if dot(r.direction(), &rec.normal) > 0.0 {
+        rec.normal = -rec.normal;
+        rec.front_face = !rec.front_face;
+    }
+    rec
+}
+
+/// Zero densities are those of delta events, which count as 1 in the
+/// ratios of densities that MIS weights are built from.
+fn remap0(pdf: f64) -> f64 {
+    if pdf != 0.0 {
+        pdf
+    } else {
+        1.0
+    }
+}
+
+/// The bidirectional integrator for one scene and camera. Only RGB is
+/// traced, and media only absorb light along the paths.
+pub(crate) struct Bidirectional<'a> {
+    scene: &'a Scene,
+    camera: &'a Camera,
+    options: &'a RaytracerOptions,
+    bounds: BoundingSphere,
+}
+
+impl<'a> Bidirectional<'a> {
+    pub(crate) fn new(
+        scene: &'a Scene,
+        camera: &'a Camera,
+        options: &'a RaytracerOptions,
+        bounds: BoundingSphere,
+    ) -> Self {
+        Bidirectional {
+            scene,
+            camera,
+            options,
+            bounds,
+        }
+    }
+
+    /// Radiance arriving at the camera along `r`. Light that paths from the
+    /// lights carry straight to the camera lands elsewhere on the film, so
+    /// it is pushed to `splats` with its film coordinates instead.
+    pub(crate) fn sample(
+        &self,
+        r: &Ray,
+        rand: &mut Rand,
+        splats: &mut Vec<(f64, f64, Color)>,
+    ) -> Color {
+        let camera_path = self.camera_path(r, rand);
+        let light_path = self.light_path(rand);
+        let max_depth = self.options.max_depth as usize;
+
+        let mut radiance = Color::new(0.0, 0.0, 0.0);
+        // Joining with one light vertex samples the lights afresh, so it
+        // does not need the light path to have started.
+        let max_s = light_path.len().max(1);
+        for t in 1..=camera_path.len() {
+            for s in 0..=max_s {
+                if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > max_depth {
+                    continue;
+                }
+                let (l, film_point) = match self.connect(&light_path, &camera_path, s, t, rand) {
+                    Some(connection) => connection,
+                    None => continue,
+                };
+                match film_point {
+                    Some((u, v)) => splats.push((u, v, l)),
+                    None => radiance += l,
+                }
+            }
+        }
+        radiance
+    }
+
+    fn emitter_count(&self) -> usize {
+        self.scene.lights().len() + 1
+    }
+
+    /// Chance of picking each light, or the background, to start from.
+    fn pdf_choice(&self) -> f64 {
+        1.0 / self.emitter_count() as f64
+    }
+
+    fn is_infinite(&self, v: &Vertex) -> bool {
+        match v.kind {
+            VertexKind::Light(Emitter::Background) => true,
+            VertexKind::Light(Emitter::Light(i)) => self.scene.lights()[i].is_directional(),
+            _ => false,
+        }
+    }
+
+    /// Whether `v` is on a light with no area or a single direction, which
+    /// paths can never hit.
+    fn is_delta_light(&self, v: &Vertex) -> bool {
+        matches!(v.kind, VertexKind::Light(Emitter::Light(_)))
+    }
+
+    fn camera_path(&self, r: &Ray, rand: &mut Rand) -> Vec<Vertex> {
+        let mut path = vec![Vertex::new(
+            VertexKind::Camera,
+            *r.origin(),
+            Color::new(1.0, 1.0, 1.0),
+        )];
+        let pdf = self.camera.pdf_direction(&unit_vector(r.direction()));
+        let max_vertices = self.options.max_depth as usize + 1;
+        self.random_walk(
+            r,
+            Color::new(1.0, 1.0, 1.0),
+            pdf,
+            max_vertices,
+            true,
+            &mut path,
+            rand,
+        );
+        path
+    }
+
+    fn light_path(&self, rand: &mut Rand) -> Vec<Vertex> {
+        let count = self.emitter_count();
+        let index = ((rand.random_double() * count as f64) as usize).min(count - 1);
+        let (emitter, emission) = if index == self.scene.lights().len() {
+            match background::sample_emission(self.scene.background(), &self.bounds, rand) {
+                Some(emission) => (Emitter::Background, emission),
+                None => return vec![],
+            }
+        } else {
+            match self.scene.lights()[index].sample_emission(&self.bounds, rand) {
+                Some(emission) => (Emitter::Light(index), emission),
+                None => return vec![],
+            }
+        };
+        let pdf_choice = self.pdf_choice();
+        let pdf_origin = pdf_choice * emission.pdf_position;
+        if pdf_origin == 0.0 || emission.pdf_direction == 0.0 || emission.radiance.near_zero() {
+            return vec![];
+        }
+
+        let mut start = Vertex::new(
+            VertexKind::Light(emitter),
+            *emission.ray.origin(),
+            emission.radiance,
+        );
+        start.pdf_fwd = pdf_origin;
+        let mut path = vec![start];
+        let beta = emission.radiance / (pdf_origin * emission.pdf_direction);
+        let max_vertices = self.options.max_depth as usize;
+        self.random_walk(
+            &emission.ray,
+            beta,
+            emission.pdf_direction,
+            max_vertices,
+            false,
+            &mut path,
+            rand,
+        );
+
+        // Light from infinitely far away is sampled by direction and then
+        // position, the reverse of other lights.
+        if self.is_infinite(&path[0]) {
+            let direction = unit_vector(emission.ray.direction());
+            if let Some(v) = path.get_mut(1) {
+                let cos_theta = v.normal().map_or(1.0, |n| dot(&n, &direction).abs());
+                v.pdf_fwd = emission.pdf_position * cos_theta;
+            }
+            path[0].pdf_fwd =
+                pdf_choice * background::emission_pdf(self.scene.background(), &-direction);
+        }
+        path
+    }
+
+    /// Extends `path` from its last vertex along `r`, with up to
+    /// `max_vertices` more vertices. Paths from the camera that leave the
+    /// scene end on the background.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        r: &Ray,
+        beta: Color,
+        pdf: f64,
+        max_vertices: usize,
+        from_camera: bool,
+        path: &mut Vec<Vertex>,
+        rand: &mut Rand,
+    ) {
+        let (mut r, mut beta, mut pdf_fwd) = (*r, beta, pdf);
+        let mut bounces = Bounces::default();
+        let end = path.len() + max_vertices;
+
+        for depth in 0.. {
+            if path.len() >= end {
+                break;
+            }
+            let surface = self.scene.hit(&r, 0.001, f64::INFINITY);
+            let t_surface = match &surface {
+                DidHit::Hit(rec) => rec.t,
+                DidHit::Miss => f64::INFINITY,
+            };
+            beta = self.scene.transmittance(&r, 0.001, t_surface, rand) * beta;
+
+            let rec = match surface {
+                DidHit::Hit(rec) => rec,
+                DidHit::Miss => {
+                    if from_camera {
+                        let p = r.origin() + unit_vector(r.direction());
+                        let mut v = Vertex::new(VertexKind::Light(Emitter::Background), p, beta);
+                        v.pdf_fwd = pdf_fwd;
+                        path.push(v);
+                    }
+                    break;
+                }
+            };
+            let (r_in, rec, walk) = match walk_interior(&r, rec, self.scene, rand) {
+                Some(walk) => walk,
+                None => break,
+            };
+            beta = beta * walk;
+
+            let mut v = Vertex::new(VertexKind::Surface { rec, r_in }, rec.p, beta);
+            v.pdf_fwd = self.convert_density(pdf_fwd, &path[path.len() - 1], &v);
+            path.push(v);
+            if path.len() >= end {
+                break;
+            }
+
+            let material = self.scene.get_material(rec.material_id());
+            let srec = match material.sample(&r_in, &rec, rand) {
+                Some(srec) => srec,
+                None => break,
+            };
+            if !bounces.take(&srec, self.options) {
+                break;
+            }
+            beta = beta * srec.attenuation;
+
+            let last = path.len() - 1;
+            let pdf_rev = if srec.is_specular() {
+                path[last].delta = true;
+                pdf_fwd = 0.0;
+                0.0
+            } else {
+                pdf_fwd = srec.pdf;
+                let wi = unit_vector(srec.scattered.direction());
+                let reverse = Ray::new(rec.p + wi, -wi);
+                material.pdf(&reverse, &facing(&rec, &reverse), &-r_in.direction())
+            };
+            path[last - 1].pdf_rev = self.convert_density(pdf_rev, &path[last], &path[last - 1]);
+            r = srec.scattered;
+
+            match roulette(max_component(&beta), depth, self.options, rand) {
+                Some(survival) => beta /= survival,
+                None => break,
+            }
+        }
+    }
+
+    /// Turns a solid angle density at `from` into an area density at `to`.
+    fn convert_density(&self, pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+        if self.is_infinite(to) {
+            return pdf;
+        }
+        let w = to.p - from.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let cos_theta = match to.normal() {
+            Some(n) => dot(&n, &w).abs() / distance_squared.sqrt(),
+            None => 1.0,
+        };
+        pdf * cos_theta / distance_squared
+    }
+
+    /// The BSDF at `v` for light leaving towards `next`.
+    fn f(&self, v: &Vertex, next: &Vertex) -> Color {
+        match &v.kind {
+            VertexKind::Surface { rec, r_in } => {
+                let wi = unit_vector(&(next.p - v.p));
+                self.scene
+                    .get_material(rec.material_id())
+                    .eval(r_in, rec, &wi)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Density of sampling `next` from `v` having arrived from `prev`.
+    fn pdf(&self, v: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let wn = next.p - v.p;
+        if wn.length_squared() == 0.0 {
+            return 0.0;
+        }
+        let wn = unit_vector(&wn);
+        let pdf = match (&v.kind, prev) {
+            (VertexKind::Light(_), _) => return self.pdf_light(v, next),
+            (VertexKind::Camera, _) => self.camera.pdf_direction(&wn),
+            (VertexKind::Surface { rec, .. }, Some(prev)) => {
+                let r = Ray::new(prev.p, v.p - prev.p);
+                let material = self.scene.get_material(rec.material_id());
+                material.pdf(&r, &facing(rec, &r), &wn)
+            }
+            (VertexKind::Surface { .. }, None) => return 0.0,
+        };
+        self.convert_density(pdf, v, next)
+    }
+
+    /// Density of a light path leaving the light at `v` towards `next`.
+    fn pdf_light(&self, v: &Vertex, next: &Vertex) -> f64 {
+        let w = next.p - v.p;
+        let distance_squared = w.length_squared();
+        let w = unit_vector(&w);
+        let pdf = if self.is_infinite(v) {
+            let radius = self.bounds.radius;
+            1.0 / (PI * radius * radius)
+        } else {
+            match v.kind {
+                VertexKind::Light(Emitter::Light(i)) => {
+                    self.scene.lights()[i].pdf_emission(&w) / distance_squared
+                }
+                _ => 0.0,
+            }
+        };
+        match next.normal() {
+            Some(n) => pdf * dot(&n, &w).abs(),
+            None => pdf,
+        }
+    }
+
+    /// Density of a light path starting at `v`, when heading for `next`.
+    fn pdf_light_origin(&self, v: &Vertex, next: &Vertex) -> f64 {
+        if self.is_infinite(v) {
+            let w = unit_vector(&(next.p - v.p));
+            return self.pdf_choice() * background::emission_pdf(self.scene.background(), &-w);
+        }
+        // Every other light emits from a single point.
+        self.pdf_choice()
+    }
+
+    /// Samples the lights as seen from `p`, giving the vertex on the light
+    /// that was found.
+    fn sample_emitter(&self, p: &Point3, rand: &mut Rand) -> Option<Vertex> {
+        let count = self.emitter_count();
+        let index = ((rand.random_double() * count as f64) as usize).min(count - 1);
+        let pdf_choice = self.pdf_choice();
+        let far = 2.0 * self.bounds.radius;
+        if index == self.scene.lights().len() {
+            let direction = background::sample_emission_direction(self.scene.background(), rand)?;
+            let pdf = background::emission_pdf(self.scene.background(), &direction);
+            let radiance = self.scene.background().radiance(&direction);
+            return Some(Vertex::new(
+                VertexKind::Light(Emitter::Background),
+                p + far * direction,
+                radiance / (pdf * pdf_choice),
+            ));
+        }
+        let sample = self.scene.lights()[index].sample(p, rand)?;
+        let distance = if sample.distance.is_finite() {
+            sample.distance
+        } else {
+            far
+        };
+        Some(Vertex::new(
+            VertexKind::Light(Emitter::Light(index)),
+            p + distance * sample.direction,
+            sample.radiance / pdf_choice,
+        ))
+    }
+
+    /// Fraction of light travelling between `a` and `b` that is neither
+    /// blocked nor absorbed.
+    fn transmittance(&self, a: &Vertex, b: &Vertex, rand: &mut Rand) -> f64 {
+        let (from, to) = if self.is_infinite(a) { (b, a) } else { (a, b) };
+        let w = to.p - from.p;
+        let distance = w.length();
+        let t_max = if self.is_infinite(to) {
+            f64::INFINITY
+        } else {
+            distance - 0.001
+        };
+        let r = Ray::new(from.p, w / distance);
+        if let DidHit::Hit(_) = self.scene.hit(&r, 0.001, t_max) {
+            return 0.0;
+        }
+        self.scene.transmittance(&r, 0.001, t_max, rand)
+    }
+
+    /// The geometry term joining two finite vertices, with visibility.
+    fn g(&self, a: &Vertex, b: &Vertex, rand: &mut Rand) -> f64 {
+        let w = a.p - b.p;
+        let distance_squared = w.length_squared();
+        let w = unit_vector(&w);
+        let cos_a = a.normal().map_or(1.0, |n| dot(&n, &w).abs());
+        let cos_b = b.normal().map_or(1.0, |n| dot(&n, &w).abs());
+        cos_a * cos_b / distance_squared * self.transmittance(a, b, rand)
+    }
+
+    /// The weighted light of the path made of the first `s` vertices of the
+    /// light path and the first `t` of the camera path, with its film
+    /// coordinates if it reaches the camera from the light path's side.
+    fn connect(
+        &self,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        rand: &mut Rand,
+    ) -> Option<(Color, Option<(f64, f64)>)> {
+        let mut sampled = None;
+        let mut film_point = None;
+        let l = if s == 0 {
+            let pt = &camera[t - 1];
+            match pt.kind {
+                VertexKind::Light(Emitter::Background) => {
+                    let direction = unit_vector(&(pt.p - camera[t - 2].p));
+                    pt.beta * self.scene.background().radiance(&direction)
+                }
+                _ => return None,
+            }
+        } else if t == 1 {
+            let qs = &light[s - 1];
+            let normal = qs.normal()?;
+            let lens = self.camera.sample_lens(rand);
+            let to_lens = lens - qs.p;
+            let distance_squared = to_lens.length_squared();
+            let wi = unit_vector(&to_lens);
+            film_point = Some(self.camera.film_point(&lens, &-wi)?);
+            let cos_lens = dot(&-wi, &self.camera.forward());
+            let pdf = distance_squared / (cos_lens * self.camera.lens_area());
+            let importance = self.camera.importance(&-wi) / pdf;
+            let v = Vertex::new(
+                VertexKind::Camera,
+                lens,
+                Color::new(importance, importance, importance),
+            );
+            let l = qs.beta * self.f(qs, &v) * v.beta * dot(&wi, &normal).abs();
+            if l.near_zero() {
+                return None;
+            }
+            let l = self.transmittance(qs, &v, rand) * l;
+            sampled = Some(v);
+            l
+        } else if s == 1 {
+            let pt = &camera[t - 1];
+            let normal = pt.normal()?;
+            let mut v = self.sample_emitter(&pt.p, rand)?;
+            v.pdf_fwd = self.pdf_light_origin(&v, pt);
+            let wi = unit_vector(&(v.p - pt.p));
+            let l = pt.beta * self.f(pt, &v) * v.beta * dot(&wi, &normal).abs();
+            if l.near_zero() {
+                return None;
+            }
+            let l = self.transmittance(pt, &v, rand) * l;
+            sampled = Some(v);
+            l
+        } else {
+            let (qs, pt) = (&light[s - 1], &camera[t - 1]);
+            if !qs.is_surface() || !pt.is_surface() {
+                return None;
+            }
+            let l = qs.beta * self.f(qs, pt) * self.f(pt, qs) * pt.beta;
+            if l.near_zero() {
+                return None;
+            }
+            self.g(qs, pt, rand) * l
+        };
+        if l.near_zero() {
+            return None;
+        }
+        let weight = self.mis_weight(light, camera, sampled, s, t);
+        Some((weight * l, film_point))
+    }
+
+    /// The balance heuristic weight of the path joined from `s` light and `t`
+    /// camera vertices, against every other split of the same path.
+    fn mis_weight(
+        &self,
+        light: &[Vertex],
+        camera: &[Vertex],
+        sampled: Option<Vertex>,
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let (mut light, mut camera) = match sampled {
+            Some(v) if s == 1 => (vec![v], camera[..t].to_vec()),
+            Some(v) if t == 1 => (light[..s].to_vec(), vec![v]),
+            _ => (light[..s].to_vec(), camera[..t].to_vec()),
+        };
+
+        // Densities at the vertices either side of the join, had the path
+        // been sampled from the other end.
+        camera[t - 1].delta = false;
+        let pt = camera[t - 1];
+        let pt_minus = if t > 1 { Some(camera[t - 2]) } else { None };
+        let (qs, qs_minus) = if s > 0 {
+            light[s - 1].delta = false;
+            (
+                Some(light[s - 1]),
+                if s > 1 { Some(light[s - 2]) } else { None },
+            )
+        } else {
+            (None, None)
+        };
+
+        camera[t - 1].pdf_rev = match &qs {
+            Some(qs) => self.pdf(qs, qs_minus.as_ref(), &pt),
+            None => match &pt_minus {
+                Some(pt_minus) => self.pdf_light_origin(&pt, pt_minus),
+                None => 0.0,
+            },
+        };
+        if let Some(pt_minus) = &pt_minus {
+            camera[t - 2].pdf_rev = match &qs {
+                Some(qs) => self.pdf(&pt, Some(qs), pt_minus),
+                None => self.pdf_light(&pt, pt_minus),
+            };
+        }
+        if let Some(qs) = &qs {
+            light[s - 1].pdf_rev = self.pdf(&pt, pt_minus.as_ref(), qs);
+            if let Some(qs_minus) = &qs_minus {
+                light[s - 2].pdf_rev = self.pdf(qs, Some(&pt), qs_minus);
+            }
+        }
+
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera[i].pdf_rev) / remap0(camera[i].pdf_fwd);
+            if !camera[i].delta && !camera[i - 1].delta {
+                sum += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light[i].pdf_rev) / remap0(light[i].pdf_fwd);
+            let delta_before = if i > 0 {
+                light[i - 1].delta
+            } else {
+                self.is_delta_light(&light[0])
+            };
+            if !light[i].delta && !delta_before {
+                sum += ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        background::SkyGradient,
+        light::{Light, PointLight, SpotLight},
+        material::Lambertian,
+        sphere::Sphere,
+    };
+
+    /// Diffuse ground under a point light, seen through a pinhole. The
+    /// ground cannot see itself, so all light reaching the camera bounced
+    /// once.
+    fn lit_ground() -> (Scene, Camera) {
+        let mut scene = Scene::new();
+        let black = Color::new(0.0, 0.0, 0.0);
+        scene.set_background(Box::new(SkyGradient::new(black, black)));
+        let ground = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        scene.add_object(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            ground,
+        )));
+        scene.add_light(Box::new(PointLight::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Color::new(10.0, 10.0, 10.0),
+        )));
+        let camera = Camera::new(
+            &Point3::new(0.0, 3.0, 4.0),
+            &Point3::new(0.0, 0.0, 0.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            0.0,
+            5.0,
+        );
+        (scene, camera)
+    }
+
+    /// Radiance reflected by the ground towards the camera at `r`.
+    fn expected(scene: &Scene, r: &Ray) -> f64 {
+        match scene.hit(r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                let to_light = Point3::new(0.0, 2.0, 0.0) - rec.p;
+                let cos = dot(&unit_vector(&to_light), &rec.normal).max(0.0);
+                0.5 / PI * 10.0 * cos / to_light.length_squared()
+            }
+            DidHit::Miss => 0.0,
+        }
+    }
+
+    #[test]
+    fn image_matches_direct_lighting() {
+        let (scene, camera) = lit_ground();
+        let options = RaytracerOptions {
+            max_depth: 5,
+            ..Default::default()
+        };
+        let bidirectional = Bidirectional::new(&scene, &camera, &options, scene.bounding_sphere());
+        let mut rand = Rand::new_with_seed(1);
+        let n = 20_000;
+        let (mut traced, mut reference) = (0.0, 0.0);
+        let mut splats = vec![];
+        for _ in 0..n {
+            let (u, v) = (rand.random_double(), rand.random_double());
+            let r = camera.get_ray(u, v, &mut rand);
+            traced += bidirectional.sample(&r, &mut rand, &mut splats).x();
+            reference += expected(&scene, &r);
+        }
+        // Light paths carried straight to the camera land all over the
+        // film, so only the image as a whole can be compared.
+        let on_film =
+            |&&(u, v, _): &&(f64, f64, Color)| (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v);
+        traced += splats.iter().filter(on_film).map(|s| s.2.x()).sum::<f64>();
+        let (traced, reference) = (traced / n as f64, reference / n as f64);
+        assert!(
+            (traced - reference).abs() < 0.03 * reference,
+            "{} != {}",
+            traced,
+            reference
+        );
+        assert!(!splats.is_empty());
+    }
+
+    #[test]
+    fn emission_matches_its_density() {
+        let spot = SpotLight::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            40.0,
+            20.0,
+        );
+        let point = PointLight::new(Point3::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        let bounds = BoundingSphere {
+            centre: Point3::new(0.0, 0.0, 0.0),
+            radius: 10.0,
+        };
+        let mut rand = Rand::new_with_seed(2);
+        for light in [&spot as &dyn Light, &point] {
+            for _ in 0..100 {
+                let sample = light.sample_emission(&bounds, &mut rand).unwrap();
+                let direction = *sample.ray.direction();
+                assert!((direction.length() - 1.0).abs() < 1e-9);
+                assert_eq!(sample.pdf_direction, light.pdf_emission(&direction));
+                assert!(sample.pdf_direction > 0.0);
+            }
+        }
+        assert_eq!(spot.pdf_emission(&Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    }
+}
@@ -0,0 +1,298 @@
+//! Materials that perturb the shading normal of the surface they are applied
+//! to before handing the hit on to another material, adding detail that the
+//! geometry does not have.
+
+use crate::{
+    hittable::HitRecord,
+    material::{InteriorEvent, Material, ScatterRecord},
+    onb::Onb,
+    rand::Rand,
+    ray::Ray,
+    texture::TextureRef,
+    vec3::{cross, dot, unit_vector, Color},
+    Vec3,
+};
+
+/// Step in texture space used to take finite differences of bump heights.
+const BUMP_DELTA: f64 = 1e-3;
+
+/// Normal mapping from a tangent-space normal map, usually an
+/// `ImageTexture` loaded with `srgb` off. Each texel encodes a normal with
+/// its red, green and blue channels mapped from `0..1` to `-1..1` along the
+/// u tangent, the v tangent and the surface normal.
+pub struct NormalMap {
+    material: Box<dyn Material + Send + Sync>,
+    normals: TextureRef,
+}
+
+impl NormalMap {
+    pub fn new(material: Box<dyn Material + Send + Sync>, normals: TextureRef) -> Self {
+        NormalMap { material, normals }
+    }
+
+    fn perturb(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let (t, b, n) = tangent_frame(rec);
+        let c = self.normals.value(rec.u, rec.v, &rec.p);
+        let local = 2.0 * c - Color::new(1.0, 1.0, 1.0);
+        if local.near_zero() {
+            return *rec;
+        }
+        let outward = unit_vector(&(local.x() * t + local.y() * b + local.z() * n));
+        let dpdu = unit_vector(&(t - dot(&t, &outward) * outward));
+        let dpdv = cross(&outward, &dpdu);
+        let dpdv = if dot(&dpdv, &b) < 0.0 { -dpdv } else { dpdv };
+        shade_with(r_in, rec, outward, dpdu, dpdv)
+    }
+}
+
+/// Bump mapping from the first channel of any texture, read as a height
+/// above the surface of `scale` world units per unit of texture value.
+pub struct BumpMap {
+    material: Box<dyn Material + Send + Sync>,
+    heights: TextureRef,
+    scale: f64,
+}
+
+impl BumpMap {
+    pub fn new(material: Box<dyn Material + Send + Sync>, heights: TextureRef, scale: f64) -> Self {
+        BumpMap {
+            material,
+            heights,
+            scale,
+        }
+    }
+
+    fn height(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.scale * self.heights.value(u, v, p).x()
+    }
+
+    fn perturb(&self, r_in: &Ray, rec: &HitRecord) -> HitRecord {
+        let (t, b, n) = tangent_frame(rec);
+        let (dpdu, dpdv) = if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            (t, b)
+        } else {
+            (rec.dpdu, rec.dpdv)
+        };
+
+        // Displace the surface along its normal and differentiate it
+        // numerically, ignoring the change of the normal itself.
+        let h = self.height(rec.u, rec.v, &rec.p);
+        let h_u = self.height(rec.u + BUMP_DELTA, rec.v, &(rec.p + BUMP_DELTA * dpdu));
+        let h_v = self.height(rec.u, rec.v + BUMP_DELTA, &(rec.p + BUMP_DELTA * dpdv));
+        let dpdu = dpdu + (h_u - h) / BUMP_DELTA * n;
+        let dpdv = dpdv + (h_v - h) / BUMP_DELTA * n;
+
+        let outward = unit_vector(&cross(&dpdu, &dpdv));
+        let outward = if dot(&outward, &n) < 0.0 {
+            -outward
+        } else {
+            outward
+        };
+        shade_with(r_in, rec, outward, dpdu, dpdv)
+    }
+}
+
+impl Material for NormalMap {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        self.material.sample(r_in, &self.perturb(r_in, rec), rand)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.material.eval(r_in, &self.perturb(r_in, rec), wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        self.material.pdf(r_in, &self.perturb(r_in, rec), wi)
+    }
+
+    fn sample_interior(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> InteriorEvent {
+        self.material.sample_interior(r_in, rec, rand)
+    }
+
+    fn is_cut_out(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.material.is_cut_out(r_in, rec)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.material.shading_normal(r_in, &self.perturb(r_in, rec))
+    }
+
+    fn non_specular_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        self.material
+            .non_specular_probability(r_in, &self.perturb(r_in, rec))
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.material.albedo(r_in, &self.perturb(r_in, rec))
+    }
+}
+
+impl Material for BumpMap {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        self.material.sample(r_in, &self.perturb(r_in, rec), rand)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        self.material.eval(r_in, &self.perturb(r_in, rec), wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        self.material.pdf(r_in, &self.perturb(r_in, rec), wi)
+    }
+
+    fn sample_interior(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> InteriorEvent {
+        self.material.sample_interior(r_in, rec, rand)
+    }
+
+    fn is_cut_out(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.material.is_cut_out(r_in, rec)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.material.shading_normal(r_in, &self.perturb(r_in, rec))
+    }
+
+    fn non_specular_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        self.material
+            .non_specular_probability(r_in, &self.perturb(r_in, rec))
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.material.albedo(r_in, &self.perturb(r_in, rec))
+    }
+}
+
+/// The outward normal at `rec` and unit tangents along u and v, made
+/// orthogonal to it. Surfaces without a parameterization get an arbitrary
+/// frame.
+fn tangent_frame(rec: &HitRecord) -> (Vec3, Vec3, Vec3) {
+    let n = outward_normal(rec);
+    let t = rec.dpdu - dot(&rec.dpdu, &n) * n;
+    if t.near_zero() {
+        let frame = Onb::build_from_w(&n);
+        return (*frame.u(), *frame.v(), n);
+    }
+    let t = unit_vector(&t);
+    // Keep the bitangent pointing along dpdv so mirrored texture
+    // coordinates mirror the map too.
+    let b = cross(&n, &t);
+    let b = if dot(&b, &rec.dpdv) < 0.0 { -b } else { b };
+    (t, b, n)
+}
+
+fn outward_normal(rec: &HitRecord) -> Vec3 {
+    if rec.front_face {
+        rec.normal
+    } else {
+        -rec.normal
+    }
+}
+
+/// Replaces the shading normal of `rec` with `outward`, unless it would
+/// face away from the incoming ray, which no material can shade.
+fn shade_with(r_in: &Ray, rec: &HitRecord, outward: Vec3, dpdu: Vec3, dpdv: Vec3) -> HitRecord {
+    let normal = if rec.front_face { outward } else { -outward };
+    if dot(&normal, r_in.direction()) >= 0.0 {
+        return *rec;
+    }
+    HitRecord {
+        normal,
+        dpdu,
+        dpdv,
+        ..*rec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::Lambertian,
+        texture::{SolidColor, Texture},
+        vec3::Point3,
+    };
+
+    /// Heights rising along u.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _p: &Point3) -> Color {
+            Color::new(u, u, u)
+        }
+    }
+
+    /// A hit on the plane z = 0 parameterized by x and y, seen from above.
+    fn hit() -> (Ray, HitRecord) {
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            u: 0.5,
+            v: 0.5,
+            dpdu: Vec3::new(2.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 2.0, 0.0),
+            ..Default::default()
+        };
+        rec.set_face_normal(&r_in, &Vec3::new(0.0, 0.0, 1.0));
+        (r_in, rec)
+    }
+
+    fn lambertian() -> Box<dyn Material + Send + Sync> {
+        Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn tangent_frame_follows_the_parameterization() {
+        let (_, mut rec) = hit();
+        let (t, b, n) = tangent_frame(&rec);
+        assert!((t - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-12);
+        assert!((b - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!((n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12);
+
+        // Mirrored texture coordinates mirror the bitangent.
+        rec.dpdv = Vec3::new(0.0, -1.0, 0.0);
+        let (_, b, _) = tangent_frame(&rec);
+        assert!((b - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-12);
+
+        // Without a parameterization any orthonormal frame will do.
+        rec.dpdu = Vec3::new(0.0, 0.0, 0.0);
+        let (t, b, n) = tangent_frame(&rec);
+        assert!(dot(&t, &n).abs() < 1e-12 && dot(&b, &n).abs() < 1e-12);
+        assert!(dot(&t, &b).abs() < 1e-12);
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_normal() {
+        let (r_in, rec) = hit();
+        let map = NormalMap::new(lambertian(), SolidColor::shared(Color::new(0.5, 0.5, 1.0)));
+        let shaded = map.perturb(&r_in, &rec);
+        assert!((shaded.normal - rec.normal).length() < 1e-12);
+    }
+
+    #[test]
+    fn normal_map_tilts_along_the_tangents() {
+        let (r_in, rec) = hit();
+        // A normal halfway between the u tangent and the surface normal.
+        let map = NormalMap::new(lambertian(), SolidColor::shared(Color::new(1.0, 0.5, 1.0)));
+        let shaded = map.perturb(&r_in, &rec);
+        let expected = unit_vector(&Vec3::new(1.0, 0.0, 1.0));
+        assert!((shaded.normal - expected).length() < 1e-12);
+        assert!(dot(&shaded.dpdu, &shaded.normal).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bump_map_tilts_away_from_the_slope() {
+        let (r_in, rec) = hit();
+        // Height rises by 0.5 per unit of u, and u by 0.5 per unit of x.
+        let map = BumpMap::new(lambertian(), std::sync::Arc::new(Ramp), 0.5);
+        let shaded = map.perturb(&r_in, &rec);
+        let expected = unit_vector(&Vec3::new(-0.25, 0.0, 1.0));
+        assert!(
+            (shaded.normal - expected).length() < 1e-9,
+            "{:?}",
+            shaded.normal
+        );
+
+        let flat = BumpMap::new(lambertian(), SolidColor::scalar(1.0), 0.5);
+        assert!((flat.perturb(&r_in, &rec).normal - rec.normal).length() < 1e-12);
+    }
+}
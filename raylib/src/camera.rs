@@ -1,8 +1,8 @@
 use crate::{
     rand::Rand,
     ray::Ray,
-    util::degrees_to_radians,
-    vec3::{cross, unit_vector, Point3},
+    util::{degrees_to_radians, PI},
+    vec3::{cross, dot, unit_vector, Point3},
     Vec3,
 };
 
@@ -15,6 +15,7 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    focus_dist: f64,
 }
 
 impl Camera {
@@ -32,7 +33,7 @@ impl Camera {
         let viewport_width = aspect_ratio * viewport_height;
 
         let w = unit_vector(&(look_from - look_at));
-        let u = unit_vector(&cross(&up, &w));
+        let u = unit_vector(&cross(up, &w));
         let v = cross(&w, &u);
 
         let origin = *look_from;
@@ -50,15 +51,83 @@ impl Camera {
             u,
             v,
             w,
+            focus_dist,
         }
     }
 
     pub fn get_ray(&self, s: f64, t: f64, rand: &mut Rand) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk(rand);
-        let offset = self.u * rd.x() + self.v * rd.y();
+        let lens_point = self.sample_lens(rand);
         Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            lens_point,
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - lens_point,
         )
     }
+
+    /// A point on the lens for a ray to leave from.
+    pub(crate) fn sample_lens(&self, rand: &mut Rand) -> Point3 {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk(rand);
+        self.origin + self.u * rd.x() + self.v * rd.y()
+    }
+
+    /// Area of the lens, or 1 for a pinhole, which has no area to sample.
+    pub(crate) fn lens_area(&self) -> f64 {
+        if self.lens_radius > 0.0 {
+            PI * self.lens_radius * self.lens_radius
+        } else {
+            1.0
+        }
+    }
+
+    /// Distance to the plane in focus.
+    pub(crate) fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    /// The direction the camera looks along.
+    pub(crate) fn forward(&self) -> Vec3 {
+        -self.w
+    }
+
+    /// Area of the film projected to unit distance from the lens.
+    fn film_area(&self) -> f64 {
+        self.horizontal.length() * self.vertical.length() / (self.focus_dist * self.focus_dist)
+    }
+
+    /// The film coordinates, as passed to `get_ray`, of a ray leaving the
+    /// lens at `lens_point` along the unit vector `direction`, or `None` if
+    /// it points away from the film. The coordinates may fall outside the
+    /// image, which callers must check.
+    pub(crate) fn film_point(&self, lens_point: &Point3, direction: &Vec3) -> Option<(f64, f64)> {
+        let cos_theta = dot(direction, &self.forward());
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let focus = lens_point + (self.focus_dist / cos_theta) * direction;
+        let d = focus - self.lower_left_corner;
+        let s = dot(&d, &self.horizontal) / self.horizontal.length_squared();
+        let t = dot(&d, &self.vertical) / self.vertical.length_squared();
+        Some((s, t))
+    }
+
+    /// The importance the camera gives to a ray leaving the lens along the
+    /// unit vector `direction`, normalized to integrate to 1 over the lens
+    /// and the film between 0 and 1.
+    pub(crate) fn importance(&self, direction: &Vec3) -> f64 {
+        let cos_theta = dot(direction, &self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        let cos2 = cos_theta * cos_theta;
+        1.0 / (self.film_area() * self.lens_area() * cos2 * cos2)
+    }
+
+    /// Solid angle density with which `get_ray` generates `direction` from
+    /// a point on the lens, for film coordinates drawn uniformly.
+    pub(crate) fn pdf_direction(&self, direction: &Vec3) -> f64 {
+        let cos_theta = dot(direction, &self.forward());
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.film_area() * cos_theta.powi(3))
+    }
 }
@@ -0,0 +1,165 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{convex_intervals, nearest_crossing, Crossing, DidHit, HitInterval, Hittable},
+    onb::Onb,
+    poly::solve_quadratic,
+    ray::Ray,
+    scene::MaterialId,
+    util::PI,
+    vec3::{dot, Point3},
+    Vec3,
+};
+
+/// Cylinder with hemispherical ends around the segment from `a` to `b`.
+pub struct Capsule {
+    a: Point3,
+    frame: Onb,
+    height: f64,
+    radius: f64,
+    material_id: MaterialId,
+}
+
+impl Capsule {
+    pub fn new(a: Point3, b: Point3, radius: f64, material_id: MaterialId) -> Self {
+        let axis = b - a;
+        Capsule {
+            a,
+            frame: Onb::build_from_w(&axis),
+            height: axis.length(),
+            radius,
+            material_id,
+        }
+    }
+
+    fn crossing_at(&self, t: f64, p: &Point3, local_normal: &Vec3) -> Crossing {
+        let phi = p.y().atan2(p.x());
+        let length = self.height + 2.0 * self.radius;
+        // v runs linearly along the axis, so over the end caps the ring
+        // radius changes with v as well.
+        let ring = (local_normal.x().powi(2) + local_normal.y().powi(2)).sqrt();
+        let dpdv = if ring > 1e-9 {
+            let slope = -local_normal.z() / ring;
+            length * Vec3::new(slope * phi.cos(), slope * phi.sin(), 1.0)
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        };
+        Crossing {
+            t,
+            outward_normal: self.frame.local(local_normal),
+            u: (phi + PI) / (2.0 * PI),
+            v: ((p.z() + self.radius) / length).clamp(0.0, 1.0),
+            dpdu: self
+                .frame
+                .local(&(2.0 * PI * Vec3::new(-p.y(), p.x(), 0.0))),
+            dpdv: self.frame.local(&dpdv),
+        }
+    }
+
+    /// Every crossing of the ray's line with the surface. The segment runs
+    /// along local z from 0 to `height`.
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = self.frame.to_local(&(r.origin() - self.a));
+        let d = self.frame.to_local(r.direction());
+        let r2 = self.radius * self.radius;
+        let mut crossings = vec![];
+
+        let a = d.x() * d.x() + d.y() * d.y();
+        if a > 1e-12 {
+            let b = 2.0 * (o.x() * d.x() + o.y() * d.y());
+            let c = o.x() * o.x() + o.y() * o.y() - r2;
+            for t in solve_quadratic([c, b, a]) {
+                let p = o + t * d;
+                if p.z() >= 0.0 && p.z() <= self.height {
+                    let n = Vec3::new(p.x(), p.y(), 0.0) / self.radius;
+                    crossings.push(self.crossing_at(t, &p, &n));
+                }
+            }
+        }
+
+        // Each end cap only counts beyond its end of the segment.
+        for (z, beyond) in [(0.0, -1.0), (self.height, 1.0)] {
+            let center = Vec3::new(0.0, 0.0, z);
+            let oc = o - center;
+            let roots = solve_quadratic([
+                oc.length_squared() - r2,
+                2.0 * dot(&oc, &d),
+                d.length_squared(),
+            ]);
+            for t in roots {
+                let p = o + t * d;
+                if beyond * (p.z() - z) >= 0.0 {
+                    let n = (p - center) / self.radius;
+                    crossings.push(self.crossing_at(t, &p, &n));
+                }
+            }
+        }
+
+        crossings
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        nearest_crossing(r, &self.crossings(r), t_min, t_max, self.material_id)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let e = Vec3::new(self.radius, self.radius, self.radius);
+        let b = self.a + self.height * self.frame.w();
+        Some(Aabb::new(self.a - e, self.a + e).surrounding(&Aabb::new(b - e, b + e)))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        convex_intervals(r, &self.crossings(r), self.material_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capsule() -> Capsule {
+        Capsule::new(
+            Point3::new(0.0, -1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            0.5,
+            0,
+        )
+    }
+
+    fn assert_hit(r: &Ray, t: (f64, f64), normal: Vec3) {
+        let intervals = capsule().intervals(r);
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].enter.t - t.0).abs() < 1e-9);
+        assert!((intervals[0].exit.t - t.1).abs() < 1e-9);
+        match capsule().hit(r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.normal - normal).near_zero(), "{:?}", rec.normal),
+            DidHit::Miss => panic!("missed the capsule"),
+        }
+    }
+
+    #[test]
+    fn crosses_the_side() {
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_hit(&r, (4.5, 5.5), Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn crosses_the_ends() {
+        let r = Ray::new(Point3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_hit(&r, (3.5, 6.5), Vec3::new(0.0, -1.0, 0.0));
+        // Past the segment's end the surface is a hemisphere.
+        let r = Ray::new(Point3::new(-5.0, 1.3, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_hit(&r, (4.6, 5.4), Vec3::new(-0.8, 0.6, 0.0));
+    }
+
+    #[test]
+    fn misses_beyond_the_ends() {
+        let r = Ray::new(Point3::new(-5.0, 1.6, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(matches!(
+            capsule().hit(&r, 0.001, f64::INFINITY),
+            DidHit::Miss
+        ));
+        assert!(capsule().intervals(&r).is_empty());
+    }
+}
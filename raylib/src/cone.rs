@@ -0,0 +1,204 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{convex_intervals, nearest_crossing, Crossing, DidHit, HitInterval, Hittable},
+    onb::Onb,
+    poly::solve_quadratic,
+    ray::Ray,
+    scene::MaterialId,
+    util::PI,
+    vec3::{unit_vector, Point3},
+    Vec3,
+};
+
+/// Cone or truncated cone around the segment from `base` to `top`, with the
+/// radius varying linearly between `base_radius` and `top_radius`. Set one
+/// radius to zero for a pointed cone.
+pub struct Cone {
+    base: Point3,
+    frame: Onb,
+    height: f64,
+    base_radius: f64,
+    top_radius: f64,
+    capped: bool,
+    material_id: MaterialId,
+}
+
+impl Cone {
+    pub fn new(
+        base: Point3,
+        top: Point3,
+        base_radius: f64,
+        top_radius: f64,
+        capped: bool,
+        material_id: MaterialId,
+    ) -> Self {
+        let axis = top - base;
+        Cone {
+            base,
+            frame: Onb::build_from_w(&axis),
+            height: axis.length(),
+            base_radius,
+            top_radius,
+            capped,
+            material_id,
+        }
+    }
+
+    /// Every crossing of the ray's line with the surface. The cone's axis
+    /// runs along local z from 0 to `height`.
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = self.frame.to_local(&(r.origin() - self.base));
+        let d = self.frame.to_local(r.direction());
+        let mut crossings = vec![];
+
+        // The radius at height z is r0 + kz.
+        let k = (self.top_radius - self.base_radius) / self.height;
+        let r_o = self.base_radius + k * o.z();
+
+        let a = d.x() * d.x() + d.y() * d.y() - k * k * d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.y() * d.y() - r_o * k * d.z());
+        let c = o.x() * o.x() + o.y() * o.y() - r_o * r_o;
+        let roots = if a.abs() > 1e-12 {
+            solve_quadratic([c, b, a])
+        } else if b.abs() > 1e-12 {
+            // The ray is parallel to the slope of the cone.
+            vec![-c / b]
+        } else {
+            vec![]
+        };
+
+        for t in roots {
+            let p = o + t * d;
+            if p.z() >= 0.0 && p.z() <= self.height {
+                let radius = self.base_radius + k * p.z();
+                let phi = p.y().atan2(p.x());
+                crossings.push(Crossing {
+                    t,
+                    outward_normal: self.frame.local(&unit_vector(&Vec3::new(
+                        p.x(),
+                        p.y(),
+                        -k * radius,
+                    ))),
+                    u: (phi + PI) / (2.0 * PI),
+                    v: p.z() / self.height,
+                    dpdu: self
+                        .frame
+                        .local(&(2.0 * PI * Vec3::new(-p.y(), p.x(), 0.0))),
+                    dpdv: self
+                        .frame
+                        .local(&(self.height * Vec3::new(k * phi.cos(), k * phi.sin(), 1.0))),
+                });
+            }
+        }
+
+        if self.capped && d.z().abs() > 1e-12 {
+            for (z, nz, radius) in [
+                (0.0, -1.0, self.base_radius),
+                (self.height, 1.0, self.top_radius),
+            ] {
+                if radius <= 0.0 {
+                    continue;
+                }
+                let t = (z - o.z()) / d.z();
+                let p = o + t * d;
+                if p.x() * p.x() + p.y() * p.y() <= radius * radius {
+                    crossings.push(Crossing {
+                        t,
+                        outward_normal: self.frame.local(&Vec3::new(0.0, 0.0, nz)),
+                        u: 0.5 * (p.x() / radius + 1.0),
+                        v: 0.5 * (p.y() / radius + 1.0),
+                        dpdu: self.frame.local(&Vec3::new(2.0 * radius, 0.0, 0.0)),
+                        dpdv: self.frame.local(&Vec3::new(0.0, 2.0 * radius, 0.0)),
+                    });
+                }
+            }
+        }
+
+        crossings
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        nearest_crossing(r, &self.crossings(r), t_min, t_max, self.material_id)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let top = self.base + self.height * self.frame.w();
+        Some(
+            Aabb::disk(&self.base, self.frame.w(), self.base_radius).surrounding(&Aabb::disk(
+                &top,
+                self.frame.w(),
+                self.top_radius,
+            )),
+        )
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        if !self.capped {
+            return vec![];
+        }
+        convex_intervals(r, &self.crossings(r), self.material_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::unit_vector;
+
+    /// A pointed cone of height 2 standing on the origin.
+    fn cone() -> Cone {
+        Cone::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            1.0,
+            0.0,
+            true,
+            0,
+        )
+    }
+
+    fn span(r: &Ray) -> Vec<(f64, f64)> {
+        cone()
+            .intervals(r)
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect()
+    }
+
+    #[test]
+    fn crosses_the_side() {
+        // Halfway up, the radius is a half.
+        let r = Ray::new(Point3::new(-5.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let spans = span(&r);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].0 - 4.5).abs() < 1e-9 && (spans[0].1 - 5.5).abs() < 1e-9);
+        match cone().hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                let expected = unit_vector(&Vec3::new(-1.0, 0.5, 0.0));
+                assert!((rec.normal - expected).near_zero(), "{:?}", rec.normal);
+            }
+            DidHit::Miss => panic!("missed the side"),
+        }
+    }
+
+    #[test]
+    fn crosses_the_base_and_side() {
+        let r = Ray::new(Point3::new(0.2, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let spans = span(&r);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].0 - 5.0).abs() < 1e-9 && (spans[0].1 - 6.6).abs() < 1e-9);
+        match cone().hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.normal - Vec3::new(0.0, -1.0, 0.0)).near_zero()),
+            DidHit::Miss => panic!("missed the base"),
+        }
+    }
+
+    #[test]
+    fn misses_above_the_tip() {
+        let r = Ray::new(Point3::new(-5.0, 2.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(matches!(cone().hit(&r, 0.001, f64::INFINITY), DidHit::Miss));
+        assert!(span(&r).is_empty());
+    }
+}
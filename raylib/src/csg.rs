@@ -0,0 +1,236 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{DidHit, HitInterval, HitRecord, Hittable},
+    ray::Ray,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// Everything inside the left operand but not the right one.
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Constructive solid geometry node combining two solids.
+///
+/// Surfaces of the result keep the material of the operand they came from,
+/// so cutting a glass sphere with a diffuse one leaves a diffuse cut face.
+pub struct Csg {
+    op: CsgOp,
+    left: Box<dyn Hittable + Send + Sync>,
+    right: Box<dyn Hittable + Send + Sync>,
+}
+
+impl Csg {
+    pub fn new(
+        op: CsgOp,
+        left: Box<dyn Hittable + Send + Sync>,
+        right: Box<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Csg { op, left, right }
+    }
+
+    pub fn union(
+        left: Box<dyn Hittable + Send + Sync>,
+        right: Box<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Csg::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(
+        left: Box<dyn Hittable + Send + Sync>,
+        right: Box<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Csg::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(
+        left: Box<dyn Hittable + Send + Sync>,
+        right: Box<dyn Hittable + Send + Sync>,
+    ) -> Self {
+        Csg::new(CsgOp::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        for interval in self.intervals(r) {
+            for rec in [interval.enter, interval.exit] {
+                if rec.t > t_max {
+                    return DidHit::Miss;
+                }
+                if rec.t >= t_min {
+                    return DidHit::Hit(rec);
+                }
+            }
+        }
+        DidHit::Miss
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.op {
+            CsgOp::Union => Some(
+                self.left
+                    .bounding_box()?
+                    .surrounding(&self.right.bounding_box()?),
+            ),
+            CsgOp::Intersection => match (self.left.bounding_box(), self.right.bounding_box()) {
+                (Some(l), Some(r)) => Some(l.overlap(&r)),
+                (l, r) => l.or(r),
+            },
+            CsgOp::Difference => self.left.bounding_box(),
+        }
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        combine(self.op, &self.left.intervals(r), &self.right.intervals(r))
+    }
+}
+
+/// Applies `op` to two sorted interval lists by sweeping over their
+/// crossings in order and emitting a crossing whenever the combined
+/// inside/outside state changes.
+pub(crate) fn combine(op: CsgOp, left: &[HitInterval], right: &[HitInterval]) -> Vec<HitInterval> {
+    // (crossing, from the left operand, entering its operand)
+    let mut crossings: Vec<(HitRecord, bool, bool)> =
+        Vec::with_capacity(2 * (left.len() + right.len()));
+    for (intervals, is_left) in [(left, true), (right, false)] {
+        for interval in intervals {
+            crossings.push((interval.enter, is_left, true));
+            crossings.push((interval.exit, is_left, false));
+        }
+    }
+    crossings.sort_by(|a, b| a.0.t.total_cmp(&b.0.t));
+
+    let mut result = vec![];
+    let (mut in_left, mut in_right) = (false, false);
+    let mut inside = false;
+    let mut enter: Option<HitRecord> = None;
+
+    for (mut rec, is_left, entering) in crossings {
+        if is_left {
+            in_left = entering;
+        } else {
+            in_right = entering;
+        }
+
+        let now_inside = op.inside(in_left, in_right);
+        if now_inside == inside {
+            continue;
+        }
+        inside = now_inside;
+
+        // The face normal already points against the ray; only which side
+        // of the combined solid we are on can change.
+        rec.front_face = now_inside;
+        if now_inside {
+            enter = Some(rec);
+        } else if let Some(enter) = enter.take() {
+            result.push(HitInterval { enter, exit: rec });
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sphere::Sphere, vec3::Point3, Vec3};
+
+    /// Unit spheres at the origin and one along x, with materials 1 and 2.
+    fn spheres() -> (Box<Sphere>, Box<Sphere>) {
+        (
+            Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, 1)),
+            Box::new(Sphere::new(Point3::new(1.0, 0.0, 0.0), 1.0, 2)),
+        )
+    }
+
+    /// A ray along x that enters the first sphere at t = 4 and the second
+    /// at t = 5.
+    fn ray() -> Ray {
+        Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    fn spans(csg: &Csg) -> Vec<(f64, f64)> {
+        csg.intervals(&ray())
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect()
+    }
+
+    fn assert_spans(actual: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.0 - e.0).abs() < 1e-9 && (a.1 - e.1).abs() < 1e-9,
+                "{:?}",
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn combines_overlapping_intervals() {
+        let (a, b) = spheres();
+        assert_spans(spans(&Csg::union(a, b)), &[(4.0, 7.0)]);
+        let (a, b) = spheres();
+        assert_spans(spans(&Csg::intersection(a, b)), &[(5.0, 6.0)]);
+        let (a, b) = spheres();
+        assert_spans(spans(&Csg::difference(a, b)), &[(4.0, 5.0)]);
+        let (a, b) = spheres();
+        assert_spans(spans(&Csg::difference(b, a)), &[(6.0, 7.0)]);
+    }
+
+    #[test]
+    fn keeps_disjoint_intervals_apart() {
+        let a = Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, 1));
+        let b = Box::new(Sphere::new(Point3::new(3.0, 0.0, 0.0), 1.0, 2));
+        assert_spans(spans(&Csg::union(a, b)), &[(4.0, 6.0), (7.0, 9.0)]);
+        let a = Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, 1));
+        let b = Box::new(Sphere::new(Point3::new(3.0, 0.0, 0.0), 1.0, 2));
+        assert!(spans(&Csg::intersection(a, b)).is_empty());
+    }
+
+    #[test]
+    fn cut_faces_keep_the_cutting_material() {
+        let (a, b) = spheres();
+        let difference = Csg::difference(a, b);
+        let intervals = difference.intervals(&ray());
+        let (enter, exit) = (intervals[0].enter, intervals[0].exit);
+        assert_eq!(enter.material_id(), 1);
+        assert!(enter.front_face);
+        // The ray leaves the result through the second sphere's surface,
+        // whose normal faces back along the ray.
+        assert_eq!(exit.material_id(), 2);
+        assert!(!exit.front_face);
+        assert!(exit.normal.x() < 0.0);
+    }
+
+    #[test]
+    fn hits_the_nearest_crossing_in_range() {
+        let (a, b) = spheres();
+        let intersection = Csg::intersection(a, b);
+        match intersection.hit(&ray(), 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.t - 5.0).abs() < 1e-9),
+            DidHit::Miss => panic!("missed the intersection"),
+        }
+        // From inside, the next crossing is the exit.
+        match intersection.hit(&ray(), 5.5, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.t - 6.0).abs() < 1e-9),
+            DidHit::Miss => panic!("missed the exit"),
+        }
+        assert!(matches!(intersection.hit(&ray(), 0.001, 4.5), DidHit::Miss));
+    }
+}
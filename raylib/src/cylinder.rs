@@ -0,0 +1,187 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{convex_intervals, nearest_crossing, Crossing, DidHit, HitInterval, Hittable},
+    onb::Onb,
+    poly::solve_quadratic,
+    ray::Ray,
+    scene::MaterialId,
+    util::PI,
+    vec3::Point3,
+    Vec3,
+};
+
+/// Cylinder of `radius` around the segment from `base` to `top`. Uncapped
+/// cylinders are open tubes and cannot be used in CSG.
+pub struct Cylinder {
+    base: Point3,
+    frame: Onb,
+    height: f64,
+    radius: f64,
+    capped: bool,
+    material_id: MaterialId,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Point3,
+        top: Point3,
+        radius: f64,
+        capped: bool,
+        material_id: MaterialId,
+    ) -> Self {
+        let axis = top - base;
+        Cylinder {
+            base,
+            frame: Onb::build_from_w(&axis),
+            height: axis.length(),
+            radius,
+            capped,
+            material_id,
+        }
+    }
+
+    /// Every crossing of the ray's line with the surface. The cylinder's
+    /// axis runs along local z from 0 to `height`.
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = self.frame.to_local(&(r.origin() - self.base));
+        let d = self.frame.to_local(r.direction());
+        let mut crossings = vec![];
+
+        let a = d.x() * d.x() + d.y() * d.y();
+        if a > 1e-12 {
+            let b = 2.0 * (o.x() * d.x() + o.y() * d.y());
+            let c = o.x() * o.x() + o.y() * o.y() - self.radius * self.radius;
+            for t in solve_quadratic([c, b, a]) {
+                let p = o + t * d;
+                if p.z() >= 0.0 && p.z() <= self.height {
+                    crossings.push(Crossing {
+                        t,
+                        outward_normal: self
+                            .frame
+                            .local(&(Vec3::new(p.x(), p.y(), 0.0) / self.radius)),
+                        u: (p.y().atan2(p.x()) + PI) / (2.0 * PI),
+                        v: p.z() / self.height,
+                        dpdu: self
+                            .frame
+                            .local(&(2.0 * PI * Vec3::new(-p.y(), p.x(), 0.0))),
+                        dpdv: self.frame.local(&Vec3::new(0.0, 0.0, self.height)),
+                    });
+                }
+            }
+        }
+
+        if self.capped && d.z().abs() > 1e-12 {
+            for (z, nz) in [(0.0, -1.0), (self.height, 1.0)] {
+                let t = (z - o.z()) / d.z();
+                let p = o + t * d;
+                if p.x() * p.x() + p.y() * p.y() <= self.radius * self.radius {
+                    crossings.push(Crossing {
+                        t,
+                        outward_normal: self.frame.local(&Vec3::new(0.0, 0.0, nz)),
+                        u: 0.5 * (p.x() / self.radius + 1.0),
+                        v: 0.5 * (p.y() / self.radius + 1.0),
+                        dpdu: self.frame.local(&Vec3::new(2.0 * self.radius, 0.0, 0.0)),
+                        dpdv: self.frame.local(&Vec3::new(0.0, 2.0 * self.radius, 0.0)),
+                    });
+                }
+            }
+        }
+
+        crossings
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        nearest_crossing(r, &self.crossings(r), t_min, t_max, self.material_id)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let top = self.base + self.height * self.frame.w();
+        Some(
+            Aabb::disk(&self.base, self.frame.w(), self.radius).surrounding(&Aabb::disk(
+                &top,
+                self.frame.w(),
+                self.radius,
+            )),
+        )
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        if !self.capped {
+            return vec![];
+        }
+        convex_intervals(r, &self.crossings(r), self.material_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cylinder(capped: bool) -> Cylinder {
+        Cylinder::new(
+            Point3::new(0.0, -1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+            capped,
+            0,
+        )
+    }
+
+    fn span(cylinder: &Cylinder, r: &Ray) -> Vec<(f64, f64)> {
+        cylinder
+            .intervals(r)
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect()
+    }
+
+    #[test]
+    fn crosses_the_side() {
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let spans = span(&cylinder(true), &r);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].0 - 4.0).abs() < 1e-9 && (spans[0].1 - 6.0).abs() < 1e-9);
+        match cylinder(true).hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.normal - Vec3::new(-1.0, 0.0, 0.0)).near_zero()),
+            DidHit::Miss => panic!("missed the side"),
+        }
+    }
+
+    #[test]
+    fn crosses_the_caps() {
+        let r = Ray::new(Point3::new(0.0, -5.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        let spans = span(&cylinder(true), &r);
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].0 - 4.0).abs() < 1e-9 && (spans[0].1 - 6.0).abs() < 1e-9);
+        match cylinder(true).hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.normal - Vec3::new(0.0, -1.0, 0.0)).near_zero()),
+            DidHit::Miss => panic!("missed the cap"),
+        }
+    }
+
+    #[test]
+    fn open_tubes_have_no_caps_or_intervals() {
+        let r = Ray::new(Point3::new(0.0, -5.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        assert!(matches!(
+            cylinder(false).hit(&r, 0.001, f64::INFINITY),
+            DidHit::Miss
+        ));
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(span(&cylinder(false), &r).is_empty());
+    }
+
+    #[test]
+    fn hits_from_inside_face_back() {
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        match cylinder(true).hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                assert!((rec.t - 1.0).abs() < 1e-9);
+                assert!(!rec.front_face);
+                assert!((rec.normal - Vec3::new(-1.0, 0.0, 0.0)).near_zero());
+            }
+            DidHit::Miss => panic!("missed from inside"),
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Quick renders of what the camera sees, which do not simulate light, for
+//! setting up scenes and finding faults in them.
+
+use crate::{
+    camera::Camera,
+    hittable::{DidHit, HitRecord, Hittable},
+    onb::Onb,
+    rand::Rand,
+    ray::Ray,
+    raytracer::{Integrator, RaytracerOptions},
+    scene::Scene,
+    vec3::{random_cosine_direction, Color},
+};
+
+/// The color a debug integrator gives the camera ray `r`, or `None` for
+/// integrators that simulate light.
+pub(crate) fn debug_color(
+    r: &Ray,
+    scene: &Scene,
+    options: &RaytracerOptions,
+    camera: &Camera,
+    rand: &mut Rand,
+) -> Option<Color> {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let rec = match options.integrator {
+        Integrator::TraversalCost => return Some(traversal_cost(r, scene)),
+        Integrator::AmbientOcclusion
+        | Integrator::Normals
+        | Integrator::Depth
+        | Integrator::MaterialIds => match scene.hit(r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => rec,
+            DidHit::Miss if options.integrator == Integrator::AmbientOcclusion => {
+                return Some(Color::new(1.0, 1.0, 1.0))
+            }
+            DidHit::Miss => return Some(black),
+        },
+        _ => return None,
+    };
+
+    Some(match options.integrator {
+        Integrator::AmbientOcclusion => ambient_occlusion(&rec, scene, options, rand),
+        Integrator::Normals => {
+            let outward = if rec.front_face {
+                rec.normal
+            } else {
+                -rec.normal
+            };
+            0.5 * (outward + Color::new(1.0, 1.0, 1.0))
+        }
+        Integrator::Depth => {
+            let distance = rec.t * r.direction().length();
+            let near = camera.focus_dist() / (camera.focus_dist() + distance);
+            Color::new(near, near, near)
+        }
+        _ => false_color(rec.material_id() as u64),
+    })
+}
+
+/// One if a cosine-weighted ray from `rec` escapes within the options'
+/// `ao_radius`, else zero.
+fn ambient_occlusion(
+    rec: &HitRecord,
+    scene: &Scene,
+    options: &RaytracerOptions,
+    rand: &mut Rand,
+) -> Color {
+    let uvw = Onb::build_from_w(&rec.normal);
+    let direction = uvw.local(&random_cosine_direction(rand));
+    match scene.hit(&Ray::new(rec.p, direction), 0.001, options.ao_radius) {
+        DidHit::Hit(_) => Color::new(0.0, 0.0, 0.0),
+        DidHit::Miss => Color::new(1.0, 1.0, 1.0),
+    }
+}
+
+/// Surface tests made for `r`, shaded from blue for none through green to
+/// red for as many as there are objects in the scene.
+fn traversal_cost(r: &Ray, scene: &Scene) -> Color {
+    let objects = scene.object_count().max(1) as f64;
+    let cost = (scene.hit_tests(r, 0.001, f64::INFINITY) as f64 / objects).min(1.0);
+    if cost < 0.5 {
+        Color::new(0.0, 2.0 * cost, 1.0 - 2.0 * cost)
+    } else {
+        Color::new(2.0 * cost - 1.0, 2.0 - 2.0 * cost, 0.0)
+    }
+}
+
+/// A saturated color for `id`, with neighboring ids far apart in hue.
+fn false_color(id: u64) -> Color {
+    // Successive multiples of the golden ratio spread evenly round the
+    // hue circle.
+    let hue = (id as f64 * 0.618_033_988_749_895).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as u32 {
+        0 => Color::new(1.0, x, 0.0),
+        1 => Color::new(x, 1.0, 0.0),
+        2 => Color::new(0.0, 1.0, x),
+        3 => Color::new(0.0, x, 1.0),
+        4 => Color::new(x, 0.0, 1.0),
+        _ => Color::new(1.0, 0.0, x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, sphere::Sphere, vec3::Point3, Vec3};
+
+    fn options(integrator: Integrator) -> RaytracerOptions {
+        RaytracerOptions {
+            integrator,
+            ..Default::default()
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera::new(
+            &Point3::new(0.0, 0.0, -5.0),
+            &Point3::new(0.0, 0.0, 0.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            20.0,
+            1.0,
+            0.0,
+            4.0,
+        )
+    }
+
+    /// Unit spheres along +x at multiples of 3, added in `order`.
+    fn row(order: &[usize]) -> Scene {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        for &i in order {
+            let centre = Point3::new(3.0 * i as f64, 0.0, 0.0);
+            scene.add_object(Box::new(Sphere::new(centre, 1.0, material)));
+        }
+        scene
+    }
+
+    fn color(integrator: Integrator, scene: &Scene, r: &Ray) -> Color {
+        let mut rand = Rand::new_with_seed(1);
+        debug_color(r, scene, &options(integrator), &camera(), &mut rand).unwrap()
+    }
+
+    #[test]
+    fn light_integrators_are_not_debug_views() {
+        let scene = row(&[0, 1, 2]);
+        let r = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut rand = Rand::new_with_seed(1);
+        let options = options(Integrator::PathTracer);
+        assert!(debug_color(&r, &scene, &options, &camera(), &mut rand).is_none());
+    }
+
+    #[test]
+    fn shows_normals_and_depth() {
+        let scene = row(&[0, 1, 2]);
+        // Hits the first sphere at distance 4, the focus distance.
+        let r = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 2.0));
+        let normal = color(Integrator::Normals, &scene, &r);
+        assert!((normal - Color::new(0.5, 0.5, 0.0)).length() < 1e-12);
+        let depth = color(Integrator::Depth, &scene, &r);
+        assert!((depth.x() - 0.5).abs() < 1e-12);
+
+        let miss = Ray::new(Point3::new(0.0, 5.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(color(Integrator::Depth, &scene, &miss).x(), 0.0);
+        assert_eq!(color(Integrator::AmbientOcclusion, &scene, &miss).x(), 1.0);
+    }
+
+    #[test]
+    fn occlusion_needs_something_within_reach() {
+        let scene = row(&[0]);
+        let mut rand = Rand::new_with_seed(2);
+        let ao = |r: &Ray, ao_radius: f64, rand: &mut Rand| {
+            let options = RaytracerOptions {
+                ao_radius,
+                ..options(Integrator::AmbientOcclusion)
+            };
+            debug_color(r, &scene, &options, &camera(), rand)
+                .unwrap()
+                .x()
+        };
+        // Seen from inside, the sphere occludes itself once it is within
+        // reach, while from outside it never does.
+        let inside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let outside = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        for _ in 0..100 {
+            assert_eq!(ao(&inside, 2.5, &mut rand), 0.0);
+            assert_eq!(ao(&inside, 1e-3, &mut rand), 1.0);
+            assert_eq!(ao(&outside, 100.0, &mut rand), 1.0);
+        }
+    }
+
+    #[test]
+    fn material_colors_are_distinct_and_saturated() {
+        let colors: Vec<Color> = (0..16).map(false_color).collect();
+        for (i, a) in colors.iter().enumerate() {
+            let max = a.x().max(a.y()).max(a.z());
+            let min = a.x().min(a.y()).min(a.z());
+            assert!(max == 1.0 && min == 0.0, "{:?}", a);
+            if i > 0 {
+                assert!((*a - colors[i - 1]).length() > 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn traversal_cost_counts_the_surfaces_tested() {
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        // Spheres found nearest first hide the boxes of those behind.
+        assert_eq!(row(&[0, 1, 2]).hit_tests(&r, 0.001, f64::INFINITY), 1);
+        assert_eq!(row(&[2, 1, 0]).hit_tests(&r, 0.001, f64::INFINITY), 3);
+        let miss = Ray::new(Point3::new(-5.0, 5.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(row(&[0, 1, 2]).hit_tests(&miss, 0.001, f64::INFINITY), 0);
+
+        // No tests is blue, testing every object red.
+        let blue = color(Integrator::TraversalCost, &row(&[0, 1, 2]), &miss);
+        assert!((blue - Color::new(0.0, 0.0, 1.0)).length() < 1e-12);
+        let red = color(Integrator::TraversalCost, &row(&[2, 1, 0]), &r);
+        assert!((red - Color::new(1.0, 0.0, 0.0)).length() < 1e-12);
+    }
+}
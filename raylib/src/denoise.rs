@@ -0,0 +1,270 @@
+//! A joint bilateral filter for rendered images, guided by the albedo,
+//! normal and depth AOVs so that it smooths noise without blurring across
+//! the edges of objects or textures.
+
+use crate::{vec3::Color, Vec3};
+
+/// Spread of the albedo differences across which pixels are still mixed.
+const ALBEDO_SIGMA: f64 = 0.1;
+/// Spread of the normal differences across which pixels are still mixed.
+const NORMAL_SIGMA: f64 = 0.25;
+/// Spread of the depth differences across which pixels are still mixed, as
+/// a fraction of the depth.
+const DEPTH_SIGMA: f64 = 0.05;
+/// Spread of the relative color differences across which pixels are still
+/// mixed, at full strength.
+const COLOR_SIGMA: f64 = 0.5;
+
+/// What the denoiser knows about a pixel.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Guide {
+    color: Color,
+    albedo: Color,
+    normal: Vec3,
+    depth: f64,
+}
+
+impl Guide {
+    pub(crate) fn new(color: Color, albedo: Color, normal: Vec3, depth: f64) -> Self {
+        Guide {
+            color,
+            albedo,
+            normal,
+            depth,
+        }
+    }
+
+    /// A pixel without AOVs, which the denoiser can only tell apart from
+    /// others by color.
+    pub(crate) fn from_color(color: Color) -> Self {
+        Guide::new(
+            color,
+            Color::new(1.0, 1.0, 1.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            0.0,
+        )
+    }
+
+    /// The albedo to divide the color by, leaving the light reaching the
+    /// surface, which is smoother than the color wherever textures are.
+    /// Channels too dark to divide by are left as they are.
+    fn divisor(&self) -> Color {
+        let safe = |a: f64| if a > 0.01 { a } else { 1.0 };
+        Color::new(
+            safe(self.albedo.x()),
+            safe(self.albedo.y()),
+            safe(self.albedo.z()),
+        )
+    }
+
+    /// The light reaching the surface, by which the color is filtered.
+    fn irradiance(&self) -> Color {
+        let divisor = self.divisor();
+        Color::new(
+            self.color.x() / divisor.x(),
+            self.color.y() / divisor.y(),
+            self.color.z() / divisor.z(),
+        )
+    }
+}
+
+/// Removes noise from finished images, as a post-process through
+/// `Raytracer::denoised_line`.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    strength: f64,
+    radius: u32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Denoiser {
+            strength: 1.0,
+            radius: 5,
+        }
+    }
+
+    /// How far apart in color pixels may be and still be mixed: 0 leaves
+    /// the image as rendered, 1 suits renders of a few samples per pixel,
+    /// and more smooths harder.
+    pub fn set_strength(&mut self, strength: f64) {
+        self.strength = strength.max(0.0)
+    }
+
+    /// How many pixels away in each direction pixels are mixed from.
+    pub fn set_radius(&mut self, radius: u32) {
+        self.radius = radius
+    }
+
+    /// Lines on each side of a line that filtering it reads.
+    pub(crate) fn reach(&self) -> u32 {
+        // The colors compared are averaged over a pixel's neighbors.
+        self.radius + 1
+    }
+
+    /// Filters line `y` of `rows`, which holds the lines within `reach` of
+    /// it that are in the image.
+    pub(crate) fn filter_line(&self, rows: &[Vec<Guide>], y: usize) -> Vec<Color> {
+        if self.strength <= 0.0 || self.radius == 0 {
+            return rows[y].iter().map(|pixel| pixel.color).collect();
+        }
+
+        let irradiance: Vec<Vec<Color>> = rows
+            .iter()
+            .map(|row| row.iter().map(Guide::irradiance).collect())
+            .collect();
+        let smoothed = box_filter(&irradiance);
+
+        let radius = self.radius as isize;
+        let spatial = 2.0 * (0.5 * self.radius as f64).max(1.0).powi(2);
+        let color = 2.0 * (COLOR_SIGMA * self.strength).powi(2);
+        let height = rows.len() as isize;
+        let width = rows[y].len() as isize;
+
+        (0..width)
+            .map(|x| {
+                let p = &rows[y][x as usize];
+                let m_p = smoothed[y][x as usize];
+                let mut sum = Color::new(0.0, 0.0, 0.0);
+                let mut weights = 0.0;
+                for j in (y as isize - radius).max(0)..(y as isize + radius + 1).min(height) {
+                    for i in (x - radius).max(0)..(x + radius + 1).min(width) {
+                        let (j, i) = (j as usize, i as usize);
+                        let q = &rows[j][i];
+                        let m_q = smoothed[j][i];
+                        let (dx, dy) = (i as f64 - x as f64, j as f64 - y as f64);
+                        let scale = 1e-4 + m_p.length_squared() + m_q.length_squared();
+                        let depth = (p.depth - q.depth) / (DEPTH_SIGMA * p.depth.max(1e-4));
+                        let exponent = (dx * dx + dy * dy) / spatial
+                            + (m_p - m_q).length_squared() / (scale * color)
+                            + (p.albedo - q.albedo).length_squared()
+                                / (ALBEDO_SIGMA * ALBEDO_SIGMA)
+                            + (p.normal - q.normal).length_squared()
+                                / (NORMAL_SIGMA * NORMAL_SIGMA)
+                            + depth * depth;
+                        let weight = (-exponent).exp();
+                        sum += weight * irradiance[j][i];
+                        weights += weight;
+                    }
+                }
+                // The pixel itself always has weight one.
+                sum / weights * p.divisor()
+            })
+            .collect()
+    }
+}
+
+/// Each pixel averaged with its neighbors in a 3 by 3 square.
+fn box_filter(rows: &[Vec<Color>]) -> Vec<Vec<Color>> {
+    let height = rows.len() as isize;
+    (0..height)
+        .map(|y| {
+            let width = rows[y as usize].len() as isize;
+            (0..width)
+                .map(|x| {
+                    let mut sum = Color::new(0.0, 0.0, 0.0);
+                    let mut count = 0.0;
+                    for j in (y - 1).max(0)..(y + 2).min(height) {
+                        for i in (x - 1).max(0)..(x + 2).min(width) {
+                            sum += rows[j as usize][i as usize];
+                            count += 1.0;
+                        }
+                    }
+                    sum / count
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rand::Rand;
+
+    const SIZE: usize = 16;
+
+    /// A grey image with noise of spread `noise`, whose left and right
+    /// halves face different ways if `edge`.
+    fn image(noise: f64, edge: bool) -> Vec<Vec<Guide>> {
+        let mut rand = Rand::new_with_seed(5);
+        (0..SIZE)
+            .map(|_| {
+                (0..SIZE)
+                    .map(|x| {
+                        let right = edge && x >= SIZE / 2;
+                        let level = if right { 0.1 } else { 0.5 };
+                        let level = level * (1.0 + noise * (rand.random_double() - 0.5));
+                        let normal = if right {
+                            Vec3::new(1.0, 0.0, 0.0)
+                        } else {
+                            Vec3::new(0.0, 0.0, -1.0)
+                        };
+                        let color = Color::new(level, level, level);
+                        Guide::new(color, Color::new(0.5, 0.5, 0.5), normal, 4.0)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn filter(denoiser: &Denoiser, rows: &[Vec<Guide>]) -> Vec<Vec<Color>> {
+        (0..rows.len())
+            .map(|y| denoiser.filter_line(rows, y))
+            .collect()
+    }
+
+    /// Mean squared difference from `level` over the columns `columns`.
+    fn error(image: &[Vec<Color>], columns: std::ops::Range<usize>, level: f64) -> f64 {
+        let mut sum = 0.0;
+        for row in image {
+            for pixel in &row[columns.clone()] {
+                sum += (pixel.x() - level).powi(2);
+            }
+        }
+        sum / (image.len() * columns.len()) as f64
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let out = filter(&Denoiser::new(), &image(0.0, false));
+        assert!(error(&out, 0..SIZE, 0.5) < 1e-20);
+    }
+
+    #[test]
+    fn zero_strength_keeps_the_image() {
+        let rows = image(0.5, false);
+        let mut denoiser = Denoiser::new();
+        denoiser.set_strength(0.0);
+        let out = filter(&denoiser, &rows);
+        for (row, out) in rows.iter().zip(&out) {
+            for (pixel, out) in row.iter().zip(out) {
+                assert_eq!(pixel.color.x(), out.x());
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_reduced() {
+        let rows = image(0.5, false);
+        let noisy: Vec<Vec<Color>> = rows
+            .iter()
+            .map(|row| row.iter().map(|pixel| pixel.color).collect())
+            .collect();
+        let out = filter(&Denoiser::new(), &rows);
+        assert!(error(&out, 0..SIZE, 0.5) < 0.2 * error(&noisy, 0..SIZE, 0.5));
+    }
+
+    #[test]
+    fn edges_between_normals_are_kept() {
+        let out = filter(&Denoiser::new(), &image(0.0, true));
+        assert!(error(&out, 0..SIZE / 2, 0.5) < 1e-6);
+        assert!(error(&out, SIZE / 2..SIZE, 0.1) < 1e-6);
+    }
+}
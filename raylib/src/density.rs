@@ -0,0 +1,274 @@
+use std::fmt;
+
+use crate::{perlin::Perlin, rand::Rand, vec3::Point3};
+
+/// A scalar field sampled in the unit cube `[0, 1]^3` of a medium's bounds.
+pub trait DensityField {
+    fn density(&self, p: &Point3) -> f64;
+    /// An upper bound on `density` anywhere in the unit cube.
+    fn max_density(&self) -> f64;
+}
+
+pub struct ConstantDensity {
+    density: f64,
+}
+
+impl ConstantDensity {
+    pub fn new(density: f64) -> Self {
+        ConstantDensity { density }
+    }
+}
+
+impl DensityField for ConstantDensity {
+    fn density(&self, _p: &Point3) -> f64 {
+        self.density
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+#[derive(Debug)]
+pub enum GridError {
+    /// The buffer ended before the header or voxel data was complete.
+    Truncated,
+    /// One of the grid dimensions was zero.
+    EmptyGrid,
+    /// The grid dimensions describe more voxels than can be addressed.
+    TooLarge,
+    /// The number of densities differs from the number of voxels.
+    WrongLength,
+    /// A density was negative or not a number.
+    InvalidDensity,
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::Truncated => write!(f, "voxel grid data is truncated"),
+            GridError::EmptyGrid => write!(f, "voxel grid has a zero dimension"),
+            GridError::TooLarge => write!(f, "voxel grid is too large"),
+            GridError::WrongLength => write!(f, "voxel grid data does not match its size"),
+            GridError::InvalidDensity => write!(f, "voxel grid has a negative or NaN density"),
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+/// A dense grid of densities, trilinearly interpolated between voxel centers.
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f32>,
+    max: f64,
+}
+
+impl VoxelGrid {
+    /// `data` is laid out x-fastest, then y, then z.
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f32>) -> Result<Self, GridError> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(GridError::EmptyGrid);
+        }
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .ok_or(GridError::TooLarge)?;
+        if data.len() != count {
+            return Err(GridError::WrongLength);
+        }
+        // Negative densities would make the majorant wrong, and NaN would
+        // poison every sample interpolated from it.
+        if data.iter().any(|d| d.is_nan() || *d < 0.0) {
+            return Err(GridError::InvalidDensity);
+        }
+
+        let max = data.iter().fold(0.0f32, |m, &d| m.max(d)) as f64;
+        Ok(VoxelGrid {
+            nx,
+            ny,
+            nz,
+            data,
+            max,
+        })
+    }
+
+    /// Parses the raw grid format: three little-endian `u32` dimensions
+    /// `nx`, `ny`, `nz` followed by `nx * ny * nz` little-endian `f32`
+    /// densities, x-fastest.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, GridError> {
+        let read_u32 = |offset: usize| -> Result<u32, GridError> {
+            bytes
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or(GridError::Truncated)
+        };
+
+        let nx = read_u32(0)? as usize;
+        let ny = read_u32(4)? as usize;
+        let nz = read_u32(8)? as usize;
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(GridError::EmptyGrid);
+        }
+
+        let end = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .and_then(|count| count.checked_mul(4))
+            .and_then(|size| size.checked_add(12))
+            .ok_or(GridError::TooLarge)?;
+        let body = bytes.get(12..end).ok_or(GridError::Truncated)?;
+        let data = body
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        VoxelGrid::new(nx, ny, nz, data)
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.data[(z * self.ny + y) * self.nx + x] as f64
+    }
+}
+
+impl DensityField for VoxelGrid {
+    fn density(&self, p: &Point3) -> f64 {
+        // Voxel centers sit at (i + 0.5) / n along each axis.
+        let gx = p.x() * self.nx as f64 - 0.5;
+        let gy = p.y() * self.ny as f64 - 0.5;
+        let gz = p.z() * self.nz as f64 - 0.5;
+
+        let clamp_index = |g: f64, n: usize| (g.floor().max(0.0) as usize).min(n - 1);
+        let (x0, y0, z0) = (
+            clamp_index(gx, self.nx),
+            clamp_index(gy, self.ny),
+            clamp_index(gz, self.nz),
+        );
+        let (x1, y1, z1) = (
+            (x0 + 1).min(self.nx - 1),
+            (y0 + 1).min(self.ny - 1),
+            (z0 + 1).min(self.nz - 1),
+        );
+        let fx = (gx - x0 as f64).clamp(0.0, 1.0);
+        let fy = (gy - y0 as f64).clamp(0.0, 1.0);
+        let fz = (gz - z0 as f64).clamp(0.0, 1.0);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+/// Procedural density from Perlin turbulence, for clouds and smoke.
+pub struct NoiseDensity {
+    perlin: Perlin,
+    frequency: f64,
+    octaves: u32,
+    scale: f64,
+}
+
+impl NoiseDensity {
+    pub fn new(frequency: f64, octaves: u32, scale: f64, rand: &mut Rand) -> Self {
+        NoiseDensity {
+            perlin: Perlin::new(rand),
+            frequency,
+            octaves,
+            scale,
+        }
+    }
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, p: &Point3) -> f64 {
+        self.scale * self.perlin.turb(&(self.frequency * p), self.octaves)
+    }
+
+    fn max_density(&self) -> f64 {
+        // Each octave of gradient noise is bounded by roughly 1.0, and octave
+        // weights halve, so the turbulence sum is bounded by 2.0.
+        2.0 * self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_bytes(dims: [u32; 3], data: &[f32]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for n in dims {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for d in data {
+            bytes.extend_from_slice(&d.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_a_grid() {
+        let data = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0];
+        let grid = VoxelGrid::from_bytes(&grid_bytes([2, 2, 2], &data)).unwrap();
+        assert_eq!(grid.max_density(), 7.0);
+        // Voxel centers sit at a quarter and three quarters of each axis.
+        assert_eq!(grid.density(&Point3::new(0.25, 0.25, 0.25)), 0.0);
+        assert_eq!(grid.density(&Point3::new(0.75, 0.25, 0.25)), 1.0);
+        assert_eq!(grid.density(&Point3::new(0.25, 0.75, 0.25)), 2.0);
+        assert_eq!(grid.density(&Point3::new(0.25, 0.25, 0.75)), 4.0);
+        assert_eq!(grid.density(&Point3::new(0.5, 0.5, 0.5)), 3.5);
+        // Outside the voxel centers the nearest values hold.
+        assert_eq!(grid.density(&Point3::new(1.0, 1.0, 1.0)), 7.0);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(matches!(
+            VoxelGrid::from_bytes(&[0; 8]),
+            Err(GridError::Truncated)
+        ));
+        assert!(matches!(
+            VoxelGrid::from_bytes(&grid_bytes([2, 0, 2], &[])),
+            Err(GridError::EmptyGrid)
+        ));
+        assert!(matches!(
+            VoxelGrid::from_bytes(&grid_bytes([2, 2, 2], &[1.0; 7])),
+            Err(GridError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let huge = grid_bytes([u32::MAX, u32::MAX, u32::MAX], &[]);
+        assert!(matches!(
+            VoxelGrid::from_bytes(&huge),
+            Err(GridError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_densities() {
+        assert!(matches!(
+            VoxelGrid::new(2, 1, 1, vec![1.0, -0.5]),
+            Err(GridError::InvalidDensity)
+        ));
+        assert!(matches!(
+            VoxelGrid::new(2, 1, 1, vec![f32::NAN, 1.0]),
+            Err(GridError::InvalidDensity)
+        ));
+        assert!(matches!(
+            VoxelGrid::new(2, 2, 1, vec![1.0; 3]),
+            Err(GridError::WrongLength)
+        ));
+        assert!(VoxelGrid::new(2, 1, 1, vec![0.0, 1.0]).is_ok());
+    }
+}
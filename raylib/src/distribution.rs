@@ -0,0 +1,176 @@
+//! Piecewise-constant distributions for importance sampling tabulated
+//! functions such as environment maps.
+
+/// A distribution over `0..1` proportional to a step function of equal
+/// width steps.
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// Builds the distribution for the steps `func`, which must not be
+    /// negative. A function that is zero everywhere samples uniformly.
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Average value of the function over `0..1`.
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Maps a uniform `u` to a point in `0..1`, returning it with its
+    /// density and the step it fell in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        let offset = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        let x = (offset as f64 + du) / n as f64;
+        (x.min(1.0 - f64::EPSILON), self.pdf_of(offset), offset)
+    }
+
+    /// Density of the point `x` in `0..1`.
+    pub fn pdf(&self, x: f64) -> f64 {
+        let offset = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.pdf_of(offset)
+    }
+
+    fn pdf_of(&self, offset: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[offset] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A distribution over the unit square proportional to a grid of values,
+/// sampled by picking a row from their marginal and then a column within
+/// it.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Builds the distribution for `width` by `height` values stored row
+    /// by row.
+    pub fn new(values: &[f64], width: usize, height: usize) -> Self {
+        assert_eq!(values.len(), width * height);
+        let rows: Vec<Distribution1D> = values
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Maps two uniform numbers to a point `(x, y)` in the unit square,
+    /// with its density.
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample(u2);
+        let (x, pdf_x, _) = self.rows[row].sample(u1);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evenly spaced numbers in `0..1`, standing in for uniform samples.
+    fn uniforms(n: usize) -> impl Iterator<Item = f64> {
+        (0..n).map(move |i| (i as f64 + 0.5) / n as f64)
+    }
+
+    #[test]
+    fn sample_density_matches_pdf_1d() {
+        let d = Distribution1D::new(vec![1.0, 0.0, 3.0, 0.5, 2.0]);
+        for u in uniforms(1000) {
+            let (x, pdf, offset) = d.sample(u);
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(offset, (x * d.count() as f64) as usize);
+            assert!(pdf > 0.0, "sampled the empty step at {}", x);
+            assert!((pdf - d.pdf(x)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_1d() {
+        let d = Distribution1D::new(vec![1.0, 0.0, 3.0, 0.5, 2.0]);
+        let n = 1000;
+        let integral: f64 = uniforms(n).map(|x| d.pdf(x)).sum::<f64>() / n as f64;
+        assert!((integral - 1.0).abs() < 1e-9);
+        assert!((d.integral() - 6.5 / 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_function_samples_uniformly() {
+        let d = Distribution1D::new(vec![0.0; 4]);
+        for u in uniforms(100) {
+            let (x, pdf, _) = d.sample(u);
+            assert!((x - u).abs() < 1e-12);
+            assert_eq!(pdf, 1.0);
+        }
+    }
+
+    #[test]
+    fn sample_density_matches_pdf_2d() {
+        let values = [0.0, 1.0, 2.0, 4.0, 0.0, 0.5, 3.0, 3.0, 1.0, 0.0, 0.0, 0.0];
+        let d = Distribution2D::new(&values, 4, 3);
+        for u1 in uniforms(50) {
+            for u2 in uniforms(50) {
+                let ((x, y), pdf) = d.sample(u1, u2);
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                assert!(pdf > 0.0, "sampled an empty cell at ({}, {})", x, y);
+                assert!((pdf - d.pdf(x, y)).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_2d() {
+        let values = [0.0, 1.0, 2.0, 4.0, 0.0, 0.5, 3.0, 3.0, 1.0, 0.0, 0.0, 0.0];
+        let d = Distribution2D::new(&values, 4, 3);
+        let n = 120;
+        let integral: f64 = uniforms(n)
+            .flat_map(|y| uniforms(n).map(move |x| (x, y)))
+            .map(|(x, y)| d.pdf(x, y))
+            .sum::<f64>()
+            / (n * n) as f64;
+        assert!((integral - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Mutex;
+
+use crate::vec3::{rgba_multisampled, Color};
+
+/// Sums of the light gathered for each pixel, kept linear so that light
+/// splatted onto arbitrary pixels by light paths can be added before the
+/// image is developed.
+pub(crate) struct Film {
+    width: usize,
+    height: usize,
+    samples_per_pixel: u32,
+    /// Sums of the samples taken through each pixel, row by row.
+    pixels: Mutex<Vec<Color>>,
+    /// Sums of the light splatted onto each pixel, row by row.
+    splats: Mutex<Vec<Color>>,
+}
+
+impl Film {
+    pub(crate) fn new(width: u32, height: u32, samples_per_pixel: u32) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        Film {
+            width,
+            height,
+            samples_per_pixel,
+            pixels: Mutex::new(vec![Color::new(0.0, 0.0, 0.0); width * height]),
+            splats: Mutex::new(vec![Color::new(0.0, 0.0, 0.0); width * height]),
+        }
+    }
+
+    /// Records the sums of the samples taken through line `y`.
+    pub(crate) fn set_line(&self, y: u32, sums: &[Color]) {
+        let start = y as usize * self.width;
+        self.pixels.lock().unwrap()[start..start + self.width].copy_from_slice(sums);
+    }
+
+    /// Adds light landing at film coordinates `(s, t)`, as passed to
+    /// `Camera::get_ray`. Light landing outside the image is dropped.
+    pub(crate) fn add_splats(&self, splats: &[(f64, f64, Color)]) {
+        if splats.is_empty() {
+            return;
+        }
+        let mut buffer = self.splats.lock().unwrap();
+        for (s, t, color) in splats {
+            // Pixel i covers film coordinates from i / (width - 1).
+            let i = (s * (self.width - 1) as f64).floor();
+            let j = (t * (self.height - 1) as f64).floor();
+            if i < 0.0 || j < 0.0 || i >= self.width as f64 || j >= self.height as f64 {
+                continue;
+            }
+            buffer[j as usize * self.width + i as usize] += *color;
+        }
+    }
+
+    /// Line `y` of the image, averaging the samples through each pixel and
+    /// adding the light splatted onto it scaled by `splat_scale`.
+    pub(crate) fn developed_colors(&self, y: u32, splat_scale: f64) -> Vec<Color> {
+        let start = y as usize * self.width;
+        let scale = 1.0 / self.samples_per_pixel as f64;
+        let pixels = self.pixels.lock().unwrap();
+        let splats = self.splats.lock().unwrap();
+        (start..start + self.width)
+            .map(|i| scale * (pixels[i] + splat_scale * splats[i]))
+            .collect()
+    }
+}
+
+/// RGBA bytes for a line of pixels from the sums of their samples.
+pub(crate) fn encode_line(sums: &[Color], samples_per_pixel: u32) -> Vec<u8> {
+    let mut line = vec![0; sums.len() * 4];
+    for (i, sum) in sums.iter().enumerate() {
+        let rgba = rgba_multisampled(sum, samples_per_pixel);
+        line[4 * i] = rgba.0;
+        line[4 * i + 1] = rgba.1;
+        line[4 * i + 2] = rgba.2;
+        line[4 * i + 3] = rgba.3;
+    }
+    line
+}
@@ -0,0 +1,219 @@
+use crate::{util::PI, vec3::Color};
+
+#[derive(Debug, Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+    fn div(self, o: Complex) -> Complex {
+        let scale = 1.0 / (o.re * o.re + o.im * o.im);
+        Complex::new(
+            scale * (self.re * o.re + self.im * o.im),
+            scale * (self.im * o.re - self.re * o.im),
+        )
+    }
+    fn scale(self, s: f64) -> Complex {
+        Complex::new(self.re * s, self.im * s)
+    }
+    /// `e^(i * self)`
+    fn exp_i(self) -> Complex {
+        let magnitude = (-self.im).exp();
+        Complex::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+    fn norm(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+    fn sqrt(self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+/// Unpolarized Fresnel reflectance at a boundary between dielectrics, where
+/// `eta` is the ratio of the index on the far side of the normal to the
+/// index on the near side. A negative `cos_theta_i` means light arrives
+/// from behind the normal.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i.max(-1.0), 1.0 / eta)
+    } else {
+        (cos_theta_i.min(1.0), eta)
+    };
+
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with complex index of
+/// refraction `eta + ik`, for light arriving at `cos_theta_i` to the normal.
+pub fn fresnel_complex(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let eta = Complex::new(eta, k);
+    let cos_i = Complex::new(cos_theta_i, 0.0);
+
+    // Snell's law gives the (complex) transmitted angle.
+    let sin2_i = Complex::new(1.0 - cos_theta_i * cos_theta_i, 0.0);
+    let sin2_t = sin2_i.div(eta.mul(eta));
+    let cos_t = Complex::new(1.0, 0.0).sub(sin2_t).sqrt();
+
+    let r_parl = eta.mul(cos_i).sub(cos_t).div(eta.mul(cos_i).add(cos_t));
+    let r_perp = cos_i.sub(eta.mul(cos_t)).div(cos_i.add(eta.mul(cos_t)));
+    (r_parl.norm() + r_perp.norm()) / 2.0
+}
+
+/// Per-channel `fresnel_complex`.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    Color::new(
+        fresnel_complex(cos_theta_i, eta.x(), k.x()),
+        fresnel_complex(cos_theta_i, eta.y(), k.y()),
+        fresnel_complex(cos_theta_i, eta.z(), k.z()),
+    )
+}
+
+/// Unpolarized reflectance of a thin film of index `film_ior` and
+/// `thickness` nanometres, between an incident medium of index `outside`
+/// and a substrate of complex index `substrate_eta + i substrate_k`, for
+/// light of `wavelength` nanometres. Sums the multiple reflections inside
+/// the film with their phases, which is what makes the color vary with
+/// thickness and angle.
+pub fn fresnel_thin_film(
+    cos_theta_i: f64,
+    outside: f64,
+    film_ior: f64,
+    substrate_eta: f64,
+    substrate_k: f64,
+    thickness: f64,
+    wavelength: f64,
+) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let n1 = Complex::new(outside, 0.0);
+    let n2 = Complex::new(film_ior, 0.0);
+    let n3 = Complex::new(substrate_eta, substrate_k);
+
+    // Snell's law carries the same n sin(theta) through every layer.
+    let sin2_i = Complex::new(1.0 - cos_theta_i * cos_theta_i, 0.0);
+    let cos_in = |n: Complex| {
+        let ratio = n1.div(n);
+        Complex::new(1.0, 0.0)
+            .sub(ratio.mul(ratio).mul(sin2_i))
+            .sqrt()
+    };
+    let (c1, c2, c3) = (Complex::new(cos_theta_i, 0.0), cos_in(n2), cos_in(n3));
+
+    let r_perp = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        na.mul(ca).sub(nb.mul(cb)).div(na.mul(ca).add(nb.mul(cb)))
+    };
+    let r_parl = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        nb.mul(ca).sub(na.mul(cb)).div(nb.mul(ca).add(na.mul(cb)))
+    };
+
+    // Phase gained by a round trip through the film.
+    let phase = n2.mul(c2).scale(4.0 * PI * thickness / wavelength).exp_i();
+    let airy = |r12: Complex, r23: Complex| {
+        let r23 = r23.mul(phase);
+        r12.add(r23)
+            .div(Complex::new(1.0, 0.0).add(r12.mul(r23)))
+            .norm()
+    };
+
+    let perp = airy(r_perp(n1, c1, n2, c2), r_perp(n2, c2, n3, c3));
+    let parl = airy(r_parl(n1, c1, n2, c2), r_parl(n2, c2, n3, c3));
+    ((perp + parl) / 2.0).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dielectric_matches_schlick_endpoints() {
+        // (n - 1)^2 / (n + 1)^2 at normal incidence, total at grazing.
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(1e-9, 1.5) - 1.0).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn dielectric_is_symmetric_and_reflects_internally() {
+        // Light leaving glass at normal incidence reflects as much as light
+        // entering it.
+        assert!((fresnel_dielectric(-1.0, 1.5) - fresnel_dielectric(1.0, 1.5)).abs() < 1e-12);
+        // Past the critical angle of asin(1 / 1.5) everything reflects.
+        assert_eq!(fresnel_dielectric(-0.5, 1.5), 1.0);
+        assert!(fresnel_dielectric(-0.9, 1.5) < 1.0);
+    }
+
+    #[test]
+    fn complex_reduces_to_dielectric_without_extinction() {
+        for cos in [0.1, 0.4, 0.7, 1.0] {
+            let expected = fresnel_dielectric(cos, 1.5);
+            assert!((fresnel_complex(cos, 1.5, 0.0) - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn conductor_at_normal_incidence() {
+        let (eta, k) = (0.2, 3.6);
+        let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert!((fresnel_complex(1.0, eta, k) - expected).abs() < 1e-12);
+        let color = fresnel_conductor(1.0, &Color::new(eta, 1.0, eta), &Color::new(k, 0.0, k));
+        assert!((color.x() - expected).abs() < 1e-12);
+        assert!(color.y().abs() < 1e-12);
+    }
+
+    #[test]
+    fn thin_film_vanishes_into_the_bare_substrate() {
+        // A film of zero thickness, or one matching the outside medium,
+        // leaves the substrate's own reflectance.
+        for cos in [0.2, 0.6, 1.0] {
+            let bare = fresnel_complex(cos, 1.5, 0.0);
+            let zero = fresnel_thin_film(cos, 1.0, 1.33, 1.5, 0.0, 0.0, 550.0);
+            let matched = fresnel_thin_film(cos, 1.0, 1.0, 1.5, 0.0, 300.0, 550.0);
+            assert!((zero - bare).abs() < 1e-9, "{} {}", zero, bare);
+            assert!((matched - bare).abs() < 1e-9, "{} {}", matched, bare);
+        }
+    }
+
+    #[test]
+    fn thin_film_interferes_with_thickness() {
+        // A quarter-wave film on glass cancels less than a half-wave one.
+        let quarter = fresnel_thin_film(1.0, 1.0, 1.33, 1.0, 0.0, 550.0 / (4.0 * 1.33), 550.0);
+        let half = fresnel_thin_film(1.0, 1.0, 1.33, 1.0, 0.0, 550.0 / (2.0 * 1.33), 550.0);
+        assert!(quarter > 0.05, "{}", quarter);
+        assert!(half < 1e-9, "{}", half);
+    }
+}
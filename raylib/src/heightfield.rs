@@ -0,0 +1,488 @@
+use std::fmt;
+
+use crate::{
+    aabb::Aabb,
+    hittable::{Crossing, DidHit, Hittable},
+    ray::Ray,
+    scene::MaterialId,
+    vec3::{cross, dot, unit_vector, Point3},
+    Vec3,
+};
+
+#[derive(Debug)]
+pub enum HeightfieldError {
+    /// The grid has fewer than two samples along x or z.
+    TooSmall,
+    /// The number of heights differs from the number of grid samples.
+    WrongLength,
+}
+
+impl fmt::Display for HeightfieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightfieldError::TooSmall => {
+                write!(f, "heightfield needs at least two samples along each axis")
+            }
+            HeightfieldError::WrongLength => {
+                write!(f, "heightfield data does not match its size")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeightfieldError {}
+
+/// Terrain defined by a regular grid of heights over the xz plane.
+///
+/// Rays are traversed through a min/max mipmap of the grid cells, so only
+/// cells whose height range the ray actually passes through are tested.
+/// Each cell is split into two triangles shaded with interpolated vertex
+/// normals.
+pub struct Heightfield {
+    /// Corner of the grid with the smallest x and z, at height zero.
+    origin: Point3,
+    /// World-space extent along x, the height of a sample of 1.0, and the
+    /// extent along z.
+    size: Vec3,
+    nx: usize,
+    nz: usize,
+    heights: Vec<f64>,
+    normals: Vec<Vec3>,
+    /// `levels[0]` holds the (min, max) height of every cell, and each
+    /// further level the range of a 2x2 block of the level below.
+    levels: Vec<MipLevel>,
+    material_id: MaterialId,
+}
+
+/// A ray being traversed, with `t_max` shrinking to the closest hit so far.
+struct Traversal<'a> {
+    r: &'a Ray,
+    t_min: f64,
+    t_max: f64,
+    closest: Option<Crossing>,
+}
+
+struct MipLevel {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f64, f64)>,
+}
+
+impl MipLevel {
+    fn range(&self, i: usize, j: usize) -> (f64, f64) {
+        self.ranges[j * self.width + i]
+    }
+}
+
+impl Heightfield {
+    /// `heights` holds `nx * nz` samples laid out x-fastest; both dimensions
+    /// must be at least two.
+    pub fn new(
+        heights: Vec<f64>,
+        nx: usize,
+        nz: usize,
+        origin: Point3,
+        size: Vec3,
+        material_id: MaterialId,
+    ) -> Result<Self, HeightfieldError> {
+        if nx < 2 || nz < 2 {
+            return Err(HeightfieldError::TooSmall);
+        }
+        if nx.checked_mul(nz) != Some(heights.len()) {
+            return Err(HeightfieldError::WrongLength);
+        }
+
+        let mut field = Heightfield {
+            origin,
+            size,
+            nx,
+            nz,
+            heights,
+            normals: vec![],
+            levels: vec![],
+            material_id,
+        };
+        field.normals = field.compute_normals();
+        field.levels = field.build_levels();
+        Ok(field)
+    }
+
+    /// Builds a heightfield from 8-bit grayscale pixels, mapping 0..=255 to
+    /// heights 0.0..=1.0. Rows run along x and successive rows along z.
+    pub fn from_grayscale(
+        width: usize,
+        height: usize,
+        pixels: &[u8],
+        origin: Point3,
+        size: Vec3,
+        material_id: MaterialId,
+    ) -> Result<Self, HeightfieldError> {
+        let heights = pixels.iter().map(|&p| p as f64 / 255.0).collect();
+        Heightfield::new(heights, width, height, origin, size, material_id)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3 {
+        self.origin
+            + Vec3::new(
+                self.size.x() * i as f64 / (self.nx - 1) as f64,
+                self.size.y() * self.heights[j * self.nx + i],
+                self.size.z() * j as f64 / (self.nz - 1) as f64,
+            )
+    }
+
+    fn compute_normals(&self) -> Vec<Vec3> {
+        let mut normals = Vec::with_capacity(self.nx * self.nz);
+        for j in 0..self.nz {
+            for i in 0..self.nx {
+                // Central differences, falling back to one-sided ones at
+                // the edges of the grid.
+                let dx =
+                    self.vertex((i + 1).min(self.nx - 1), j) - self.vertex(i.saturating_sub(1), j);
+                let dz =
+                    self.vertex(i, (j + 1).min(self.nz - 1)) - self.vertex(i, j.saturating_sub(1));
+                normals.push(unit_vector(&cross(&dz, &dx)));
+            }
+        }
+        normals
+    }
+
+    fn build_levels(&self) -> Vec<MipLevel> {
+        let (cx, cz) = (self.nx - 1, self.nz - 1);
+        let mut ranges = Vec::with_capacity(cx * cz);
+        for j in 0..cz {
+            for i in 0..cx {
+                let corners = [
+                    self.heights[j * self.nx + i],
+                    self.heights[j * self.nx + i + 1],
+                    self.heights[(j + 1) * self.nx + i],
+                    self.heights[(j + 1) * self.nx + i + 1],
+                ];
+                let min = corners.iter().cloned().fold(f64::INFINITY, f64::min);
+                let max = corners.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                ranges.push((min, max));
+            }
+        }
+
+        let mut levels = vec![MipLevel {
+            width: cx,
+            depth: cz,
+            ranges,
+        }];
+        while levels.last().is_some_and(|l| l.width > 1 || l.depth > 1) {
+            let below = levels.last().unwrap();
+            let (width, depth) = (below.width.div_ceil(2), below.depth.div_ceil(2));
+            let mut ranges = Vec::with_capacity(width * depth);
+            for j in 0..depth {
+                for i in 0..width {
+                    let mut range = (f64::INFINITY, f64::NEG_INFINITY);
+                    for (ci, cj) in [
+                        (2 * i, 2 * j),
+                        (2 * i + 1, 2 * j),
+                        (2 * i, 2 * j + 1),
+                        (2 * i + 1, 2 * j + 1),
+                    ] {
+                        if ci < below.width && cj < below.depth {
+                            let (lo, hi) = below.range(ci, cj);
+                            range = (range.0.min(lo), range.1.max(hi));
+                        }
+                    }
+                    ranges.push(range);
+                }
+            }
+            levels.push(MipLevel {
+                width,
+                depth,
+                ranges,
+            });
+        }
+        levels
+    }
+
+    /// World-space bounds of node `(i, j)` at `level`. Flat nodes would
+    /// have a zero-thickness box, which rays cannot enter, so boxes are
+    /// padded slightly.
+    fn node_box(&self, level: usize, i: usize, j: usize) -> Aabb {
+        let span = 1 << level;
+        let (cx, cz) = ((self.nx - 1) as f64, (self.nz - 1) as f64);
+        let (lo, hi) = self.levels[level].range(i, j);
+        let pad = Vec3::new(1e-9, 1e-9, 1e-9);
+        Aabb::new(
+            self.origin
+                + Vec3::new(
+                    self.size.x() * (i * span) as f64 / cx,
+                    self.size.y() * lo,
+                    self.size.z() * (j * span) as f64 / cz,
+                )
+                - pad,
+            self.origin
+                + Vec3::new(
+                    self.size.x() * (((i + 1) * span) as f64).min(cx) / cx,
+                    self.size.y() * hi,
+                    self.size.z() * (((j + 1) * span) as f64).min(cz) / cz,
+                )
+                + pad,
+        )
+    }
+
+    fn traverse(&self, level: usize, i: usize, j: usize, state: &mut Traversal) {
+        let r = state.r;
+        if !self.node_box(level, i, j).hit(r, state.t_min, state.t_max) {
+            return;
+        }
+
+        if level == 0 {
+            for crossing in self.cell_crossings(r, i, j) {
+                if crossing.t >= state.t_min && crossing.t <= state.t_max {
+                    state.t_max = crossing.t;
+                    state.closest = Some(crossing);
+                }
+            }
+            return;
+        }
+
+        // Visit children nearest the ray origin first so later ones are
+        // more likely to be culled by the closest hit so far.
+        let below = &self.levels[level - 1];
+        let xs = if r.direction().x() >= 0.0 {
+            [0, 1]
+        } else {
+            [1, 0]
+        };
+        let zs = if r.direction().z() >= 0.0 {
+            [0, 1]
+        } else {
+            [1, 0]
+        };
+        for dj in zs {
+            for di in xs {
+                let (ci, cj) = (2 * i + di, 2 * j + dj);
+                if ci < below.width && cj < below.depth {
+                    self.traverse(level - 1, ci, cj, state);
+                }
+            }
+        }
+    }
+
+    /// Crossings of the ray with the two triangles of cell `(i, j)`.
+    fn cell_crossings(&self, r: &Ray, i: usize, j: usize) -> Vec<Crossing> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut crossings = vec![];
+        for tri in [[0, 1, 2], [0, 2, 3]] {
+            let idx = tri.map(|k| corners[k]);
+            let p = idx.map(|(a, b)| self.vertex(a, b));
+            if let Some((t, b1, b2)) = intersect_triangle(r, &p[0], &p[1], &p[2]) {
+                let b0 = 1.0 - b1 - b2;
+                let n = idx.map(|(a, b)| self.normals[b * self.nx + a]);
+                let hit = b0 * p[0] + b1 * p[1] + b2 * p[2] - self.origin;
+                // u and v follow x and z, so the tangents run along the
+                // triangle's slope in those directions.
+                let (dpdu, dpdv) = triangle_tangents(&p, &idx.map(|(a, b)| (a as f64, b as f64)));
+                crossings.push(Crossing {
+                    t,
+                    outward_normal: unit_vector(&(b0 * n[0] + b1 * n[1] + b2 * n[2])),
+                    u: hit.x() / self.size.x(),
+                    v: hit.z() / self.size.z(),
+                    dpdu: (self.nx - 1) as f64 * dpdu,
+                    dpdv: (self.nz - 1) as f64 * dpdv,
+                });
+            }
+        }
+        crossings
+    }
+}
+
+/// Derivatives of position over a triangle with respect to its texture
+/// coordinates `uv`.
+fn triangle_tangents(p: &[Point3; 3], uv: &[(f64, f64); 3]) -> (Vec3, Vec3) {
+    let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+    let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+    let det = du1 * dv2 - dv1 * du2;
+    let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+    ((dv2 * e1 - dv1 * e2) / det, (du1 * e2 - du2 * e1) / det)
+}
+
+/// Moller-Trumbore ray/triangle intersection, returning the ray parameter
+/// and the barycentric weights of `p1` and `p2`.
+fn intersect_triangle(r: &Ray, p0: &Point3, p1: &Point3, p2: &Point3) -> Option<(f64, f64, f64)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pvec = cross(r.direction(), &e2);
+    let det = dot(&e1, &pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = r.origin() - p0;
+    let b1 = dot(&tvec, &pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = cross(&tvec, &e1);
+    let b2 = dot(r.direction(), &qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    Some((dot(&e2, &qvec) * inv_det, b1, b2))
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        let mut state = Traversal {
+            r,
+            t_min,
+            t_max,
+            closest: None,
+        };
+        self.traverse(self.levels.len() - 1, 0, 0, &mut state);
+        match state.closest {
+            Some(crossing) => DidHit::Hit(crossing.to_record(r, self.material_id)),
+            None => DidHit::Miss,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let top = self.levels.len() - 1;
+        Some(self.node_box(top, 0, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, rand::Rand, scene::Scene, vec3::Color};
+
+    /// A bumpy 9 by 7 field over the square from 0 to 8 along x and z.
+    fn hills() -> Heightfield {
+        let (nx, nz) = (9, 7);
+        let heights = (0..nx * nz)
+            .map(|k| {
+                let (i, j) = ((k % nx) as f64, (k / nx) as f64);
+                0.5 + 0.5 * (0.9 * i).sin() * (0.7 * j).cos()
+            })
+            .collect();
+        Heightfield::new(
+            heights,
+            nx,
+            nz,
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(8.0, 2.0, 8.0),
+            0,
+        )
+        .unwrap()
+    }
+
+    /// The nearest crossing found by testing every cell.
+    fn brute_force(field: &Heightfield, r: &Ray) -> Option<f64> {
+        let mut nearest: Option<f64> = None;
+        for j in 0..field.nz - 1 {
+            for i in 0..field.nx - 1 {
+                for c in field.cell_crossings(r, i, j) {
+                    if c.t >= 0.001 && nearest.is_none_or(|t| c.t < t) {
+                        nearest = Some(c.t);
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    #[test]
+    fn flat_field_faces_up() {
+        let field = Heightfield::from_grayscale(
+            3,
+            3,
+            &[128; 9],
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 2.0),
+            0,
+        )
+        .unwrap();
+        let r = Ray::new(Point3::new(0.7, 5.0, 1.3), Vec3::new(0.0, -1.0, 0.0));
+        match field.hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                assert!((rec.p.y() - 128.0 / 255.0).abs() < 1e-9);
+                assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).near_zero());
+                assert!((rec.u - 0.35).abs() < 1e-9 && (rec.v - 0.65).abs() < 1e-9);
+            }
+            DidHit::Miss => panic!("missed the flat field"),
+        }
+        let outside = Ray::new(Point3::new(3.0, 5.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(matches!(
+            field.hit(&outside, 0.001, f64::INFINITY),
+            DidHit::Miss
+        ));
+
+        // Scenes only test objects whose bounds a ray enters.
+        let mut scene = Scene::new();
+        scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        scene.add_object(Box::new(field));
+        assert!(scene.hit_object(&r, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn traversal_matches_testing_every_cell() {
+        let field = hills();
+        let mut rand = Rand::new_with_seed(5);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let origin = Point3::new(
+                -2.0 + 12.0 * rand.random_double(),
+                3.0 + 2.0 * rand.random_double(),
+                -2.0 + 12.0 * rand.random_double(),
+            );
+            let target = Point3::new(
+                8.0 * rand.random_double(),
+                2.0 * rand.random_double(),
+                8.0 * rand.random_double(),
+            );
+            let r = Ray::new(origin, target - origin);
+            let traversed = match field.hit(&r, 0.001, f64::INFINITY) {
+                DidHit::Hit(rec) => Some(rec.t),
+                DidHit::Miss => None,
+            };
+            let expected = brute_force(&field, &r);
+            match (traversed, expected) {
+                (Some(a), Some(b)) => {
+                    assert!((a - b).abs() < 1e-9, "{} vs {}", a, b);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("traversal found {:?}, every cell {:?}", traversed, expected),
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn bounds_enclose_the_terrain() {
+        let field = hills();
+        let bounds = field.bounding_box().unwrap();
+        let (min, max) = (bounds.min(), bounds.max());
+        assert!(min.y() >= -1e-6 && max.y() <= 2.0 + 1e-6);
+        assert!(min.x().abs() < 1e-6 && (max.x() - 8.0).abs() < 1e-6);
+        assert!(min.z().abs() < 1e-6 && (max.z() - 8.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rejects_grids_that_do_not_fit() {
+        let new = |heights: Vec<f64>, nx, nz| {
+            Heightfield::new(
+                heights,
+                nx,
+                nz,
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 1.0),
+                0,
+            )
+        };
+        assert!(matches!(
+            new(vec![0.0; 3], 3, 1),
+            Err(HeightfieldError::TooSmall)
+        ));
+        assert!(matches!(
+            new(vec![0.0; 5], 2, 3),
+            Err(HeightfieldError::WrongLength)
+        ));
+        assert!(new(vec![0.0; 6], 2, 3).is_ok());
+    }
+}
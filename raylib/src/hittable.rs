@@ -1,10 +1,4 @@
-use std::sync::Arc;
-
-use crate::{
-    material::{Lambertian, Material},
-    scene::MaterialId,
-    vec3::Color,
-};
+use crate::{aabb::Aabb, scene::MaterialId};
 
 use super::{
     ray::Ray,
@@ -17,10 +11,17 @@ pub enum DidHit {
     Miss,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    /// Derivatives of `p` with respect to `u` and `v`, or zero where the
+    /// surface has no parameterization.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
     pub material_id: MaterialId,
 }
@@ -42,18 +43,105 @@ impl HitRecord {
     pub fn material_id(&self) -> MaterialId {
         self.material_id
     }
+}
 
-    pub fn default() -> HitRecord {
+impl Default for HitRecord {
+    fn default() -> Self {
         HitRecord {
             p: Point3::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             front_face: false,
             material_id: 0,
         }
     }
 }
 
+/// A span of a ray that lies inside a solid, bounded by the surface crossings
+/// where the ray enters and exits it.
+#[derive(Debug, Clone, Copy)]
+pub struct HitInterval {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit;
+
+    /// A box enclosing the object, or `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Every interval along the whole line of `r` that is inside the object,
+    /// sorted and non-overlapping. Objects that do not enclose a volume
+    /// return no intervals and cannot take part in CSG.
+    fn intervals(&self, _r: &Ray) -> Vec<HitInterval> {
+        vec![]
+    }
+}
+
+/// A candidate ray/surface crossing found by an analytic primitive, before
+/// it is checked against the ray's valid interval.
+pub(crate) struct Crossing {
+    pub t: f64,
+    pub outward_normal: Vec3,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+}
+
+impl Crossing {
+    pub fn to_record(&self, r: &Ray, material_id: MaterialId) -> HitRecord {
+        let mut rec = HitRecord {
+            p: r.at(self.t),
+            t: self.t,
+            u: self.u,
+            v: self.v,
+            dpdu: self.dpdu,
+            dpdv: self.dpdv,
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &self.outward_normal);
+        rec.set_material_id(material_id);
+        rec
+    }
+}
+
+/// The nearest of `crossings` within `t_min..t_max`.
+pub(crate) fn nearest_crossing(
+    r: &Ray,
+    crossings: &[Crossing],
+    t_min: f64,
+    t_max: f64,
+    material_id: MaterialId,
+) -> DidHit {
+    crossings
+        .iter()
+        .filter(|c| c.t >= t_min && c.t <= t_max)
+        .min_by(|a, b| a.t.total_cmp(&b.t))
+        .map_or(DidHit::Miss, |c| DidHit::Hit(c.to_record(r, material_id)))
+}
+
+/// The single inside interval of a closed convex solid, spanning its
+/// nearest and furthest crossings.
+pub(crate) fn convex_intervals(
+    r: &Ray,
+    crossings: &[Crossing],
+    material_id: MaterialId,
+) -> Vec<HitInterval> {
+    let enter = crossings.iter().min_by(|a, b| a.t.total_cmp(&b.t));
+    let exit = crossings.iter().max_by(|a, b| a.t.total_cmp(&b.t));
+    match (enter, exit) {
+        (Some(enter), Some(exit)) if exit.t > enter.t => vec![HitInterval {
+            enter: enter.to_record(r, material_id),
+            exit: exit.to_record(r, material_id),
+        }],
+        _ => vec![],
+    }
 }
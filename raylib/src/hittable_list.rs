@@ -1,7 +1,11 @@
-use crate::hittable::DidHit;
+use crate::{
+    aabb::Aabb,
+    csg::{combine, CsgOp},
+    hittable::DidHit,
+};
 
 use super::{
-    hittable::{HitRecord, Hittable},
+    hittable::{HitInterval, HitRecord, Hittable},
     ray::Ray,
 };
 
@@ -9,6 +13,12 @@ pub struct HittableList {
     objects: Vec<Box<dyn Hittable + Send + Sync>>,
 }
 
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HittableList {
     pub fn add(&mut self, object: Box<dyn Hittable + Send + Sync>) {
         self.objects.push(object)
@@ -30,13 +40,10 @@ impl Hittable for HittableList {
         let mut closest_so_far = t_max;
 
         for obj in &self.objects {
-            match obj.hit(r, t_min, closest_so_far) {
-                DidHit::Hit(rec) => {
-                    hit_anything = true;
-                    temp_rec = rec;
-                    closest_so_far = temp_rec.t;
-                }
-                _ => {}
+            if let DidHit::Hit(rec) = obj.hit(r, t_min, closest_so_far) {
+                hit_anything = true;
+                temp_rec = rec;
+                closest_so_far = temp_rec.t;
             }
         }
 
@@ -46,4 +53,25 @@ impl Hittable for HittableList {
             DidHit::Miss
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        surrounding_box(&self.objects)
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        self.objects.iter().fold(vec![], |acc, obj| {
+            combine(CsgOp::Union, &acc, &obj.intervals(r))
+        })
+    }
+}
+
+/// Bounds every object, or `None` if any of them is unbounded.
+pub(crate) fn surrounding_box(objects: &[Box<dyn Hittable + Send + Sync>]) -> Option<Aabb> {
+    objects.iter().try_fold(None, |acc: Option<Aabb>, obj| {
+        let bbox = obj.bounding_box()?;
+        Some(Some(match acc {
+            Some(acc) => acc.surrounding(&bbox),
+            None => bbox,
+        }))
+    })?
 }
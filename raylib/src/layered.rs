@@ -0,0 +1,249 @@
+use crate::{
+    fresnel::fresnel_dielectric,
+    hittable::HitRecord,
+    material::{shading_frame, InteriorEvent, Lobe, Material, ScatterRecord},
+    microfacet::{microfacet_reflection, microfacet_reflection_pdf, TrowbridgeReitz},
+    rand::Rand,
+    ray::Ray,
+    vec3::{dot, reflect, unit_vector, Color},
+    Vec3,
+};
+
+/// A dielectric coat over any other material, as on car paint or varnished
+/// wood. Each sample either reflects off the coat or passes through it to
+/// the base, in proportion to the coat's Fresnel reflectance, so bases that
+/// can only be sampled still work. Light reaching the base is dimmed by the
+/// Fresnel transmission in and out of the coat and by absorption inside it,
+/// but is not bent by refraction.
+pub struct Layered {
+    base: Box<dyn Material + Send + Sync>,
+    ior: f64,
+    distribution: TrowbridgeReitz,
+    /// Absorption coefficient of the coat per unit distance, per channel.
+    sigma_a: Color,
+    thickness: f64,
+}
+
+impl Layered {
+    pub fn new(base: Box<dyn Material + Send + Sync>, ior: f64, roughness: f64) -> Self {
+        Layered {
+            base,
+            ior: ior.max(1.0),
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            sigma_a: Color::new(0.0, 0.0, 0.0),
+            thickness: 0.0,
+        }
+    }
+
+    /// Tints the coat, absorbing `sigma_a` per unit distance through a coat
+    /// `thickness` thick.
+    pub fn set_absorption(&mut self, sigma_a: Color, thickness: f64) {
+        self.sigma_a = sigma_a;
+        self.thickness = thickness.max(0.0);
+    }
+
+    /// Chance of reflecting off the coat when seen from `cos_theta`.
+    fn reflectance(&self, cos_theta: f64) -> f64 {
+        fresnel_dielectric(cos_theta, self.ior)
+    }
+
+    /// Fraction of light leaving along `wi` that passes through the coat
+    /// from `wo` to the base and back, in the local shading frame.
+    fn transmittance(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let t = (1.0 - self.reflectance(wo.z())) * (1.0 - self.reflectance(wi.z().abs()));
+        // Path length through the coat along the refracted directions.
+        let inside = |cos: f64| (1.0 - (1.0 - cos * cos) / (self.ior * self.ior)).sqrt();
+        let depth = self.thickness * (1.0 / inside(wo.z()) + 1.0 / inside(wi.z().abs()));
+        let absorbed = |s: f64| (-s * depth).exp();
+        t * Color::new(
+            absorbed(self.sigma_a.x()),
+            absorbed(self.sigma_a.y()),
+            absorbed(self.sigma_a.z()),
+        )
+    }
+
+    /// Whether the coat is seen at all, rather than the base from behind.
+    fn coated(&self, rec: &HitRecord, wo: &Vec3) -> bool {
+        rec.front_face && wo.z() > 0.0
+    }
+
+    /// Combined throughput and density of a non-specular sample `wi`.
+    fn weigh(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Option<(Color, f64)> {
+        let pdf = self.pdf(r_in, rec, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let cos_theta = dot(&unit_vector(wi), &rec.normal).abs();
+        Some((self.eval(r_in, rec, wi) * cos_theta / pdf, pdf))
+    }
+}
+
+impl Material for Layered {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if !self.coated(rec, &wo) {
+            return self.base.sample(r_in, rec, rand);
+        }
+
+        let reflectance = self.reflectance(wo.z());
+        if rand.random_double() < reflectance {
+            if self.distribution.is_smooth() {
+                let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+                return Some(ScatterRecord {
+                    scattered: Ray::new(rec.p, frame.local(&wi)),
+                    attenuation: Color::new(1.0, 1.0, 1.0),
+                    pdf: 0.0,
+                    lobe: Lobe::Specular,
+                    transmission: false,
+                    dispersed: false,
+                });
+            }
+
+            let wm = self.distribution.sample_wm(&wo, rand);
+            let wi = reflect(&-wo, &wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let direction = frame.local(&wi);
+            let (attenuation, pdf) = self.weigh(r_in, rec, &direction)?;
+            return Some(ScatterRecord {
+                scattered: Ray::new(rec.p, direction),
+                attenuation,
+                pdf,
+                lobe: Lobe::Glossy,
+                transmission: false,
+                dispersed: false,
+            });
+        }
+
+        let srec = self.base.sample(r_in, rec, rand)?;
+        if srec.is_specular() {
+            let wi = frame.to_local(&unit_vector(srec.scattered.direction()));
+            return Some(ScatterRecord {
+                attenuation: srec.attenuation * self.transmittance(&wo, &wi) / (1.0 - reflectance),
+                ..srec
+            });
+        }
+
+        let (attenuation, pdf) = self.weigh(r_in, rec, srec.scattered.direction())?;
+        Some(ScatterRecord {
+            attenuation,
+            pdf,
+            ..srec
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if !self.coated(rec, &wo) {
+            return self.base.eval(r_in, rec, wi);
+        }
+        let local = frame.to_local(&unit_vector(wi));
+        let base = self.transmittance(&wo, &local) * self.base.eval(r_in, rec, wi);
+        if self.distribution.is_smooth() {
+            return base;
+        }
+        let coat = microfacet_reflection(&self.distribution, &wo, &local, |cos| {
+            let f = self.reflectance(cos);
+            Color::new(f, f, f)
+        });
+        coat + base
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if !self.coated(rec, &wo) {
+            return self.base.pdf(r_in, rec, wi);
+        }
+        let reflectance = self.reflectance(wo.z());
+        let base = (1.0 - reflectance) * self.base.pdf(r_in, rec, wi);
+        if self.distribution.is_smooth() {
+            return base;
+        }
+        let local = frame.to_local(&unit_vector(wi));
+        reflectance * microfacet_reflection_pdf(&self.distribution, &wo, &local) + base
+    }
+
+    fn sample_interior(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> InteriorEvent {
+        self.base.sample_interior(r_in, rec, rand)
+    }
+
+    fn is_cut_out(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.base.is_cut_out(r_in, rec)
+    }
+
+    fn shading_normal(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.base.shading_normal(r_in, rec)
+    }
+
+    fn non_specular_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let (_, wo) = shading_frame(r_in, rec);
+        let base = self.base.non_specular_probability(r_in, rec);
+        if !self.coated(rec, &wo) {
+            return base;
+        }
+        let reflectance = self.reflectance(wo.z());
+        if self.distribution.is_smooth() {
+            (1.0 - reflectance) * base
+        } else {
+            reflectance + (1.0 - reflectance) * base
+        }
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base.albedo(r_in, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{tests::*, Conductor, Lambertian};
+
+    fn lambertian() -> Box<dyn Material + Send + Sync> {
+        Box::new(Lambertian::new(Color::new(0.8, 0.4, 0.2)))
+    }
+
+    #[test]
+    fn coats_are_consistent() {
+        let mut tinted = Layered::new(lambertian(), 1.5, 0.3);
+        tinted.set_absorption(Color::new(0.0, 1.0, 2.0), 0.2);
+        let materials = [
+            Layered::new(lambertian(), 1.5, 0.0),
+            Layered::new(lambertian(), 1.5, 0.3),
+            tinted,
+            Layered::new(Box::new(Conductor::gold(0.5)), 1.5, 0.2),
+        ];
+        for material in materials {
+            for cos_theta in [0.3, 0.9] {
+                let (r_in, rec) = hit_at(cos_theta, true);
+                check_consistency(&material, &r_in, &rec);
+            }
+        }
+    }
+
+    #[test]
+    fn coat_dims_the_base() {
+        let (r_in, rec) = hit_at(1.0, true);
+        let wi = Vec3::new(0.0, 0.0, 1.0);
+        let base = Lambertian::new(Color::new(0.8, 0.4, 0.2)).eval(&r_in, &rec, &wi);
+        let coated = Layered::new(lambertian(), 1.5, 0.0).eval(&r_in, &rec, &wi);
+        // Four percent is reflected on the way in and again on the way out.
+        assert!((coated.x() - 0.96 * 0.96 * base.x()).abs() < 1e-12);
+
+        let mut tinted = Layered::new(lambertian(), 1.5, 0.0);
+        tinted.set_absorption(Color::new(1.0, 1.0, 1.0), 0.5);
+        // Straight down and back up through half a unit of coat.
+        let dimmed = tinted.eval(&r_in, &rec, &wi);
+        assert!((dimmed.x() - (-1.0f64).exp() * coated.x()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn back_faces_see_the_base() {
+        let material = Layered::new(lambertian(), 1.5, 0.3);
+        let (r_in, rec) = hit_at(0.7, false);
+        let wi = Vec3::new(0.3, 0.0, -0.9);
+        let base = Lambertian::new(Color::new(0.8, 0.4, 0.2));
+        assert_eq!(material.pdf(&r_in, &rec, &wi), base.pdf(&r_in, &rec, &wi));
+    }
+}
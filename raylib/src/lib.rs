@@ -1,20 +1,92 @@
+mod aabb;
+mod alpha;
+mod aov;
+mod background;
+mod bdpt;
+mod bump;
 mod camera;
+mod capsule;
+mod cone;
+mod csg;
+mod cylinder;
+mod debug;
+mod denoise;
+mod density;
+mod distribution;
+mod film;
+mod fresnel;
+mod heightfield;
 mod hittable;
 mod hittable_list;
 mod image;
+mod layered;
+mod light;
 mod material;
+mod medium;
+mod microfacet;
+mod mlt;
+mod onb;
+mod perlin;
+mod photon;
+mod poly;
+mod principled;
 mod rand;
 mod ray;
 mod raytracer;
 mod scene;
+mod sdf;
+mod sky;
+mod spectrum;
 mod sphere;
+mod subsurface;
+mod texture;
+mod thin_film;
+mod torus;
 mod util;
 mod vec3;
 
+pub use aabb::Aabb;
+pub use alpha::{AlphaMask, AlphaMode};
+pub use aov::Aov;
+pub use background::{Background, BackgroundSample, EnvironmentMap, SkyGradient};
+pub use bump::{BumpMap, NormalMap};
+pub use capsule::Capsule;
+pub use cone::Cone;
+pub use csg::{Csg, CsgOp};
+pub use cylinder::Cylinder;
+pub use denoise::Denoiser;
+pub use density::{ConstantDensity, DensityField, GridError, NoiseDensity, VoxelGrid};
+pub use heightfield::{Heightfield, HeightfieldError};
+pub use hittable::{DidHit, HitInterval, HitRecord, Hittable};
+pub use hittable_list::HittableList;
 pub use image::Image;
+pub use layered::Layered;
+pub use light::{
+    BoundingSphere, DirectionalLight, EmissionSample, IesError, Light, LightSample,
+    PhotometricProfile, PointLight, SpotLight,
+};
+pub use material::{
+    Conductor, Dielectric, InteriorEvent, Lambertian, Lobe, Material, Metal, RoughDielectric,
+    ScatterRecord,
+};
+pub use medium::{HenyeyGreenstein, HeterogeneousMedium, Medium, MediumEvent, MediumSample};
+pub use principled::Principled;
 pub use rand::Rand;
-pub use raytracer::{random_scene, Raytracer, RaytracerOptions};
-pub use vec3::Vec3;
+pub use ray::Ray;
+pub use raytracer::{random_scene, Integrator, Raytracer, RaytracerOptions};
+pub use scene::{MaterialId, Scene};
+pub use sdf::{
+    BoxedSdf, Repeat, Sdf, SdfBox, SdfCapsule, SdfObject, SdfRoundedBox, SdfSphere, SdfTorus,
+    SmoothSubtraction, SmoothUnion, Translate, Twist,
+};
+pub use sky::PhysicalSky;
+pub use spectrum::{Dispersion, SampledSpectrum, SampledWavelengths};
+pub use sphere::Sphere;
+pub use subsurface::Subsurface;
+pub use texture::{CheckerTexture, ImageTexture, SolidColor, Texture, TextureError, TextureRef};
+pub use thin_film::ThinFilm;
+pub use torus::Torus;
+pub use vec3::{Color, Point3, Vec3};
 
 pub fn hello_raylib() {
     log::info!("hello from raylib");
@@ -0,0 +1,551 @@
+use std::fmt;
+
+use crate::{
+    onb::Onb,
+    rand::Rand,
+    ray::Ray,
+    util::{degrees_to_radians, PI},
+    vec3::{dot, random_cone_direction, unit_vector, Color, Point3},
+    Vec3,
+};
+
+/// Light arriving at a point from a sampled position on a light.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Unit vector from the point towards the light.
+    pub direction: Vec3,
+    /// Distance to the light along `direction`, infinite for lights that
+    /// are infinitely far away.
+    pub distance: f64,
+    /// Incident radiance divided by the density it was sampled with.
+    pub radiance: Color,
+}
+
+/// A ray of light leaving a light, to follow light paths from.
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample {
+    /// The ray, with a unit direction.
+    pub ray: Ray,
+    /// Radiance carried along the ray, or intensity for lights at a point.
+    pub radiance: Color,
+    /// Area density of the ray's origin, or 1 for a point.
+    pub pdf_position: f64,
+    /// Solid angle density of the ray's direction, or 1 for a single
+    /// direction.
+    pub pdf_direction: f64,
+}
+
+/// A sphere enclosing the scene, for lights far away to aim at.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingSphere {
+    pub centre: Point3,
+    pub radius: f64,
+}
+
+/// A light that is not part of the scene's geometry, so rays never hit it
+/// and it is only found through shadow rays.
+pub trait Light {
+    /// Samples the light as seen from `p`, or `None` if it does not light
+    /// `p` at all.
+    fn sample(&self, p: &Point3, rand: &mut Rand) -> Option<LightSample>;
+
+    /// Samples a ray leaving the light towards the scene within `bounds`.
+    fn sample_emission(&self, bounds: &BoundingSphere, rand: &mut Rand) -> Option<EmissionSample>;
+
+    /// Solid angle density with which `sample_emission` sends light along
+    /// the unit vector `direction`, or 0 if it only has one direction.
+    fn pdf_emission(&self, direction: &Vec3) -> f64;
+
+    /// Whether all the light travels along one direction from infinitely
+    /// far away.
+    fn is_directional(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub enum IesError {
+    /// The file ended before all the photometric data was read.
+    Truncated,
+    /// The file has no `TILT=` line.
+    MissingTilt,
+    /// Tilt data is only supported as `TILT=NONE`.
+    UnsupportedTilt,
+    /// A value could not be read as a number.
+    InvalidNumber(String),
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IesError::Truncated => write!(f, "IES data is truncated"),
+            IesError::MissingTilt => write!(f, "IES data has no TILT line"),
+            IesError::UnsupportedTilt => write!(f, "IES tilt data is not supported"),
+            IesError::InvalidNumber(s) => write!(f, "invalid number in IES data: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for IesError {}
+
+/// How a light's intensity varies with the angle from its axis, as
+/// measured for real fixtures. Rotational symmetry about the axis is
+/// assumed.
+#[derive(Debug, Clone)]
+pub struct PhotometricProfile {
+    /// Angles from the axis in radians, ascending.
+    angles: Vec<f64>,
+    /// Relative intensity at each angle, peaking at 1.
+    values: Vec<f64>,
+}
+
+impl PhotometricProfile {
+    /// A profile through the points `(angle in degrees, intensity)`,
+    /// interpolated linearly and normalized to a peak of 1.
+    pub fn new(points: &[(f64, f64)]) -> Self {
+        let mut points = points.to_vec();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let peak = points.iter().fold(0.0f64, |m, p| m.max(p.1));
+        let scale = if peak > 0.0 { 1.0 / peak } else { 0.0 };
+        PhotometricProfile {
+            angles: points.iter().map(|p| degrees_to_radians(p.0)).collect(),
+            values: points.iter().map(|p| (scale * p.1).max(0.0)).collect(),
+        }
+    }
+
+    /// Parses IESNA LM-63 photometric data with `TILT=NONE`, averaging over
+    /// the horizontal angles. Vertical angles are measured from the
+    /// fixture's axis, which points down in the file's convention.
+    pub fn from_ies(text: &str) -> Result<Self, IesError> {
+        let mut lines = text.lines();
+        let tilt = lines
+            .find(|line| line.trim_start().starts_with("TILT="))
+            .ok_or(IesError::MissingTilt)?;
+        if tilt.trim() != "TILT=NONE" {
+            return Err(IesError::UnsupportedTilt);
+        }
+
+        let mut numbers =
+            lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','));
+        let mut next = || -> Result<f64, IesError> {
+            let word = numbers
+                .by_ref()
+                .find(|w| !w.is_empty())
+                .ok_or(IesError::Truncated)?;
+            word.parse()
+                .map_err(|_| IesError::InvalidNumber(word.to_string()))
+        };
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical = next()? as usize;
+        let horizontal = next()? as usize;
+        // Photometric type, units, dimensions, ballast factor, file
+        // generation type and input watts.
+        for _ in 0..8 {
+            next()?;
+        }
+
+        let angles = (0..vertical)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        for _ in 0..horizontal {
+            next()?;
+        }
+        let mut values = vec![0.0; vertical];
+        for _ in 0..horizontal {
+            for v in values.iter_mut() {
+                *v += multiplier * next()? / horizontal as f64;
+            }
+        }
+
+        let points: Vec<(f64, f64)> = angles.into_iter().zip(values).collect();
+        Ok(PhotometricProfile::new(&points))
+    }
+
+    /// Relative intensity at `cos_theta` from the axis.
+    fn value(&self, cos_theta: f64) -> f64 {
+        let theta = cos_theta.clamp(-1.0, 1.0).acos();
+        let i = self.angles.partition_point(|&a| a <= theta);
+        if i == 0 {
+            return self.values.first().copied().unwrap_or(0.0);
+        }
+        if i == self.angles.len() {
+            return self.values[i - 1];
+        }
+        let (a0, a1) = (self.angles[i - 1], self.angles[i]);
+        let t = (theta - a0) / (a1 - a0);
+        (1.0 - t) * self.values[i - 1] + t * self.values[i]
+    }
+}
+
+/// Light radiating from a point, or from a small sphere for soft shadows,
+/// optionally shaped by a photometric profile.
+pub struct PointLight {
+    position: Point3,
+    /// Radiant intensity along the peak of the profile.
+    intensity: Color,
+    radius: f64,
+    profile: Option<(Vec3, PhotometricProfile)>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        PointLight {
+            position,
+            intensity,
+            radius: 0.0,
+            profile: None,
+        }
+    }
+
+    /// Spreads the light over a sphere of `radius`, which softens its
+    /// shadows without changing its intensity.
+    pub fn set_radius(&mut self, radius: f64) {
+        self.radius = radius.max(0.0)
+    }
+
+    /// Shapes the light by `profile` about the `axis` it points along.
+    pub fn set_profile(&mut self, axis: Vec3, profile: PhotometricProfile) {
+        self.profile = Some((unit_vector(&axis), profile))
+    }
+
+    fn intensity_towards(&self, direction: &Vec3) -> Color {
+        match &self.profile {
+            Some((axis, profile)) => profile.value(dot(axis, direction)) * self.intensity,
+            None => self.intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Point3, rand: &mut Rand) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        if distance <= self.radius {
+            return None;
+        }
+        let towards_centre = to_light / distance;
+
+        if self.radius == 0.0 {
+            return Some(LightSample {
+                direction: towards_centre,
+                distance,
+                radiance: self.intensity_towards(&-towards_centre) / distance_squared,
+            });
+        }
+
+        // Uniformly within the cone subtended by the sphere, whose radiance
+        // gives it the same intensity as the point.
+        let sin_max = self.radius / distance;
+        let cos_max = (1.0 - sin_max * sin_max).max(0.0).sqrt();
+        let local = random_cone_direction(cos_max, rand);
+        let (cos_theta, sin_theta) = (local.z(), (1.0 - local.z() * local.z()).max(0.0).sqrt());
+        let direction = Onb::build_from_w(&towards_centre).local(&local);
+
+        // Distance to the near side of the sphere along the direction.
+        let along = distance * cos_theta;
+        let off_axis = distance * sin_theta;
+        let distance = along
+            - (self.radius * self.radius - off_axis * off_axis)
+                .max(0.0)
+                .sqrt();
+
+        let radiance = self.intensity_towards(&-towards_centre) / (PI * self.radius * self.radius);
+        let pdf = 1.0 / (2.0 * PI * (1.0 - cos_max));
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: radiance / pdf,
+        })
+    }
+
+    fn sample_emission(&self, _bounds: &BoundingSphere, rand: &mut Rand) -> Option<EmissionSample> {
+        // Light leaves a sphere from the point on it facing its direction,
+        // which is otherwise treated as leaving the centre.
+        let direction = random_cone_direction(-1.0, rand);
+        Some(EmissionSample {
+            ray: Ray::new(self.position + self.radius * direction, direction),
+            radiance: self.intensity_towards(&direction),
+            pdf_position: 1.0,
+            pdf_direction: self.pdf_emission(&direction),
+        })
+    }
+
+    fn pdf_emission(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// A point light shining into a cone, fading out towards its edge.
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    /// Cosine of the angle where the light starts to fade.
+    cos_falloff_start: f64,
+    /// Cosine of the angle of the edge of the cone.
+    cos_total: f64,
+}
+
+impl SpotLight {
+    /// A spotlight at `position` pointing along `direction`, at full
+    /// `intensity` within `falloff_start` degrees of its axis and dark
+    /// beyond `cone_angle` degrees.
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        cone_angle: f64,
+        falloff_start: f64,
+    ) -> Self {
+        let cone_angle = cone_angle.clamp(0.0, 180.0);
+        SpotLight {
+            position,
+            direction: unit_vector(&direction),
+            intensity,
+            cos_falloff_start: degrees_to_radians(falloff_start.min(cone_angle)).cos(),
+            cos_total: degrees_to_radians(cone_angle).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_total {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_total) / (self.cos_falloff_start - self.cos_total);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Point3, _rand: &mut Rand) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let falloff = self.falloff(dot(&-direction, &self.direction));
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: (falloff / distance_squared) * self.intensity,
+        })
+    }
+
+    fn sample_emission(&self, _bounds: &BoundingSphere, rand: &mut Rand) -> Option<EmissionSample> {
+        let local = random_cone_direction(self.cos_total, rand);
+        let direction = Onb::build_from_w(&self.direction).local(&local);
+        Some(EmissionSample {
+            ray: Ray::new(self.position, direction),
+            radiance: self.falloff(local.z()) * self.intensity,
+            pdf_position: 1.0,
+            pdf_direction: self.pdf_emission(&direction),
+        })
+    }
+
+    fn pdf_emission(&self, direction: &Vec3) -> f64 {
+        if dot(direction, &self.direction) < self.cos_total {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - self.cos_total))
+    }
+}
+
+/// Parallel light from infinitely far away, like the sun.
+pub struct DirectionalLight {
+    /// Unit vector the light travels along.
+    direction: Vec3,
+    /// Irradiance of a surface facing the light.
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        DirectionalLight {
+            direction: unit_vector(&direction),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Point3, _rand: &mut Rand) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+        })
+    }
+
+    fn sample_emission(&self, bounds: &BoundingSphere, rand: &mut Rand) -> Option<EmissionSample> {
+        // From a disk facing the light just outside the scene, as wide as
+        // the scene.
+        let frame = Onb::build_from_w(&self.direction);
+        let disk = bounds.radius * Vec3::random_in_unit_disk(rand);
+        let origin = bounds.centre + frame.local(&Vec3::new(disk.x(), disk.y(), -bounds.radius));
+        Some(EmissionSample {
+            ray: Ray::new(origin, self.direction),
+            radiance: self.irradiance,
+            pdf_position: 1.0 / (PI * bounds.radius * bounds.radius),
+            pdf_direction: 1.0,
+        })
+    }
+
+    fn pdf_emission(&self, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn is_directional(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fixture measured at three vertical and two horizontal angles,
+    /// brightest straight down its axis.
+    const IES: &str = "IESNA:LM-63-2002
+[TEST] sample
+[MANUFAC] nobody
+TILT=NONE
+1 1000 2.0 3 2 1 2 0.5 0.5 0.2
+1.0 1.0 100
+0 45 90
+0, 180
+100 50 0
+80 30 0
+";
+
+    #[test]
+    fn parses_ies_and_averages_horizontal_angles() {
+        let profile = PhotometricProfile::from_ies(IES).unwrap();
+        // Averaged over the two planes the intensities are 90, 40 and 0,
+        // normalized to the peak.
+        assert!((profile.value(1.0) - 1.0).abs() < 1e-12);
+        let cos_45 = degrees_to_radians(45.0).cos();
+        assert!((profile.value(cos_45) - 40.0 / 90.0).abs() < 1e-12);
+        assert!(profile.value(0.0).abs() < 1e-12);
+        // Halfway between 0 and 45 degrees.
+        let cos_22 = degrees_to_radians(22.5).cos();
+        assert!((profile.value(cos_22) - 0.5 * (1.0 + 40.0 / 90.0)).abs() < 1e-12);
+        // Past the last angle the last value holds.
+        assert!(profile.value(-1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_bad_ies() {
+        assert!(matches!(
+            PhotometricProfile::from_ies("IESNA:LM-63-2002\n1 2 3"),
+            Err(IesError::MissingTilt)
+        ));
+        assert!(matches!(
+            PhotometricProfile::from_ies("TILT=INCLUDE\n1 2 3"),
+            Err(IesError::UnsupportedTilt)
+        ));
+        let truncated = &IES[..IES.len() - 8];
+        assert!(matches!(
+            PhotometricProfile::from_ies(truncated),
+            Err(IesError::Truncated)
+        ));
+        let garbled = IES.replace("100 50", "100 fifty");
+        match PhotometricProfile::from_ies(&garbled) {
+            Err(IesError::InvalidNumber(word)) => assert_eq!(word, "fifty"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn point_light_falls_off_with_distance() {
+        let light = PointLight::new(Point3::new(0.0, 2.0, 0.0), Color::new(8.0, 8.0, 8.0));
+        let mut rand = Rand::new_with_seed(1);
+        let sample = light
+            .sample(&Point3::new(0.0, 0.0, 0.0), &mut rand)
+            .unwrap();
+        assert!((sample.direction - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert_eq!(sample.distance, 2.0);
+        assert!((sample.radiance.x() - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spherical_light_matches_a_point() {
+        let mut light = PointLight::new(Point3::new(0.0, 2.0, 0.0), Color::new(8.0, 8.0, 8.0));
+        light.set_radius(0.1);
+        let mut rand = Rand::new_with_seed(2);
+        let n = 1000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let sample = light
+                .sample(&Point3::new(0.0, 0.0, 0.0), &mut rand)
+                .unwrap();
+            assert!(sample.distance >= 1.9 - 1e-9 && sample.distance < 2.0);
+            total += sample.radiance.x();
+        }
+        assert!((total / n as f64 - 2.0).abs() < 0.01);
+        // Points inside the sphere are not lit.
+        assert!(light
+            .sample(&Point3::new(0.0, 1.95, 0.0), &mut rand)
+            .is_none());
+    }
+
+    #[test]
+    fn profile_shapes_a_point_light() {
+        let mut light = PointLight::new(Point3::new(0.0, 1.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        light.set_profile(
+            Vec3::new(0.0, -1.0, 0.0),
+            PhotometricProfile::new(&[(0.0, 2.0), (90.0, 0.0)]),
+        );
+        let mut rand = Rand::new_with_seed(3);
+        let below = light
+            .sample(&Point3::new(0.0, 0.0, 0.0), &mut rand)
+            .unwrap();
+        assert!((below.radiance.x() - 1.0).abs() < 1e-12);
+        let beside = light
+            .sample(&Point3::new(1.0, 1.0, 0.0), &mut rand)
+            .unwrap();
+        assert!(beside.radiance.x().abs() < 1e-12);
+    }
+
+    #[test]
+    fn spot_light_fades_to_its_edge() {
+        let light = SpotLight::new(
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            45.0,
+            30.0,
+        );
+        let mut rand = Rand::new_with_seed(4);
+        let at = |x: f64, rand: &mut Rand| {
+            light
+                .sample(&Point3::new(x, 0.0, 0.0), rand)
+                .map(|s| s.radiance.x() * (1.0 + x * x))
+        };
+        assert_eq!(at(0.0, &mut rand), Some(1.0));
+        assert_eq!(at(0.5, &mut rand), Some(1.0));
+        let fading = at(0.8, &mut rand).unwrap();
+        assert!(fading > 0.0 && fading < 1.0);
+        assert_eq!(at(1.5, &mut rand), None);
+    }
+
+    #[test]
+    fn directional_light_is_everywhere() {
+        let light = DirectionalLight::new(Vec3::new(0.0, -2.0, 0.0), Color::new(3.0, 3.0, 3.0));
+        let mut rand = Rand::new_with_seed(5);
+        let sample = light
+            .sample(&Point3::new(5.0, -7.0, 1.0), &mut rand)
+            .unwrap();
+        assert!((sample.direction - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert_eq!(sample.distance, f64::INFINITY);
+        assert_eq!(sample.radiance.x(), 3.0);
+    }
+}
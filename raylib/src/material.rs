@@ -1,14 +1,127 @@
 use crate::{
+    fresnel::{fresnel_conductor, fresnel_dielectric},
     hittable::HitRecord,
+    microfacet::{
+        microfacet_dielectric, microfacet_dielectric_pdf, microfacet_reflection,
+        microfacet_reflection_pdf, TrowbridgeReitz,
+    },
+    onb::Onb,
     rand::Rand,
     ray::Ray,
-    util::clamp,
+    spectrum::{Dispersion, D_LINE},
+    thin_film::ThinFilm,
+    util::{clamp, PI},
     vec3::{dot, random_unit_vector, reflect, refract, unit_vector, Color},
     Vec3,
 };
 
+/// The kind of lobe a scattered direction was drawn from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lobe {
+    /// Broad, roughly cosine distributed scattering.
+    Diffuse,
+    /// Rough reflection or transmission with a finite density.
+    Glossy,
+    /// A perfect mirror or refraction, or any lobe whose density cannot be
+    /// evaluated. `eval` and `pdf` ignore specular lobes, so integrators
+    /// can only follow them by sampling.
+    Specular,
+}
+
+/// A direction sampled from a material's BSDF.
+#[derive(Debug, Clone, Copy)]
+pub struct ScatterRecord {
+    pub scattered: Ray,
+    /// BSDF times the cosine to the normal over the pdf, the factor by
+    /// which the path throughput changes.
+    pub attenuation: Color,
+    /// Solid angle density of the sampled direction, or zero for specular
+    /// lobes.
+    pub pdf: f64,
+    pub lobe: Lobe,
+    /// Whether the direction passes through the surface.
+    pub transmission: bool,
+    /// Whether the direction depends on the ray's wavelength, so it is only
+    /// valid for the hero wavelength of a spectral path.
+    pub dispersed: bool,
+}
+
+/// What becomes of light travelling through the inside of a material
+/// towards the surface it hit from behind.
+pub enum InteriorEvent {
+    /// The light scattered inside and continues along `scattered`.
+    Scatter { attenuation: Color, scattered: Ray },
+    /// The light reached the surface.
+    Reached { attenuation: Color },
+}
+
+impl ScatterRecord {
+    pub fn is_specular(&self) -> bool {
+        self.lobe == Lobe::Specular
+    }
+}
+
+/// A BSDF. Directions are in world space; `r_in` is the ray that arrived
+/// at the hit and `wi` is a direction light is scattered into.
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, rand: &mut Rand) -> (bool, Color, Ray);
+    /// Samples a direction to continue the path along, or `None` if the
+    /// light was absorbed.
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord>;
+
+    /// BSDF value for scattering from `r_in` into `wi`, excluding specular
+    /// lobes. Purely specular materials can keep the default.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Density with which `sample` produces `wi`, excluding specular lobes.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Samples the inside of a material that scatters light beneath its
+    /// surface, for a ray that reached `rec`. Integrators call this before
+    /// shading the hit and keep walking while light scatters inside.
+    fn sample_interior(&self, _r_in: &Ray, _rec: &HitRecord, _rand: &mut Rand) -> InteriorEvent {
+        InteriorEvent::Reached {
+            attenuation: Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Whether the surface is cut away at `rec`, so the ray should ignore
+    /// this hit and continue to the next surface.
+    fn is_cut_out(&self, _r_in: &Ray, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    /// The normal the material shades `rec` with, facing back along
+    /// `r_in`. Materials that perturb the normal override this.
+    fn shading_normal(&self, _r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        rec.normal
+    }
+
+    /// Chance of `sample` picking a lobe that is not specular for `r_in`.
+    /// It is only asked for once such a lobe was picked, so materials that
+    /// do not mix specular and other lobes can keep the default.
+    fn non_specular_probability(&self, _r_in: &Ray, _rec: &HitRecord) -> f64 {
+        1.0
+    }
+
+    /// The color of the surface at `rec` as seen along `r_in`, without the
+    /// noise of sampling, for the albedo AOV that guides denoising.
+    /// Materials without a color of their own, like clear glass, keep the
+    /// default of white.
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+}
+
+/// A frame around the shading normal, and the direction back along `r_in`
+/// expressed in it.
+pub(crate) fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let frame = Onb::build_from_w(&rec.normal);
+    let wo = frame.to_local(&-unit_vector(r_in.direction()));
+    (frame, wo)
 }
 
 pub struct Lambertian {
@@ -22,7 +135,7 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> (bool, Color, Ray) {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
         let mut scatter_direction = rec.normal + random_unit_vector(rand);
 
         // Catch degenerate scatter direction
@@ -30,9 +143,30 @@ impl Material for Lambertian {
             scatter_direction = rec.normal;
         }
 
-        let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.albedo;
-        (true, attenuation, scattered)
+        Some(ScatterRecord {
+            scattered: Ray::new(rec.p, scatter_direction),
+            attenuation: self.albedo,
+            pdf: self.pdf(r_in, rec, &scatter_direction),
+            lobe: Lobe::Diffuse,
+            transmission: false,
+            dispersed: false,
+        })
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        if dot(wi, &rec.normal) <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo / PI
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        // Offsetting the normal by a random unit vector is cosine weighted.
+        dot(&unit_vector(wi), &rec.normal).max(0.0) / PI
+    }
+
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        self.albedo
     }
 }
 
@@ -50,18 +184,215 @@ impl Metal {
     }
 }
 
+/// The fuzzed reflection has no closed-form density, so every `Metal`
+/// lobe is reported as specular.
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> (bool, Color, Ray) {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
         let reflected = reflect(&unit_vector(r_in.direction()), &rec.normal);
         let scattered = Ray::new(
             rec.p,
             reflected + self.fuzz * Vec3::random_in_unit_sphere(rand),
         );
-        let attenuation = self.albedo;
-        (
-            dot(scattered.direction(), &rec.normal) > 0.0,
-            attenuation,
+        if dot(scattered.direction(), &rec.normal) <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
             scattered,
+            attenuation: self.albedo,
+            pdf: 0.0,
+            lobe: Lobe::Specular,
+            transmission: false,
+            dispersed: false,
+        })
+    }
+
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+/// Rough metal modelled with a GGX microfacet distribution and the exact
+/// Fresnel equations for a complex index of refraction `eta + ik`, given
+/// per RGB channel.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: TrowbridgeReitz,
+    thin_film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            thin_film: None,
+        }
+    }
+
+    /// Coats the metal with a thin film, as for oxidized or heat tinted
+    /// metals.
+    pub fn set_thin_film(&mut self, thin_film: ThinFilm) {
+        self.thin_film = Some(thin_film)
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Conductor::new(
+            Color::new(0.143119, 0.374957, 1.44248),
+            Color::new(3.98316, 2.38572, 1.60322),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Conductor::new(
+            Color::new(0.200438, 0.924033, 1.10221),
+            Color::new(3.91295, 2.45285, 2.14219),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Conductor::new(
+            Color::new(1.65746, 0.880369, 0.521229),
+            Color::new(9.22387, 6.26952, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Self {
+        Conductor::new(
+            Color::new(0.155265, 0.116723, 0.138342),
+            Color::new(4.82835, 3.12225, 2.14696),
+            roughness,
+        )
+    }
+
+    pub fn iron(roughness: f64) -> Self {
+        Conductor::new(
+            Color::new(2.91140, 2.94970, 2.58450),
+            Color::new(3.0893, 2.9318, 2.7670),
+            roughness,
+        )
+    }
+}
+
+impl Conductor {
+    fn fresnel(&self, r_in: &Ray, rec: &HitRecord, cos_theta: f64) -> Color {
+        match &self.thin_film {
+            Some(film) => {
+                film.reflectance(rec, cos_theta, 1.0, &self.eta, &self.k, r_in.wavelength())
+            }
+            None => fresnel_conductor(cos_theta, &self.eta, &self.k),
+        }
+    }
+
+    /// Whether reflectance is evaluated at the hero wavelength alone.
+    fn dispersed(&self, r_in: &Ray) -> bool {
+        self.thin_film.is_some() && r_in.wavelength().is_some()
+    }
+}
+
+impl Material for Conductor {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            return Some(ScatterRecord {
+                scattered: Ray::new(rec.p, frame.local(&wi)),
+                attenuation: self.fresnel(r_in, rec, wo.z()),
+                pdf: 0.0,
+                lobe: Lobe::Specular,
+                transmission: false,
+                dispersed: self.dispersed(r_in),
+            });
+        }
+
+        // With visible-normal sampling the distribution term and most of the
+        // masking cancel out of the estimator, leaving F * G2 / G1.
+        let wm = self.distribution.sample_wm(&wo, rand);
+        let wi = reflect(&-wo, &wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            scattered: Ray::new(rec.p, frame.local(&wi)),
+            attenuation: self.fresnel(r_in, rec, dot(&wo, &wm))
+                * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo)),
+            pdf: microfacet_reflection_pdf(&self.distribution, &wo, &wi),
+            lobe: Lobe::Glossy,
+            transmission: false,
+            dispersed: self.dispersed(r_in),
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let (frame, wo) = shading_frame(r_in, rec);
+        let wi = frame.to_local(&unit_vector(wi));
+        microfacet_reflection(&self.distribution, &wo, &wi, |cos| {
+            self.fresnel(r_in, rec, cos)
+        })
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = shading_frame(r_in, rec);
+        microfacet_reflection_pdf(&self.distribution, &wo, &frame.to_local(&unit_vector(wi)))
+    }
+
+    fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let (_, wo) = shading_frame(r_in, rec);
+        self.fresnel(r_in, rec, wo.z().clamp(0.0, 1.0))
+    }
+}
+
+/// Beer-Lambert absorption of light travelling through the inside of a
+/// closed dielectric object.
+#[derive(Debug, Copy, Clone)]
+struct Absorption {
+    /// Absorption coefficient per unit distance, per channel.
+    sigma_a: Color,
+}
+
+impl Absorption {
+    fn none() -> Self {
+        Absorption {
+            sigma_a: Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Absorption that tints white light to `color` after travelling
+    /// `distance` through the medium. Distances that are not positive
+    /// would give infinite or negative coefficients, so are clamped.
+    fn from_color(color: Color, distance: f64) -> Self {
+        let distance = distance.max(1e-6);
+        let sigma = |c: f64| -c.clamp(1e-6, 1.0).ln() / distance;
+        Absorption {
+            sigma_a: Color::new(sigma(color.x()), sigma(color.y()), sigma(color.z())),
+        }
+    }
+
+    /// Transmittance along `r_in` up to `rec`. Only rays that hit the back
+    /// of a surface have been travelling inside the medium.
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let distance = rec.t * r_in.direction().length();
+        Color::new(
+            (-self.sigma_a.x() * distance).exp(),
+            (-self.sigma_a.y() * distance).exp(),
+            (-self.sigma_a.z() * distance).exp(),
         )
     }
 }
@@ -69,11 +400,48 @@ impl Material for Metal {
 pub struct Dielectric {
     /// Index of refraction
     ir: f64,
+    dispersion: Option<Dispersion>,
+    absorption: Absorption,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Self {
-        Dielectric { ir }
+        Dielectric {
+            ir,
+            dispersion: None,
+            absorption: Absorption::none(),
+            thin_film: None,
+        }
+    }
+
+    /// Tints light inside the material so that white light becomes `color`
+    /// after travelling `distance`, for colored glass and liquids.
+    pub fn set_absorption(&mut self, color: Color, distance: f64) {
+        self.absorption = Absorption::from_color(color, distance)
+    }
+
+    /// Varies the index of refraction with wavelength, splitting white
+    /// light into colors in spectral mode. RGB rendering uses the index at
+    /// the d-line.
+    pub fn set_dispersion(&mut self, dispersion: Dispersion) {
+        self.ir = dispersion.ior(D_LINE);
+        self.dispersion = Some(dispersion)
+    }
+
+    /// Coats the surface with a thin film. An index of refraction of 1.0
+    /// with a film of water makes a soap bubble.
+    pub fn set_thin_film(&mut self, thin_film: ThinFilm) {
+        self.thin_film = Some(thin_film)
+    }
+}
+
+/// Index of refraction seen by `r_in`, and whether it depended on the
+/// ray's wavelength.
+fn ior_for(ir: f64, dispersion: &Option<Dispersion>, r_in: &Ray) -> (f64, bool) {
+    match (dispersion, r_in.wavelength()) {
+        (Some(dispersion), Some(wavelength)) => (dispersion.ior(wavelength), true),
+        _ => (ir, false),
     }
 }
 
@@ -85,13 +453,10 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> (bool, Color, Ray) {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        let attenuation = self.absorption.transmittance(r_in, rec);
+        let (ir, dispersed) = ior_for(self.ir, &self.dispersion, r_in);
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
 
         let unit_direction = unit_vector(r_in.direction());
         let cos_theta = dot(&-unit_direction, &rec.normal).min(1.0);
@@ -99,15 +464,368 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
-        let direction =
-            if cannot_refract || reflectance(cos_theta, refraction_ratio) > rand.random_double() {
-                reflect(&unit_direction, &rec.normal)
-            } else {
-                refract(&unit_direction, &rec.normal, refraction_ratio)
+        let (reflects, attenuation) = match &self.thin_film {
+            None => (
+                cannot_refract || reflectance(cos_theta, refraction_ratio) > rand.random_double(),
+                attenuation,
+            ),
+            Some(_) if cannot_refract => (true, attenuation),
+            Some(film) => {
+                // The film colors reflected and transmitted light
+                // differently, so choose between them by the average
+                // reflectance and reweight each channel.
+                let (outside, substrate) = if rec.front_face { (1.0, ir) } else { (ir, 1.0) };
+                let r = film.reflectance(
+                    rec,
+                    cos_theta,
+                    outside,
+                    &Color::new(substrate, substrate, substrate),
+                    &Color::new(0.0, 0.0, 0.0),
+                    r_in.wavelength(),
+                );
+                let p = (r.x() + r.y() + r.z()) / 3.0;
+                if rand.random_double() < p {
+                    (true, attenuation * r / p)
+                } else {
+                    let t = Color::new(1.0, 1.0, 1.0) - r;
+                    (false, attenuation * t / (1.0 - p))
+                }
+            }
+        };
+        let dispersed = dispersed || (self.thin_film.is_some() && r_in.wavelength().is_some());
+        let direction = if reflects {
+            reflect(&unit_direction, &rec.normal)
+        } else {
+            refract(&unit_direction, &rec.normal, refraction_ratio)
+        };
+
+        Some(ScatterRecord {
+            scattered: Ray::new(rec.p, direction),
+            attenuation,
+            pdf: 0.0,
+            lobe: Lobe::Specular,
+            transmission: !reflects,
+            dispersed,
+        })
+    }
+}
+
+/// Frosted glass: a dielectric whose surface is a GGX microfacet
+/// distribution, both reflecting and transmitting, with optional
+/// absorption inside.
+pub struct RoughDielectric {
+    /// Index of refraction
+    ir: f64,
+    dispersion: Option<Dispersion>,
+    distribution: TrowbridgeReitz,
+    absorption: Absorption,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> Self {
+        RoughDielectric {
+            ir,
+            dispersion: None,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            absorption: Absorption::none(),
+        }
+    }
+
+    /// Tints light inside the material so that white light becomes `color`
+    /// after travelling `distance`.
+    pub fn set_absorption(&mut self, color: Color, distance: f64) {
+        self.absorption = Absorption::from_color(color, distance)
+    }
+
+    /// Varies the index of refraction with wavelength, as for `Dielectric`.
+    pub fn set_dispersion(&mut self, dispersion: Dispersion) {
+        self.ir = dispersion.ior(D_LINE);
+        self.dispersion = Some(dispersion)
+    }
+
+    /// Ratio of the index beyond the surface to the index on our side.
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let (ir, _) = ior_for(self.ir, &self.dispersion, r_in);
+        if rec.front_face {
+            ir
+        } else {
+            1.0 / ir
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        let absorbed = self.absorption.transmittance(r_in, rec);
+        let eta = self.eta(r_in, rec);
+        let (_, dispersed) = ior_for(self.ir, &self.dispersion, r_in);
+
+        let (frame, wo) = shading_frame(r_in, rec);
+        let wm = if self.distribution.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_wm(&wo, rand)
+        };
+
+        // Choosing reflection with probability F cancels F out of both
+        // lobes, leaving only the masking ratio G2 / G1.
+        let cos_o = dot(&wo, &wm);
+        let wi = if rand.random_double() < fresnel_dielectric(cos_o, eta) {
+            let wi = reflect(&-wo, &wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(&-wo, &wm, 1.0 / eta);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let scattered = Ray::new(rec.p, frame.local(&wi));
+        if self.distribution.is_smooth() {
+            return Some(ScatterRecord {
+                scattered,
+                attenuation: absorbed,
+                pdf: 0.0,
+                lobe: Lobe::Specular,
+                transmission: wi.z() < 0.0,
+                dispersed,
+            });
+        }
+        let masking = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+        Some(ScatterRecord {
+            scattered,
+            attenuation: masking * absorbed,
+            pdf: microfacet_dielectric_pdf(&self.distribution, &wo, &wi, eta),
+            lobe: Lobe::Glossy,
+            transmission: wi.z() < 0.0,
+            dispersed,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let (frame, wo) = shading_frame(r_in, rec);
+        let wi = frame.to_local(&unit_vector(wi));
+        // Absorption along the incoming segment applies whichever way the
+        // light leaves.
+        microfacet_dielectric(&self.distribution, &wo, &wi, self.eta(r_in, rec))
+            * self.absorption.transmittance(r_in, rec)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = shading_frame(r_in, rec);
+        let wi = frame.to_local(&unit_vector(wi));
+        microfacet_dielectric_pdf(&self.distribution, &wo, &wi, self.eta(r_in, rec))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::vec3::Point3;
+
+    /// A ray hitting the plane z = 0 at the origin from `cos_theta` to the
+    /// normal, on the front face or from behind.
+    pub(crate) fn hit_at(cos_theta: f64, front_face: bool) -> (Ray, HitRecord) {
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let z = if front_face { cos_theta } else { -cos_theta };
+        let r_in = Ray::new(
+            Point3::new(sin_theta, 0.0, z),
+            Vec3::new(-sin_theta, 0.0, -z),
+        );
+        let mut rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            t: 1.0,
+            u: 0.5,
+            v: 0.5,
+            dpdu: Vec3::new(1.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+        rec.set_face_normal(&r_in, &Vec3::new(0.0, 0.0, 1.0));
+        (r_in, rec)
+    }
+
+    /// Integrates `f` over the sphere of directions with the midpoint rule.
+    pub(crate) fn integrate_sphere(f: impl Fn(&Vec3) -> f64) -> f64 {
+        let (n_theta, n_phi) = (300, 150);
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n_theta as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                sum += f(&Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+            }
+        }
+        sum * 4.0 * PI / (n_theta * n_phi) as f64
+    }
+
+    /// Checks that `sample`, `eval` and `pdf` describe the same BSDF: the
+    /// densities and weights of sampled directions agree with `pdf` and
+    /// `eval`, the density integrates to at most one and no energy is
+    /// created. Returns the mean weight of the samples, the material's
+    /// directional albedo.
+    pub(crate) fn check_consistency(material: &dyn Material, r_in: &Ray, rec: &HitRecord) -> Color {
+        let mut rand = Rand::new_with_seed(7);
+        let n = 20_000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let srec = match material.sample(r_in, rec, &mut rand) {
+                Some(srec) => srec,
+                None => continue,
             };
+            total += srec.attenuation;
+            if srec.is_specular() {
+                continue;
+            }
+            let wi = *srec.scattered.direction();
+            let pdf = material.pdf(r_in, rec, &wi);
+            assert!(
+                (srec.pdf - pdf).abs() <= 1e-6 * pdf.max(1.0),
+                "sampled pdf {} != {}",
+                srec.pdf,
+                pdf
+            );
+            let cos = dot(&unit_vector(&wi), &rec.normal).abs();
+            let expected = material.eval(r_in, rec, &wi) * cos / pdf;
+            for (a, b) in [
+                (srec.attenuation.x(), expected.x()),
+                (srec.attenuation.y(), expected.y()),
+                (srec.attenuation.z(), expected.z()),
+            ] {
+                assert!((a - b).abs() <= 1e-6 * b.max(1.0), "weight {} != {}", a, b);
+            }
+        }
 
-        let scattered = Ray::new(rec.p, direction);
+        let integral = integrate_sphere(|wi| material.pdf(r_in, rec, wi));
+        assert!(integral <= 1.02, "pdf integrates to {}", integral);
 
-        (true, attenuation, scattered)
+        let albedo = total / n as f64;
+        for c in [albedo.x(), albedo.y(), albedo.z()] {
+            assert!(c <= 1.02, "albedo {}", c);
+        }
+        albedo
+    }
+
+    #[test]
+    fn lambertian_is_consistent() {
+        let material = Lambertian::new(Color::new(0.8, 0.5, 0.2));
+        let (r_in, rec) = hit_at(0.6, true);
+        let albedo = check_consistency(&material, &r_in, &rec);
+        assert!((albedo.x() - 0.8).abs() < 1e-9);
+        let integral = integrate_sphere(|wi| material.pdf(&r_in, &rec, wi));
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+    }
+
+    #[test]
+    fn conductor_is_consistent() {
+        for roughness in [0.3, 0.7] {
+            for cos_theta in [0.2, 0.9] {
+                let (r_in, rec) = hit_at(cos_theta, true);
+                let albedo = check_consistency(&Conductor::gold(roughness), &r_in, &rec);
+                // Gold reflects red most strongly.
+                assert!(albedo.x() > albedo.z());
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_conductor_reflects_by_fresnel() {
+        let material = Conductor::silver(0.0);
+        let (r_in, rec) = hit_at(1.0, true);
+        let mut rand = Rand::new_with_seed(1);
+        let srec = material.sample(&r_in, &rec, &mut rand).unwrap();
+        assert!(srec.is_specular());
+        assert!((unit_vector(srec.scattered.direction()).z() - 1.0).abs() < 1e-9);
+        let expected = fresnel_conductor(1.0, &material.eta, &material.k);
+        assert!((srec.attenuation - expected).length() < 1e-12);
+    }
+
+    #[test]
+    fn albedo_is_free_of_noise() {
+        let (r_in, rec) = hit_at(1.0, true);
+        let gold = Conductor::gold(0.5);
+        let expected = fresnel_conductor(1.0, &gold.eta, &gold.k);
+        assert!((gold.albedo(&r_in, &rec) - expected).length() < 1e-12);
+        let grey = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        assert_eq!(grey.albedo(&r_in, &rec).x(), 0.5);
+        assert_eq!(Dielectric::new(1.5).albedo(&r_in, &rec).x(), 1.0);
+    }
+
+    #[test]
+    fn rough_dielectric_is_consistent() {
+        for front_face in [true, false] {
+            for cos_theta in [0.3, 0.9] {
+                let (r_in, rec) = hit_at(cos_theta, front_face);
+                check_consistency(&RoughDielectric::new(1.5, 0.5), &r_in, &rec);
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_splits_by_fresnel() {
+        let material = Dielectric::new(1.5);
+        let (r_in, rec) = hit_at(1.0, true);
+        let mut rand = Rand::new_with_seed(2);
+        let n = 20_000;
+        let reflected = (0..n)
+            .filter(|_| {
+                !material
+                    .sample(&r_in, &rec, &mut rand)
+                    .unwrap()
+                    .transmission
+            })
+            .count();
+        assert!((reflected as f64 / n as f64 - 0.04).abs() < 0.005);
+
+        // Past the critical angle light inside the glass always reflects.
+        let (r_in, rec) = hit_at(0.5, false);
+        let srec = material.sample(&r_in, &rec, &mut rand).unwrap();
+        assert!(!srec.transmission);
+    }
+
+    #[test]
+    fn absorption_tints_to_the_color_at_the_distance() {
+        let absorption = Absorption::from_color(Color::new(0.5, 0.25, 1.0), 2.0);
+        let (r_in, mut rec) = hit_at(1.0, false);
+        // The ray direction has unit length, so the light travelled 2.
+        rec.t = 2.0;
+        let t = absorption.transmittance(&r_in, &rec);
+        assert!((t - Color::new(0.5, 0.25, 1.0)).length() < 1e-12);
+        rec.t = 4.0;
+        let t = absorption.transmittance(&r_in, &rec);
+        assert!((t - Color::new(0.25, 0.0625, 1.0)).length() < 1e-12);
+
+        // Light arriving from outside has not been through the medium.
+        let (r_in, rec) = hit_at(1.0, true);
+        assert_eq!(absorption.transmittance(&r_in, &rec).x(), 1.0);
+    }
+
+    #[test]
+    fn absorption_needs_a_positive_distance() {
+        let (r_in, mut rec) = hit_at(1.0, false);
+        rec.t = 2.0;
+        for distance in [0.0, -1.0, f64::NAN] {
+            let absorption = Absorption::from_color(Color::new(0.5, 1.0, 1.0), distance);
+            let t = absorption.transmittance(&r_in, &rec);
+            // Colored channels are absorbed almost at once, white ones not
+            // at all.
+            assert!(t.x() >= 0.0 && t.x() < 1e-6, "{:?}", t);
+            assert_eq!(t.y(), 1.0);
+        }
     }
 }
@@ -0,0 +1,332 @@
+use crate::{
+    aabb::Aabb,
+    density::DensityField,
+    onb::Onb,
+    rand::Rand,
+    ray::Ray,
+    util::PI,
+    vec3::{dot, unit_vector, Color},
+    Vec3,
+};
+
+/// Henyey-Greenstein phase function. `g > 0` scatters forward, `g < 0`
+/// backward and `g == 0` is isotropic.
+#[derive(Debug, Copy, Clone)]
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Phase function value for light travelling along `direction` and
+    /// scattering into `scattered`.
+    pub fn eval(&self, direction: &Vec3, scattered: &Vec3) -> f64 {
+        let cos_theta = dot(&unit_vector(direction), &unit_vector(scattered));
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /// Density of `sample` returning `scattered`, which is `eval` itself.
+    pub fn pdf(&self, direction: &Vec3, scattered: &Vec3) -> f64 {
+        self.eval(direction, scattered)
+    }
+
+    /// Samples a scattered direction proportionally to `eval`.
+    pub fn sample(&self, direction: &Vec3, rand: &mut Rand) -> Vec3 {
+        let g = self.g;
+        let xi = rand.random_double();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rand.random_double();
+
+        Onb::build_from_w(direction).local(&Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+}
+
+pub enum MediumEvent {
+    /// The ray collided with the medium and continues along `scattered`,
+    /// which was sampled from `phase`.
+    Scatter {
+        attenuation: Color,
+        scattered: Ray,
+        phase: HenyeyGreenstein,
+    },
+    /// The ray left the sampled interval without a real collision.
+    Passed,
+}
+
+pub struct MediumSample {
+    /// Radiance emitted by the medium between the ray origin and the event.
+    pub emitted: Color,
+    pub event: MediumEvent,
+}
+
+impl MediumSample {
+    pub fn passed() -> Self {
+        MediumSample {
+            emitted: Color::new(0.0, 0.0, 0.0),
+            event: MediumEvent::Passed,
+        }
+    }
+}
+
+pub trait Medium {
+    /// The parametric interval of `r` inside the medium, if any.
+    fn interval(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)>;
+
+    /// Samples a free-flight distance along `r` within `t_min..t_max`.
+    fn sample(&self, r: &Ray, t_min: f64, t_max: f64, rand: &mut Rand) -> MediumSample;
+
+    /// Estimates the transmittance along `r` within `t_min..t_max`.
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rand: &mut Rand) -> f64;
+}
+
+/// A participating medium whose density varies across an axis-aligned box.
+///
+/// Free-flight distances are sampled with delta tracking against the
+/// density field's majorant, and transmittance is estimated with ratio
+/// tracking, so the field never needs to be integrated analytically.
+pub struct HeterogeneousMedium {
+    bounds: Aabb,
+    density: Box<dyn DensityField + Send + Sync>,
+    /// Extinction coefficient per unit density.
+    sigma_t: f64,
+    albedo: Color,
+    phase: HenyeyGreenstein,
+    emission: Color,
+    emission_field: Option<Box<dyn DensityField + Send + Sync>>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        bounds: Aabb,
+        density: Box<dyn DensityField + Send + Sync>,
+        sigma_t: f64,
+        albedo: Color,
+        g: f64,
+    ) -> Self {
+        HeterogeneousMedium {
+            bounds,
+            density,
+            sigma_t,
+            albedo,
+            phase: HenyeyGreenstein::new(g),
+            emission: Color::new(0.0, 0.0, 0.0),
+            emission_field: None,
+        }
+    }
+
+    /// Makes the medium emit `emission`, scaled by `field` if given, where
+    /// it absorbs light. Useful for fire and explosions.
+    pub fn set_emission(
+        &mut self,
+        emission: Color,
+        field: Option<Box<dyn DensityField + Send + Sync>>,
+    ) {
+        self.emission = emission;
+        self.emission_field = field;
+    }
+
+    fn sigma_t_at(&self, local: &Vec3) -> f64 {
+        self.sigma_t * self.density.density(local)
+    }
+
+    fn majorant(&self) -> f64 {
+        self.sigma_t * self.density.max_density()
+    }
+
+    fn emitted_at(&self, local: &Vec3) -> Color {
+        match &self.emission_field {
+            Some(field) => field.density(local) * self.emission,
+            None => self.emission,
+        }
+    }
+}
+
+impl Medium for HeterogeneousMedium {
+    fn interval(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        self.bounds.intersect(r, t_min, t_max)
+    }
+
+    fn sample(&self, r: &Ray, t_min: f64, t_max: f64, rand: &mut Rand) -> MediumSample {
+        let (t0, t1) = match self.interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return MediumSample::passed(),
+        };
+        let sigma_maj = self.majorant();
+        if sigma_maj <= 0.0 {
+            return MediumSample::passed();
+        }
+
+        let ray_length = r.direction().length();
+        let absorption = Color::new(1.0, 1.0, 1.0) - self.albedo;
+        let mut emitted = Color::new(0.0, 0.0, 0.0);
+        let mut t = t0;
+
+        loop {
+            t -= (1.0 - rand.random_double()).ln() / (sigma_maj * ray_length);
+            if t >= t1 {
+                return MediumSample {
+                    emitted,
+                    event: MediumEvent::Passed,
+                };
+            }
+
+            let p = r.at(t);
+            let local = self.bounds.to_local(&p);
+            let sigma_t = self.sigma_t_at(&local);
+
+            // Emission is collected at every tentative collision, weighted by
+            // the absorbing fraction of the majorant.
+            emitted += (sigma_t / sigma_maj) * absorption * self.emitted_at(&local);
+
+            if rand.random_double() < sigma_t / sigma_maj {
+                let scattered = Ray::new(p, self.phase.sample(r.direction(), rand));
+                return MediumSample {
+                    emitted,
+                    event: MediumEvent::Scatter {
+                        attenuation: self.albedo,
+                        scattered,
+                        phase: self.phase,
+                    },
+                };
+            }
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rand: &mut Rand) -> f64 {
+        let (t0, t1) = match self.interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return 1.0,
+        };
+        let sigma_maj = self.majorant();
+        if sigma_maj <= 0.0 {
+            return 1.0;
+        }
+
+        let ray_length = r.direction().length();
+        let mut tr = 1.0;
+        let mut t = t0;
+
+        loop {
+            t -= (1.0 - rand.random_double()).ln() / (sigma_maj * ray_length);
+            if t >= t1 {
+                return tr;
+            }
+            let local = self.bounds.to_local(&r.at(t));
+            tr *= 1.0 - self.sigma_t_at(&local) / sigma_maj;
+            if tr <= 0.0 {
+                return 0.0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{density::ConstantDensity, vec3::Point3};
+
+    fn unit_box(sigma_t: f64) -> HeterogeneousMedium {
+        HeterogeneousMedium::new(
+            Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0)),
+            Box::new(ConstantDensity::new(1.0)),
+            sigma_t,
+            Color::new(0.5, 0.5, 0.5),
+            0.0,
+        )
+    }
+
+    #[test]
+    fn transmittance_follows_beer_lambert() {
+        let medium = unit_box(1.5);
+        let mut rand = Rand::new_with_seed(1);
+        // Crosses the box along x, through one unit of medium.
+        let r = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vec3::new(2.0, 0.0, 0.0));
+        let n = 20_000;
+        let mean = (0..n)
+            .map(|_| medium.transmittance(&r, 0.0, f64::INFINITY, &mut rand))
+            .sum::<f64>()
+            / n as f64;
+        assert!((mean - (-1.5f64).exp()).abs() < 0.02, "{}", mean);
+    }
+
+    #[test]
+    fn collisions_follow_beer_lambert() {
+        let medium = unit_box(1.5);
+        let mut rand = Rand::new_with_seed(2);
+        let r = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        let n = 20_000;
+        let passed = (0..n)
+            .filter(|_| {
+                let sample = medium.sample(&r, 0.0, f64::INFINITY, &mut rand);
+                matches!(sample.event, MediumEvent::Passed)
+            })
+            .count();
+        let fraction = passed as f64 / n as f64;
+        assert!((fraction - (-1.5f64).exp()).abs() < 0.02, "{}", fraction);
+    }
+
+    #[test]
+    fn misses_outside_its_bounds() {
+        let medium = unit_box(1.5);
+        let mut rand = Rand::new_with_seed(3);
+        let r = Ray::new(Point3::new(-1.0, 2.0, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(medium.transmittance(&r, 0.0, f64::INFINITY, &mut rand), 1.0);
+        assert!(medium.interval(&r, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn phase_function_integrates_to_one() {
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(g);
+            let direction = Vec3::new(0.0, 0.0, 1.0);
+            // Midpoint rule over cos theta, the function being symmetric
+            // about the direction.
+            let n = 20_000;
+            let integral: f64 = (0..n)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let scattered = Vec3::new(sin_theta, 0.0, cos_theta);
+                    2.0 * PI * phase.eval(&direction, &scattered) * 2.0 / n as f64
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3, "g = {}: {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn phase_samples_scatter_forward_for_positive_g() {
+        let phase = HenyeyGreenstein::new(0.6);
+        let mut rand = Rand::new_with_seed(4);
+        let direction = Vec3::new(0.0, 1.0, 0.0);
+        let n = 20_000;
+        // The mean cosine of Henyey-Greenstein scattering is g.
+        let mean = (0..n)
+            .map(|_| {
+                dot(
+                    &unit_vector(&phase.sample(&direction, &mut rand)),
+                    &direction,
+                )
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!((mean - 0.6).abs() < 0.02, "{}", mean);
+    }
+}
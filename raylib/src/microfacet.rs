@@ -0,0 +1,289 @@
+//! Trowbridge-Reitz (GGX) microfacet distribution. Directions are expressed
+//! in a local shading frame where the surface normal is +z.
+
+use crate::{
+    fresnel::fresnel_dielectric,
+    rand::Rand,
+    util::PI,
+    vec3::{cross, dot, unit_vector, Color},
+    Vec3,
+};
+
+#[derive(Debug, Copy, Clone)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    /// Maps a perceptual `roughness` in `0..=1` to the distribution's
+    /// `alpha = roughness^2`.
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        TrowbridgeReitz {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// Below this alpha the surface is treated as a perfect mirror, since
+    /// the distribution becomes numerically unusable.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// Distribution of microfacet normals `wm`.
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2 = wm.z() * wm.z();
+        if cos2 == 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        let a2 = self.alpha * self.alpha;
+        let e = 1.0 + tan2 / a2;
+        1.0 / (PI * a2 * cos2 * cos2 * e * e)
+    }
+
+    /// Density with which `sample_wm` produces `wm` when viewed from `wo`.
+    pub fn pdf(&self, wo: &Vec3, wm: &Vec3) -> f64 {
+        if wo.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / wo.z().abs() * self.d(wm) * dot(wo, wm).abs()
+    }
+
+    /// Smith's auxiliary function for the fraction of microfacets masked
+    /// when seen from `w`.
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated masking-shadowing for the pair `wo`, `wi`.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal from the distribution of normals visible
+    /// from `wo` (Heitz 2018).
+    pub fn sample_wm(&self, wo: &Vec3, rand: &mut Rand) -> Vec3 {
+        // Stretch the view direction to the hemisphere configuration.
+        let mut wh = unit_vector(&Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()));
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {
+            unit_vector(&cross(&Vec3::new(0.0, 0.0, 1.0), &wh))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&wh, &t1);
+
+        // Sample a point on the projected half disk.
+        let r = rand.random_double().sqrt();
+        let phi = 2.0 * PI * rand.random_double();
+        let p1 = r * phi.cos();
+        let mut p2 = r * phi.sin();
+        let s = 0.5 * (1.0 + wh.z());
+        p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * wh;
+        unit_vector(&Vec3::new(
+            self.alpha * nh.x(),
+            self.alpha * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+
+/// A GGX reflection lobe with the given Fresnel term.
+pub(crate) fn microfacet_reflection(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    wi: &Vec3,
+    fresnel: impl Fn(f64) -> Color,
+) -> Color {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let wm = unit_vector(&(wo + wi));
+    distribution.d(&wm) * distribution.g(wo, wi) / (4.0 * wo.z() * wi.z()) * fresnel(dot(wo, &wm))
+}
+
+/// Density of `wi` when reflecting `wo` off a visible normal sample.
+pub(crate) fn microfacet_reflection_pdf(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    wi: &Vec3,
+) -> f64 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return 0.0;
+    }
+    let wm = unit_vector(&(wo + wi));
+    distribution.pdf(wo, &wm) / (4.0 * dot(wo, &wm))
+}
+
+/// Generalized half vector of a refraction from `wo` into `wi`, oriented
+/// with the normal, or `None` if no microfacet could produce it.
+fn refraction_half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+    let wm = eta * wi + wo;
+    if wm.length_squared() == 0.0 {
+        return None;
+    }
+    let wm = unit_vector(&wm);
+    let wm = if wm.z() < 0.0 { -wm } else { wm };
+    if dot(&wm, wo) <= 0.0 || dot(&wm, wi) >= 0.0 {
+        return None;
+    }
+    Some(wm)
+}
+
+/// A rough dielectric boundary that both reflects and refracts, where
+/// `eta` is the ratio of the index beyond the surface to the index on the
+/// side of `wo`.
+pub(crate) fn microfacet_dielectric(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    wi: &Vec3,
+    eta: f64,
+) -> f64 {
+    if wi.z() > 0.0 {
+        return microfacet_reflection(distribution, wo, wi, |cos| {
+            let f = fresnel_dielectric(cos, eta);
+            Color::new(f, f, f)
+        })
+        .x();
+    }
+
+    let wm = match refraction_half_vector(wo, wi, eta) {
+        Some(wm) => wm,
+        None => return 0.0,
+    };
+    let t = 1.0 - fresnel_dielectric(dot(wo, &wm), eta);
+    let denom = dot(wi, &wm) + dot(wo, &wm) / eta;
+    distribution.d(&wm)
+        * distribution.g(wo, wi)
+        * t
+        * (dot(wi, &wm) * dot(wo, &wm) / (wi.z() * wo.z() * denom * denom)).abs()
+}
+
+/// Density of `wi` when a visible normal is sampled and then reflected or
+/// refracted through in proportion to the Fresnel term.
+pub(crate) fn microfacet_dielectric_pdf(
+    distribution: &TrowbridgeReitz,
+    wo: &Vec3,
+    wi: &Vec3,
+    eta: f64,
+) -> f64 {
+    if wi.z() > 0.0 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let wm = unit_vector(&(wo + wi));
+        let r = fresnel_dielectric(dot(wo, &wm), eta);
+        return r * microfacet_reflection_pdf(distribution, wo, wi);
+    }
+
+    let wm = match refraction_half_vector(wo, wi, eta) {
+        Some(wm) => wm,
+        None => return 0.0,
+    };
+    let t = 1.0 - fresnel_dielectric(dot(wo, &wm), eta);
+    let denom = dot(wi, &wm) + dot(wo, &wm) / eta;
+    distribution.pdf(wo, &wm) * t * dot(wi, &wm).abs() / (denom * denom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integrates `f` over the sphere of directions with the midpoint rule.
+    fn integrate_sphere(f: impl Fn(&Vec3) -> f64) -> f64 {
+        let (n_theta, n_phi) = (400, 200);
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n_theta as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                let w = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += f(&w);
+            }
+        }
+        sum * 4.0 * PI / (n_theta * n_phi) as f64
+    }
+
+    #[test]
+    fn projected_normals_integrate_to_one() {
+        for roughness in [0.5, 0.7, 1.0] {
+            let distribution = TrowbridgeReitz::from_roughness(roughness);
+            let area = integrate_sphere(|wm| {
+                if wm.z() > 0.0 {
+                    distribution.d(wm) * wm.z()
+                } else {
+                    0.0
+                }
+            });
+            assert!((area - 1.0).abs() < 0.02, "{}: {}", roughness, area);
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        let distribution = TrowbridgeReitz::from_roughness(0.6);
+        let wo = unit_vector(&Vec3::new(0.5, 0.2, 0.8));
+        let total = integrate_sphere(|wm| {
+            if wm.z() > 0.0 {
+                distribution.pdf(&wo, wm)
+            } else {
+                0.0
+            }
+        });
+        assert!((total - 1.0).abs() < 0.02, "{}", total);
+    }
+
+    #[test]
+    fn sampled_normals_face_the_viewer() {
+        let distribution = TrowbridgeReitz::from_roughness(0.8);
+        let mut rand = Rand::new_with_seed(1);
+        let wo = unit_vector(&Vec3::new(0.9, 0.0, 0.3));
+        for _ in 0..1000 {
+            let wm = distribution.sample_wm(&wo, &mut rand);
+            assert!((wm.length() - 1.0).abs() < 1e-9);
+            assert!(wm.z() > 0.0);
+            assert!(dot(&wo, &wm) > 0.0);
+        }
+    }
+
+    #[test]
+    fn reflection_pdf_integrates_to_at_most_one() {
+        let distribution = TrowbridgeReitz::from_roughness(0.5);
+        let wo = unit_vector(&Vec3::new(0.3, 0.0, 0.9));
+        let total = integrate_sphere(|wi| microfacet_reflection_pdf(&distribution, &wo, wi));
+        // Reflections of visible normals that fall below the horizon are
+        // lost.
+        assert!(total <= 1.01 && total > 0.9, "{}", total);
+    }
+
+    #[test]
+    fn dielectric_conserves_energy() {
+        let distribution = TrowbridgeReitz::from_roughness(0.5);
+        let wo = unit_vector(&Vec3::new(0.3, 0.0, 0.9));
+        for eta in [1.5, 1.0 / 1.5] {
+            let pdf = integrate_sphere(|wi| microfacet_dielectric_pdf(&distribution, &wo, wi, eta));
+            assert!(pdf <= 1.01 && pdf > 0.9, "{}: {}", eta, pdf);
+            let albedo = integrate_sphere(|wi| {
+                microfacet_dielectric(&distribution, &wo, wi, eta) * wi.z().abs()
+            });
+            // Radiance is not scaled by eta^2 on transmission, so only
+            // check that the lobe does not create energy.
+            assert!(albedo <= 1.01 && albedo > 0.8, "{}: {}", eta, albedo);
+        }
+    }
+}
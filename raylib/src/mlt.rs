@@ -0,0 +1,343 @@
+//! Primary sample space Metropolis light transport (Kelemen et al., 2002).
+//! The random numbers a path is traced from are treated as a point in a
+//! unit hypercube, and Markov chains wander through it with a density
+//! proportional to the brightness of the paths, so effort goes to the
+//! paths that carry the most light however hard they are to find.
+
+use std::sync::OnceLock;
+
+use crate::{
+    distribution::Distribution1D,
+    film::Film,
+    rand::Rand,
+    util::PI,
+    vec3::{luminance, Color},
+};
+
+/// Samples in a chain's batch before they are added to the film.
+const SPLAT_BATCH: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    /// Iteration the value was last changed in.
+    modified: u64,
+    backup: f64,
+    modified_backup: u64,
+}
+
+/// The random numbers of the current path of a chain, mutated lazily: each
+/// number catches up on the mutations it missed when it is next used.
+pub(crate) struct PrimarySamples {
+    samples: Vec<PrimarySample>,
+    index: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    /// Standard deviation of small steps.
+    sigma: f64,
+    large_step_probability: f64,
+}
+
+impl PrimarySamples {
+    pub(crate) fn new(sigma: f64, large_step_probability: f64) -> Self {
+        // The first path is drawn afresh, as by a large step.
+        PrimarySamples {
+            samples: vec![],
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            sigma,
+            large_step_probability,
+        }
+    }
+
+    pub(crate) fn next(&mut self, rng: &mut oorandom::Rand64) -> f64 {
+        let index = self.index;
+        self.index += 1;
+        if index >= self.samples.len() {
+            // Numbers first used by this path are as fresh as by a large step.
+            self.samples.push(PrimarySample {
+                value: rng.rand_float(),
+                modified: self.last_large_step,
+                ..PrimarySample::default()
+            });
+        }
+
+        let sample = &mut self.samples[index];
+        // Small steps since the last accepted large step are only
+        // relative to its values.
+        if sample.modified < self.last_large_step {
+            sample.value = rng.rand_float();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.modified_backup = sample.modified;
+
+        if self.large_step {
+            sample.value = rng.rand_float();
+        } else {
+            // The small steps missed add up to one of their combined size.
+            let missed = (self.iteration - sample.modified) as f64;
+            let sigma = self.sigma * missed.sqrt();
+            let (u1, u2) = (1.0 - rng.rand_float(), rng.rand_float());
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            sample.value = (sample.value + sigma * normal).rem_euclid(1.0);
+        }
+        sample.modified = self.iteration;
+        sample.value
+    }
+
+    pub(crate) fn start_iteration(&mut self, rng: &mut oorandom::Rand64) {
+        self.iteration += 1;
+        self.large_step = rng.rand_float() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub(crate) fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    pub(crate) fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+}
+
+/// Paths traced from fresh random numbers, to scale the image by and to
+/// start chains from.
+struct Bootstrap {
+    /// Brightness of the path from each seed.
+    distribution: Distribution1D,
+    /// Average brightness of a path.
+    brightness: f64,
+}
+
+/// Seed for the random numbers of bootstrap path `index`.
+fn bootstrap_seed(index: usize) -> u128 {
+    (index as u128 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835)
+}
+
+/// The Metropolis integrator's bootstrap, shared by the chains.
+pub(crate) struct Metropolis {
+    bootstrap: OnceLock<Bootstrap>,
+    bootstrap_samples: usize,
+    sigma: f64,
+    large_step_probability: f64,
+}
+
+impl Metropolis {
+    pub(crate) fn new(bootstrap_samples: u32, sigma: f64, large_step_probability: f64) -> Self {
+        Metropolis {
+            bootstrap: OnceLock::new(),
+            bootstrap_samples: bootstrap_samples.max(1) as usize,
+            sigma,
+            large_step_probability: large_step_probability.clamp(0.0, 1.0),
+        }
+    }
+
+    fn bootstrap(&self, sample: &impl Fn(&mut Rand) -> (Color, (f64, f64))) -> &Bootstrap {
+        self.bootstrap.get_or_init(|| {
+            let brightness: Vec<f64> = (0..self.bootstrap_samples)
+                .map(|i| {
+                    let mut rand = self.chain_rand(i);
+                    luminance(&sample(&mut rand).0).max(0.0)
+                })
+                .collect();
+            let distribution = Distribution1D::new(brightness);
+            Bootstrap {
+                brightness: distribution.integral(),
+                distribution,
+            }
+        })
+    }
+
+    fn chain_rand(&self, index: usize) -> Rand {
+        Rand::primary_sample_space(
+            bootstrap_seed(index),
+            PrimarySamples::new(self.sigma, self.large_step_probability),
+        )
+    }
+
+    /// Average brightness of a path, which the light splatted onto the
+    /// film must be scaled by. Only known once a chain has run.
+    pub(crate) fn brightness(&self) -> f64 {
+        self.bootstrap.get().map_or(0.0, |b| b.brightness)
+    }
+
+    /// Runs a chain of `mutations` steps, splatting each path it visits
+    /// onto `film`. `sample` traces a path from the random numbers it is
+    /// given, returning its light and film coordinates.
+    pub(crate) fn run_chain(
+        &self,
+        sample: impl Fn(&mut Rand) -> (Color, (f64, f64)),
+        mutations: usize,
+        film: &Film,
+        rand: &mut Rand,
+    ) {
+        let bootstrap = self.bootstrap(&sample);
+        if bootstrap.brightness <= 0.0 {
+            return;
+        }
+        let (_, _, start) = bootstrap.distribution.sample(rand.random_double());
+        let mut chain = self.chain_rand(start);
+        let (mut l, mut point) = sample(&mut chain);
+        let mut c = luminance(&l);
+        let mut splats = Vec::with_capacity(SPLAT_BATCH);
+
+        for _ in 0..mutations {
+            chain.start_iteration();
+            let (proposed_l, proposed_point) = sample(&mut chain);
+            let proposed_c = luminance(&proposed_l);
+            let accept = if c > 0.0 {
+                (proposed_c / c).min(1.0)
+            } else {
+                1.0
+            };
+
+            // Both paths are splatted by their expected share of the step.
+            if accept > 0.0 {
+                splats.push((
+                    proposed_point.0,
+                    proposed_point.1,
+                    (accept / proposed_c) * proposed_l,
+                ));
+            }
+            if c > 0.0 && accept < 1.0 {
+                splats.push((point.0, point.1, ((1.0 - accept) / c) * l));
+            }
+
+            if rand.random_double() < accept {
+                l = proposed_l;
+                point = proposed_point;
+                c = proposed_c;
+                chain.accept();
+            } else {
+                chain.reject();
+            }
+
+            if splats.len() >= SPLAT_BATCH {
+                film.add_splats(&splats);
+                splats.clear();
+            }
+        }
+        film.add_splats(&splats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(samples: &PrimarySamples) -> Vec<f64> {
+        samples.samples.iter().map(|s| s.value).collect()
+    }
+
+    /// Starts an iteration and draws `n` numbers from it.
+    fn propose(samples: &mut PrimarySamples, rng: &mut oorandom::Rand64, n: usize) -> Vec<f64> {
+        samples.start_iteration(rng);
+        (0..n).map(|_| samples.next(rng)).collect()
+    }
+
+    #[test]
+    fn rejected_steps_restore_the_path() {
+        let mut rng = oorandom::Rand64::new(1);
+        let mut samples = PrimarySamples::new(0.01, 0.3);
+        let first: Vec<f64> = (0..5).map(|_| samples.next(&mut rng)).collect();
+        for _ in 0..20 {
+            let proposed = propose(&mut samples, &mut rng, 5);
+            assert_ne!(proposed, first);
+            samples.reject();
+            assert_eq!(values(&samples), first);
+        }
+    }
+
+    #[test]
+    fn accepted_steps_keep_the_proposal() {
+        let mut rng = oorandom::Rand64::new(2);
+        let mut samples = PrimarySamples::new(0.01, 0.3);
+        for _ in 0..5 {
+            samples.next(&mut rng);
+        }
+        for _ in 0..20 {
+            let proposed = propose(&mut samples, &mut rng, 5);
+            samples.accept();
+            assert_eq!(values(&samples), proposed);
+        }
+    }
+
+    #[test]
+    fn small_steps_stay_close() {
+        let mut rng = oorandom::Rand64::new(3);
+        let mut samples = PrimarySamples::new(0.01, 0.0);
+        let mut current: Vec<f64> = (0..4).map(|_| samples.next(&mut rng)).collect();
+        for _ in 0..100 {
+            let proposed = propose(&mut samples, &mut rng, 4);
+            for (a, b) in current.iter().zip(&proposed) {
+                // Distance around the unit circle the numbers wrap on.
+                let d = (a - b).abs();
+                assert!(d.min(1.0 - d) < 0.1, "{} -> {}", a, b);
+            }
+            samples.accept();
+            current = proposed;
+        }
+    }
+
+    #[test]
+    fn numbers_catch_up_on_missed_steps() {
+        let mut rng = oorandom::Rand64::new(4);
+        let mut samples = PrimarySamples::new(0.01, 0.0);
+        samples.next(&mut rng);
+        samples.next(&mut rng);
+        // The second number is not used for a while.
+        for _ in 0..10 {
+            propose(&mut samples, &mut rng, 1);
+            samples.accept();
+        }
+        propose(&mut samples, &mut rng, 2);
+        assert!(samples
+            .samples
+            .iter()
+            .all(|s| s.modified == samples.iteration));
+
+        // A large step redraws every number the path uses, and numbers
+        // only used later count as redrawn by it too.
+        let mut samples = PrimarySamples::new(0.01, 1.0);
+        samples.next(&mut rng);
+        propose(&mut samples, &mut rng, 1);
+        samples.accept();
+        assert_eq!(samples.last_large_step, samples.iteration);
+        propose(&mut samples, &mut rng, 2);
+        assert_eq!(samples.samples[1].modified, samples.iteration);
+    }
+
+    #[test]
+    fn chains_visit_paths_by_brightness() {
+        // Paths along a line of the film whose brightness grows as u².
+        let sample = |rand: &mut Rand| {
+            let u = rand.random_double();
+            (Color::new(u * u, u * u, u * u), (u, 0.5))
+        };
+        let metropolis = Metropolis::new(10_000, 0.05, 0.3);
+        // Two pixels, splitting the line at u = 0.5.
+        let film = Film::new(3, 2, 1);
+        let mut rand = Rand::new_with_seed(5);
+        let mutations = 200_000;
+        metropolis.run_chain(sample, mutations, &film, &mut rand);
+
+        assert!((metropolis.brightness() - 1.0 / 3.0).abs() < 0.01);
+        let line = film.developed_colors(0, 1.0 / mutations as f64);
+        // Brightness below u = 0.5 is an eighth of the total.
+        assert!((line[0].x() - 0.125).abs() < 0.02, "{:?}", line);
+        assert!((line[1].x() - 0.875).abs() < 0.02, "{:?}", line);
+        assert!(line[2].x().abs() < 1e-12);
+    }
+}
@@ -0,0 +1,509 @@
+//! Stochastic progressive photon mapping (Hachisuka and Jensen, 2009).
+//! Each pass traces photons from the lights into a spatial hash, then
+//! follows one path from the camera through every pixel until it meets a
+//! surface that is not specular and gathers the photons around it. Each
+//! pixel's gathering radius shrinks as photons are found, so the blur of
+//! the estimate vanishes over the passes.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+use crate::{
+    background,
+    camera::Camera,
+    hittable::{DidHit, HitRecord, Hittable},
+    light::BoundingSphere,
+    rand::Rand,
+    ray::Ray,
+    raytracer::{
+        background_radiance, direct_light, max_component, roulette, walk_interior, Bounces,
+        RaytracerOptions,
+    },
+    scene::Scene,
+    util::PI,
+    vec3::{unit_vector, Color, Point3},
+    Vec3,
+};
+
+/// Fraction of newly found photons kept in each pixel's count, which sets
+/// how quickly the gathering radius shrinks.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// Light arriving at a surface along a path from a light.
+#[derive(Debug, Clone, Copy)]
+struct Photon {
+    p: Point3,
+    /// Unit vector back towards where the photon came from.
+    wi: Vec3,
+    power: Color,
+}
+
+/// Photons hashed into a grid of cubes.
+struct PhotonMap {
+    cell_size: f64,
+    cells: HashMap<[i64; 3], Vec<Photon>>,
+}
+
+impl PhotonMap {
+    fn new(photons: Vec<Photon>, cell_size: f64) -> Self {
+        let mut map = PhotonMap {
+            cell_size,
+            cells: HashMap::new(),
+        };
+        for photon in photons {
+            let cell = map.cell(&photon.p);
+            map.cells.entry(cell).or_default().push(photon);
+        }
+        map
+    }
+
+    fn cell(&self, p: &Point3) -> [i64; 3] {
+        [0, 1, 2].map(|a| (p[a] / self.cell_size).floor() as i64)
+    }
+
+    /// Calls `f` with every photon within `radius` of `p`.
+    fn for_each_near(&self, p: &Point3, radius: f64, mut f: impl FnMut(&Photon)) {
+        let r = Vec3::new(radius, radius, radius);
+        let (low, high) = (self.cell(&(p - r)), self.cell(&(p + r)));
+        for x in low[0]..=high[0] {
+            for y in low[1]..=high[1] {
+                for z in low[2]..=high[2] {
+                    let photons = match self.cells.get(&[x, y, z]) {
+                        Some(photons) => photons,
+                        None => continue,
+                    };
+                    for photon in photons {
+                        if (photon.p - p).length_squared() <= radius * radius {
+                            f(photon)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What a pixel has gathered over the passes so far.
+#[derive(Debug, Clone, Copy)]
+struct PixelEstimate {
+    /// Sum of the light reaching the camera other than through photons.
+    direct: Color,
+    /// Photon power gathered within the current radius, weighted by the
+    /// BSDF and the camera path.
+    tau: Color,
+    /// Photons counted towards the current radius.
+    photons: f64,
+    radius: f64,
+}
+
+/// Where a path from the camera meets a surface that is not specular, to
+/// gather photons at.
+struct VisiblePoint {
+    r: Ray,
+    rec: HitRecord,
+    beta: Color,
+}
+
+/// The photon mapping integrator's photons and the estimates of every
+/// pixel. Only RGB is traced, and media only absorb light.
+pub(crate) struct PhotonMapper {
+    width: usize,
+    map: RwLock<Option<PhotonMap>>,
+    pixels: Mutex<Vec<PixelEstimate>>,
+    passes: AtomicU32,
+}
+
+impl PhotonMapper {
+    pub(crate) fn new(width: u32, height: u32, options: &RaytracerOptions) -> Self {
+        let estimate = PixelEstimate {
+            direct: Color::new(0.0, 0.0, 0.0),
+            tau: Color::new(0.0, 0.0, 0.0),
+            photons: 0.0,
+            // Estimates divide by the area within the radius.
+            radius: options.photon_radius.max(1e-6),
+        };
+        PhotonMapper {
+            width: width as usize,
+            map: RwLock::new(None),
+            pixels: Mutex::new(vec![estimate; (width * height) as usize]),
+            passes: AtomicU32::new(0),
+        }
+    }
+
+    /// Starts a pass by tracing the options' `photons_per_pass` photons.
+    pub(crate) fn trace_photons(
+        &self,
+        scene: &Scene,
+        options: &RaytracerOptions,
+        bounds: &BoundingSphere,
+        rand: &mut Rand,
+    ) {
+        let map = self.trace_map(scene, options, bounds, rand);
+        *self.map.write().unwrap() = Some(map);
+        self.passes.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Starts the first pass unless one was started, for front ends that
+    /// only trace lines. Lines traced meanwhile wait for it.
+    pub(crate) fn trace_first_photons(
+        &self,
+        scene: &Scene,
+        options: &RaytracerOptions,
+        bounds: &BoundingSphere,
+        rand: &mut Rand,
+    ) {
+        if self.map.read().unwrap().is_some() {
+            return;
+        }
+        let mut map = self.map.write().unwrap();
+        if map.is_none() {
+            *map = Some(self.trace_map(scene, options, bounds, rand));
+            self.passes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn trace_map(
+        &self,
+        scene: &Scene,
+        options: &RaytracerOptions,
+        bounds: &BoundingSphere,
+        rand: &mut Rand,
+    ) -> PhotonMap {
+        let mut photons = vec![];
+        for _ in 0..options.photons_per_pass {
+            trace_photon(scene, options, bounds, &mut photons, rand);
+        }
+        // Cells as large as the largest radius keep lookups to a few cells.
+        let largest_radius = self
+            .pixels
+            .lock()
+            .unwrap()
+            .iter()
+            .fold(0.0f64, |r, pixel| r.max(pixel.radius));
+        PhotonMap::new(photons, largest_radius)
+    }
+
+    /// Gathers the current pass's photons through every pixel of line `y`,
+    /// with `u` and `v` mapping pixels to film coordinates as for other
+    /// integrators.
+    pub(crate) fn trace_line(
+        &self,
+        y: u32,
+        scene: &Scene,
+        camera: &Camera,
+        options: &RaytracerOptions,
+        film_coordinates: impl Fn(usize, &mut Rand) -> (f64, f64),
+        rand: &mut Rand,
+    ) {
+        let map = self.map.read().unwrap();
+        let map = match map.as_ref() {
+            Some(map) => map,
+            None => return,
+        };
+        let start = y as usize * self.width;
+        let mut line = self.pixels.lock().unwrap()[start..start + self.width].to_vec();
+
+        for (i, pixel) in line.iter_mut().enumerate() {
+            let (u, v) = film_coordinates(i, rand);
+            let r = camera.get_ray(u, v, rand);
+            let (direct, visible) = visible_point(&r, scene, options, rand);
+            pixel.direct += direct;
+            let vp = match visible {
+                Some(vp) => vp,
+                None => continue,
+            };
+
+            let material = scene.get_material(vp.rec.material_id());
+            let mut phi = Color::new(0.0, 0.0, 0.0);
+            let mut found = 0.0;
+            map.for_each_near(&vp.rec.p, pixel.radius, |photon| {
+                phi += material.eval(&vp.r, &vp.rec, &photon.wi) * photon.power;
+                found += 1.0;
+            });
+            if found > 0.0 {
+                let photons = pixel.photons + ALPHA * found;
+                let radius = pixel.radius * (photons / (pixel.photons + found)).sqrt();
+                let shrink = (radius / pixel.radius).powi(2);
+                pixel.tau = shrink * (pixel.tau + vp.beta * phi);
+                pixel.photons = photons;
+                pixel.radius = radius;
+            }
+        }
+
+        self.pixels.lock().unwrap()[start..start + self.width].copy_from_slice(&line);
+    }
+
+    /// Line `y` of the image, from the passes so far.
+    pub(crate) fn developed_colors(&self, y: u32, options: &RaytracerOptions) -> Vec<Color> {
+        let passes = self.passes.load(Ordering::SeqCst).max(1) as f64;
+        let emitted = passes * options.photons_per_pass as f64;
+        let start = y as usize * self.width;
+        let pixels = self.pixels.lock().unwrap();
+        pixels[start..start + self.width]
+            .iter()
+            .map(|pixel| {
+                let area = PI * pixel.radius * pixel.radius;
+                pixel.direct / passes + pixel.tau / (emitted * area)
+            })
+            .collect()
+    }
+}
+
+/// Follows `r` from the camera through specular scattering to the first
+/// surface it would gather photons at. Also returns the light reaching the
+/// camera directly from the background and lights on the way, which
+/// photons do not carry.
+fn visible_point(
+    r: &Ray,
+    scene: &Scene,
+    options: &RaytracerOptions,
+    rand: &mut Rand,
+) -> (Color, Option<VisiblePoint>) {
+    let mut direct = Color::new(0.0, 0.0, 0.0);
+    let mut beta = Color::new(1.0, 1.0, 1.0);
+    let mut r = *r;
+    let mut bounces = Bounces::default();
+
+    for _ in 0..options.max_depth {
+        let surface = scene.hit(&r, 0.001, f64::INFINITY);
+        let t_surface = match &surface {
+            DidHit::Hit(rec) => rec.t,
+            DidHit::Miss => f64::INFINITY,
+        };
+        beta = scene.transmittance(&r, 0.001, t_surface, rand) * beta;
+
+        let rec = match surface {
+            DidHit::Hit(rec) => rec,
+            DidHit::Miss => {
+                direct += beta * background_radiance(&r, scene, 0.0);
+                break;
+            }
+        };
+        let (walked, rec, walk) = match walk_interior(&r, rec, scene, rand) {
+            Some(walk) => walk,
+            None => break,
+        };
+        beta = beta * walk;
+        direct += beta * direct_light(&walked, &rec, scene, rand);
+
+        let material = scene.get_material(rec.material_id());
+        let srec = match material.sample(&walked, &rec, rand) {
+            Some(srec) => srec,
+            None => break,
+        };
+        if !srec.is_specular() {
+            // Direct light was sampled against the BSDF finding the
+            // background, which the photons do not cover.
+            let scattered = srec.scattered;
+            if let DidHit::Miss = scene.hit(&scattered, 0.001, f64::INFINITY) {
+                let transmittance = scene.transmittance(&scattered, 0.001, f64::INFINITY, rand);
+                let background = background_radiance(&scattered, scene, srec.pdf);
+                direct += (transmittance * beta) * srec.attenuation * background;
+            }
+            // Surfaces that mix specular and other lobes, like clearcoats,
+            // only gather when another lobe is picked.
+            let vp = VisiblePoint {
+                beta: beta / material.non_specular_probability(&walked, &rec),
+                r: walked,
+                rec,
+            };
+            return (direct, Some(vp));
+        }
+        if !bounces.take(&srec, options) {
+            break;
+        }
+        beta = beta * srec.attenuation;
+        r = srec.scattered;
+    }
+
+    (direct, None)
+}
+
+/// Traces one photon from a light or the background chosen uniformly,
+/// storing it at every surface it reaches after its first.
+fn trace_photon(
+    scene: &Scene,
+    options: &RaytracerOptions,
+    bounds: &BoundingSphere,
+    photons: &mut Vec<Photon>,
+    rand: &mut Rand,
+) {
+    let lights = scene.lights();
+    let count = lights.len() + 1;
+    let index = ((rand.random_double() * count as f64) as usize).min(count - 1);
+    let emission = if index == lights.len() {
+        background::sample_emission(scene.background(), bounds, rand)
+    } else {
+        lights[index].sample_emission(bounds, rand)
+    };
+    let emission = match emission {
+        Some(emission) => emission,
+        None => return,
+    };
+    let pdf = emission.pdf_position * emission.pdf_direction / count as f64;
+    if pdf <= 0.0 {
+        return;
+    }
+
+    let mut power = emission.radiance / pdf;
+    let mut r = emission.ray;
+    let mut bounces = Bounces::default();
+    for depth in 0..options.max_depth as usize {
+        let rec = match scene.hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => rec,
+            DidHit::Miss => return,
+        };
+        power = scene.transmittance(&r, 0.001, rec.t, rand) * power;
+        let (walked, rec, walk) = match walk_interior(&r, rec, scene, rand) {
+            Some(walk) => walk,
+            None => return,
+        };
+        power = power * walk;
+        // Light arriving straight from the lights is sampled directly.
+        if depth > 0 {
+            photons.push(Photon {
+                p: rec.p,
+                wi: -unit_vector(walked.direction()),
+                power,
+            });
+        }
+
+        let material = scene.get_material(rec.material_id());
+        let srec = match material.sample(&walked, &rec, rand) {
+            Some(srec) => srec,
+            None => return,
+        };
+        if !bounces.take(&srec, options) {
+            return;
+        }
+        let survival = match roulette(max_component(&srec.attenuation), depth, options, rand) {
+            Some(survival) => survival,
+            None => return,
+        };
+        power = power * srec.attenuation / survival;
+        r = srec.scattered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{background::SkyGradient, material::Lambertian, sphere::Sphere};
+
+    fn random_photons(n: usize, half_width: f64, power: Color, rand: &mut Rand) -> Vec<Photon> {
+        (0..n)
+            .map(|_| Photon {
+                p: Point3::new(
+                    half_width * (2.0 * rand.random_double() - 1.0),
+                    0.0,
+                    half_width * (2.0 * rand.random_double() - 1.0),
+                ),
+                wi: Vec3::new(0.0, 1.0, 0.0),
+                power,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_photons_within_the_radius() {
+        let mut rand = Rand::new_with_seed(1);
+        let photons = random_photons(2000, 1.0, Color::new(1.0, 1.0, 1.0), &mut rand);
+        let map = PhotonMap::new(photons.clone(), 0.15);
+        for _ in 0..50 {
+            let p = Point3::new(rand.random_double() - 0.5, 0.05, rand.random_double() - 0.5);
+            let radius = 0.3 * rand.random_double();
+            let mut found = 0;
+            map.for_each_near(&p, radius, |photon| {
+                assert!((photon.p - p).length() <= radius);
+                found += 1;
+            });
+            let expected = photons
+                .iter()
+                .filter(|photon| (photon.p - p).length() <= radius)
+                .count();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn gathering_estimates_reflected_irradiance() {
+        let mut scene = Scene::new();
+        let black = Color::new(0.0, 0.0, 0.0);
+        scene.set_background(Box::new(SkyGradient::new(black, black)));
+        let ground = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        scene.add_object(Box::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            ground,
+        )));
+        let camera = Camera::new(
+            &Point3::new(0.0, 5.0, 0.0),
+            &Point3::new(0.0, 0.0, 0.0),
+            &Vec3::new(0.0, 0.0, -1.0),
+            20.0,
+            1.0,
+            0.0,
+            5.0,
+        );
+        let options = RaytracerOptions {
+            photons_per_pass: 1000,
+            photon_radius: 0.1,
+            ..Default::default()
+        };
+        let (width, height) = (8, 8);
+        let mapper = PhotonMapper::new(width, height, &options);
+
+        // Photons spread evenly over a square of area 16, carrying an
+        // irradiance of 1 on the ground between them.
+        let mut rand = Rand::new_with_seed(2);
+        let count = 40_000;
+        let power = 16.0 * options.photons_per_pass as f64 / count as f64;
+        for _ in 0..4 {
+            let photons = random_photons(count, 2.0, Color::new(power, power, power), &mut rand);
+            *mapper.map.write().unwrap() = Some(PhotonMap::new(photons, options.photon_radius));
+            mapper.passes.fetch_add(1, Ordering::SeqCst);
+            for y in 0..height {
+                let film_coordinates = |i: usize, rand: &mut Rand| {
+                    (
+                        (i as f64 + rand.random_double()) / (width - 1) as f64,
+                        (y as f64 + rand.random_double()) / (height - 1) as f64,
+                    )
+                };
+                mapper.trace_line(y, &scene, &camera, &options, film_coordinates, &mut rand);
+            }
+        }
+
+        let expected = 0.5 / PI;
+        let mut mean = 0.0;
+        for y in 0..height {
+            for color in mapper.developed_colors(y, &options) {
+                assert!((color.x() - expected).abs() < 0.2 * expected, "{:?}", color);
+                mean += color.x() / (width * height) as f64;
+            }
+        }
+        assert!((mean - expected).abs() < 0.05 * expected, "{}", mean);
+        // Radii only ever shrink as photons are found.
+        let pixels = mapper.pixels.lock().unwrap();
+        assert!(pixels
+            .iter()
+            .all(|pixel| pixel.radius < options.photon_radius));
+    }
+
+    #[test]
+    fn radii_start_positive() {
+        for photon_radius in [0.0, -1.0, f64::NAN] {
+            let options = RaytracerOptions {
+                photon_radius,
+                ..Default::default()
+            };
+            let mapper = PhotonMapper::new(2, 2, &options);
+            let pixels = mapper.pixels.lock().unwrap();
+            assert!(pixels.iter().all(|pixel| pixel.radius > 0.0));
+        }
+    }
+}
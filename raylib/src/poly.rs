@@ -0,0 +1,210 @@
+//! Closed-form real roots of low-degree polynomials, after Jochen Schwarze's
+//! "Cubic and Quartic Roots" in Graphics Gems. Coefficients are given lowest
+//! degree first, so `c[0] + c[1] x + c[2] x^2 + ...`.
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+pub fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    // Normal form: x^2 + 2px + q = 0
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let d = p * p - q;
+
+    if is_zero(d) {
+        vec![-p]
+    } else if d < 0.0 {
+        vec![]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![sqrt_d - p, -sqrt_d - p]
+    }
+}
+
+pub fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    // Normal form: x^3 + Ax^2 + Bx + C = 0
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+
+    // Substitute x = y - A/3 to eliminate the quadratic term:
+    // y^3 + 3py + 2q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    let mut roots = if is_zero(d) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if d < 0.0 {
+        // Casus irreducibilis: three real solutions
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + std::f64::consts::FRAC_PI_3).cos(),
+            -t * (phi - std::f64::consts::FRAC_PI_3).cos(),
+        ]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    let sub = a / 3.0;
+    for root in roots.iter_mut() {
+        *root -= sub;
+    }
+    roots
+}
+
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    // Normal form: x^4 + Ax^3 + Bx^2 + Cx + D = 0
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    // Substitute x = y - A/4 to eliminate the cubic term:
+    // y^4 + py^2 + qy + r = 0
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+    let mut roots = if is_zero(r) {
+        // No absolute term: y(y^3 + py + q) = 0
+        let mut roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // Solve the resolvent cubic and take its one guaranteed real root
+        // to split the quartic into two quadratics.
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return vec![];
+        };
+
+        let mut roots = solve_quadratic([z - u, if q < 0.0 { -v } else { v }, 1.0]);
+        roots.extend(solve_quadratic([z + u, if q < 0.0 { v } else { -v }, 1.0]));
+        roots
+    };
+
+    let sub = a / 4.0;
+    for root in roots.iter_mut() {
+        *root -= sub;
+        // The closed form loses precision quickly, so polish each root with
+        // a couple of Newton steps on the original polynomial.
+        for _ in 0..2 {
+            let x = *root;
+            let f = (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+            let df = ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+            if df != 0.0 {
+                *root = x - f / df;
+            }
+        }
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `roots` holds every one of `expected`, and nothing else
+    /// but repeats of them.
+    fn assert_roots(roots: &[f64], expected: &[f64], tolerance: f64) {
+        for e in expected {
+            assert!(
+                roots.iter().any(|r| (r - e).abs() < tolerance),
+                "missing root {} in {:?}",
+                e,
+                roots
+            );
+        }
+        for r in roots {
+            assert!(
+                expected.iter().any(|e| (r - e).abs() < tolerance),
+                "spurious root {} in {:?}",
+                r,
+                roots
+            );
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        // (x - 1)(x + 3)
+        assert_roots(&solve_quadratic([-3.0, 2.0, 1.0]), &[1.0, -3.0], 1e-9);
+        assert_roots(&solve_quadratic([1.0, -2.0, 1.0]), &[1.0], 1e-9);
+        assert!(solve_quadratic([1.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn cubic_roots() {
+        // (x - 1)(x - 2)(x + 4)
+        assert_roots(
+            &solve_cubic([8.0, -10.0, 1.0, 1.0]),
+            &[1.0, 2.0, -4.0],
+            1e-9,
+        );
+        // (x - 2)(x^2 + 1)
+        assert_roots(&solve_cubic([-2.0, 1.0, -2.0, 1.0]), &[2.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_distinct_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let roots = solve_quartic([24.0, -50.0, 35.0, -10.0, 1.0]);
+        assert_eq!(roots.len(), 4);
+        assert_roots(&roots, &[1.0, 2.0, 3.0, 4.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_double_root() {
+        // (x - 1)^2 (x - 3)(x + 2)
+        let roots = solve_quartic([-6.0, 11.0, -3.0, -3.0, 1.0]);
+        assert_roots(&roots, &[1.0, 3.0, -2.0], 1e-6);
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        assert!(solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+        // (x^2 + 1)(x^2 + 4)
+        assert!(solve_quartic([4.0, 0.0, 5.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn quartic_torus_intersection() {
+        // A ray along x through a torus of radii 2 and 0.5 about the y axis
+        // crosses its tube at x = -2.5, -1.5, 1.5 and 2.5:
+        // (x^2 + R^2 - r^2)^2 - 4R^2 x^2 = 0 with R = 2, r = 0.5.
+        let k = 4.0 - 0.25;
+        let roots = solve_quartic([k * k, 0.0, 2.0 * k - 16.0, 0.0, 1.0]);
+        assert_eq!(roots.len(), 4);
+        assert_roots(&roots, &[-2.5, -1.5, 1.5, 2.5], 1e-9);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{
+    fresnel::fresnel_dielectric,
+    hittable::HitRecord,
+    material::{shading_frame, Lobe, Material, ScatterRecord},
+    microfacet::{
+        microfacet_dielectric, microfacet_dielectric_pdf, microfacet_reflection,
+        microfacet_reflection_pdf, TrowbridgeReitz,
+    },
+    rand::Rand,
+    ray::Ray,
+    texture::{SolidColor, TextureRef},
+    util::PI,
+    vec3::{dot, luminance, random_cosine_direction, reflect, refract, unit_vector, Color},
+    Vec3,
+};
+
+/// Roughness is clamped to this so every lobe stays a true distribution
+/// that can be evaluated for arbitrary directions.
+const MIN_ROUGHNESS: f64 = 0.05;
+const CLEARCOAT_ROUGHNESS: f64 = 0.1;
+
+/// A Disney-style principled BSDF combining diffuse, sheen, specular,
+/// clearcoat and transmission lobes behind a single set of artist-friendly
+/// parameters. Every parameter is a texture; scalar parameters read the
+/// first channel.
+pub struct Principled {
+    base_color: TextureRef,
+    metallic: TextureRef,
+    roughness: TextureRef,
+    /// Dielectric specular strength, where 0.5 is a typical 4% reflectance.
+    specular: TextureRef,
+    /// Tints dielectric specular towards the base color.
+    specular_tint: TextureRef,
+    sheen: TextureRef,
+    clearcoat: TextureRef,
+    transmission: TextureRef,
+    /// Index of refraction used by transmission.
+    ior: TextureRef,
+}
+
+impl Principled {
+    pub fn new(base_color: Color) -> Self {
+        Principled {
+            base_color: SolidColor::shared(base_color),
+            metallic: SolidColor::scalar(0.0),
+            roughness: SolidColor::scalar(0.5),
+            specular: SolidColor::scalar(0.5),
+            specular_tint: SolidColor::scalar(0.0),
+            sheen: SolidColor::scalar(0.0),
+            clearcoat: SolidColor::scalar(0.0),
+            transmission: SolidColor::scalar(0.0),
+            ior: SolidColor::scalar(1.5),
+        }
+    }
+
+    pub fn set_base_color(&mut self, texture: TextureRef) {
+        self.base_color = texture
+    }
+    pub fn set_metallic(&mut self, texture: TextureRef) {
+        self.metallic = texture
+    }
+    pub fn set_roughness(&mut self, texture: TextureRef) {
+        self.roughness = texture
+    }
+    pub fn set_specular(&mut self, texture: TextureRef) {
+        self.specular = texture
+    }
+    pub fn set_specular_tint(&mut self, texture: TextureRef) {
+        self.specular_tint = texture
+    }
+    pub fn set_sheen(&mut self, texture: TextureRef) {
+        self.sheen = texture
+    }
+    pub fn set_clearcoat(&mut self, texture: TextureRef) {
+        self.clearcoat = texture
+    }
+    pub fn set_transmission(&mut self, texture: TextureRef) {
+        self.transmission = texture
+    }
+    pub fn set_ior(&mut self, texture: TextureRef) {
+        self.ior = texture
+    }
+
+    fn lobes(&self, rec: &HitRecord) -> Lobes {
+        let scalar = |t: &TextureRef| t.value(rec.u, rec.v, &rec.p).x().clamp(0.0, 1.0);
+        let base_color = self.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = scalar(&self.metallic);
+        let transmission = scalar(&self.transmission);
+        let ior = self.ior.value(rec.u, rec.v, &rec.p).x().max(1.0);
+
+        let lum = luminance(&base_color);
+        let tint = if lum > 0.0 {
+            base_color / lum
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        };
+        let white = Color::new(1.0, 1.0, 1.0);
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_spec =
+            scalar(&self.specular) * 0.08 * ((1.0 - specular_tint) * white + specular_tint * tint);
+
+        let mut lobes = Lobes {
+            base_color,
+            sheen_color: scalar(&self.sheen) * (0.5 * white + 0.5 * tint),
+            spec_color: (1.0 - metallic) * dielectric_spec + metallic * base_color,
+            roughness: scalar(&self.roughness).max(MIN_ROUGHNESS),
+            spec: TrowbridgeReitz::from_roughness(scalar(&self.roughness).max(MIN_ROUGHNESS)),
+            coat: TrowbridgeReitz::from_roughness(CLEARCOAT_ROUGHNESS),
+            eta: if rec.front_face { ior } else { 1.0 / ior },
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            spec_weight: 1.0 - (1.0 - metallic) * transmission,
+            transmission_weight: (1.0 - metallic) * transmission,
+            coat_weight: 0.25 * scalar(&self.clearcoat),
+        };
+
+        // Rays that reach the back of a transmissive surface are inside the
+        // object, where only the refractive boundary applies. Opaque
+        // surfaces are shaded the same from both sides, about the normal
+        // facing the ray.
+        if !rec.front_face && transmission > 0.0 {
+            lobes.diffuse_weight = 0.0;
+            lobes.spec_weight = 0.0;
+            lobes.coat_weight = 0.0;
+            lobes.transmission_weight = 1.0;
+        }
+        lobes
+    }
+}
+
+/// The principled parameters evaluated at one hit point, in a local frame
+/// where the shading normal is +z and `wo` points back along the ray.
+struct Lobes {
+    base_color: Color,
+    sheen_color: Color,
+    spec_color: Color,
+    roughness: f64,
+    spec: TrowbridgeReitz,
+    coat: TrowbridgeReitz,
+    /// Ratio of the index beyond the surface to the index on our side.
+    eta: f64,
+    diffuse_weight: f64,
+    spec_weight: f64,
+    transmission_weight: f64,
+    coat_weight: f64,
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+impl Lobes {
+    fn sampling_weights(&self) -> [f64; 4] {
+        let weights = [
+            self.diffuse_weight,
+            self.spec_weight,
+            self.transmission_weight,
+            self.coat_weight,
+        ];
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let mut f = Color::new(0.0, 0.0, 0.0);
+
+        if self.diffuse_weight > 0.0 && wi.z() > 0.0 {
+            let wh = unit_vector(&(wo + wi));
+            let cos_d = dot(wi, &wh);
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z()))
+                * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()));
+            let diffuse = retro / PI * self.base_color;
+            let sheen = schlick_weight(cos_d) * self.sheen_color;
+            f += self.diffuse_weight * (diffuse + sheen);
+        }
+
+        if self.spec_weight > 0.0 {
+            let spec = microfacet_reflection(&self.spec, wo, wi, |cos| {
+                let w = schlick_weight(cos);
+                (1.0 - w) * self.spec_color + w * Color::new(1.0, 1.0, 1.0)
+            });
+            f += self.spec_weight * spec;
+        }
+
+        if self.coat_weight > 0.0 {
+            let coat = microfacet_reflection(&self.coat, wo, wi, |cos| {
+                let f = 0.04 + 0.96 * schlick_weight(cos);
+                Color::new(f, f, f)
+            });
+            f += self.coat_weight * coat;
+        }
+
+        if self.transmission_weight > 0.0 {
+            let t = microfacet_dielectric(&self.spec, wo, wi, self.eta);
+            f += self.transmission_weight * Color::new(t, t, t);
+        }
+
+        f
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let [p_diffuse, p_spec, p_transmission, p_coat] = self.sampling_weights();
+        let mut pdf = 0.0;
+        if wi.z() > 0.0 {
+            pdf += p_diffuse * wi.z() / PI;
+        }
+        pdf += p_spec * microfacet_reflection_pdf(&self.spec, wo, wi);
+        pdf += p_coat * microfacet_reflection_pdf(&self.coat, wo, wi);
+        if p_transmission > 0.0 {
+            pdf += p_transmission * microfacet_dielectric_pdf(&self.spec, wo, wi, self.eta);
+        }
+        pdf
+    }
+
+    /// Picks a lobe in proportion to its weight and samples a direction
+    /// from it alone.
+    fn sample(&self, wo: &Vec3, rand: &mut Rand) -> (Vec3, Lobe) {
+        let [p_diffuse, p_spec, p_transmission, _] = self.sampling_weights();
+        let u = rand.random_double();
+
+        if u < p_diffuse {
+            (random_cosine_direction(rand), Lobe::Diffuse)
+        } else if u < p_diffuse + p_spec {
+            (reflect(&-wo, &self.spec.sample_wm(wo, rand)), Lobe::Glossy)
+        } else if u < p_diffuse + p_spec + p_transmission {
+            let wm = self.spec.sample_wm(wo, rand);
+            if rand.random_double() < fresnel_dielectric(dot(wo, &wm), self.eta) {
+                (reflect(&-wo, &wm), Lobe::Glossy)
+            } else {
+                (refract(&-wo, &wm, 1.0 / self.eta), Lobe::Glossy)
+            }
+        } else {
+            (reflect(&-wo, &self.coat.sample_wm(wo, rand)), Lobe::Glossy)
+        }
+    }
+}
+
+impl Material for Principled {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        let lobes = self.lobes(rec);
+        let (frame, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        // One-sample MIS over the lobes: the direction came from a single
+        // lobe, but is weighted by the full BSDF and the combined density.
+        let (wi, lobe) = lobes.sample(&wo, rand);
+        let pdf = lobes.pdf(&wo, &wi);
+        if pdf <= 0.0 || wi.z() == 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            scattered: Ray::new(rec.p, frame.local(&wi)),
+            attenuation: lobes.eval(&wo, &wi) * (wi.z().abs() / pdf),
+            pdf,
+            lobe,
+            transmission: wi.z() < 0.0,
+            dispersed: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.lobes(rec).eval(&wo, &frame.to_local(&unit_vector(wi)))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let (frame, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.lobes(rec).pdf(&wo, &frame.to_local(&unit_vector(wi)))
+    }
+
+    fn albedo(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base_color.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::tests::*, vec3::Point3};
+
+    fn front_hit() -> HitRecord {
+        HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+            ..Default::default()
+        }
+    }
+
+    fn variants() -> Vec<Principled> {
+        let mut plastic = Principled::new(Color::new(0.8, 0.3, 0.1));
+        plastic.set_sheen(SolidColor::scalar(1.0));
+        plastic.set_clearcoat(SolidColor::scalar(1.0));
+        let mut metal = Principled::new(Color::new(0.9, 0.6, 0.2));
+        metal.set_metallic(SolidColor::scalar(1.0));
+        metal.set_roughness(SolidColor::scalar(0.3));
+        let mut glass = Principled::new(Color::new(1.0, 1.0, 1.0));
+        glass.set_transmission(SolidColor::scalar(1.0));
+        glass.set_roughness(SolidColor::scalar(0.4));
+        vec![plastic, metal, glass]
+    }
+
+    #[test]
+    fn lobes_sample_what_they_evaluate() {
+        let wo = unit_vector(&Vec3::new(0.4, 0.1, 0.8));
+        for principled in variants() {
+            let lobes = principled.lobes(&front_hit());
+            let mut rand = Rand::new_with_seed(3);
+            let n = 20_000;
+            let mut albedo = Color::new(0.0, 0.0, 0.0);
+            for _ in 0..n {
+                let (wi, _) = lobes.sample(&wo, &mut rand);
+                let pdf = lobes.pdf(&wo, &wi);
+                if pdf > 0.0 {
+                    albedo += lobes.eval(&wo, &wi) * (wi.z().abs() / pdf);
+                }
+            }
+            let albedo = albedo / n as f64;
+            for c in [albedo.x(), albedo.y(), albedo.z()] {
+                assert!(c > 0.05 && c <= 1.02, "albedo {}", c);
+            }
+
+            let integral = integrate_sphere(|wi| lobes.pdf(&wo, wi));
+            assert!(integral <= 1.02, "pdf integrates to {}", integral);
+        }
+    }
+
+    #[test]
+    fn principled_is_consistent() {
+        for principled in variants() {
+            for cos_theta in [0.3, 0.9] {
+                let (r_in, rec) = hit_at(cos_theta, true);
+                check_consistency(&principled, &r_in, &rec);
+            }
+        }
+    }
+
+    #[test]
+    fn opaque_back_faces_only_reflect() {
+        let plastic = Principled::new(Color::new(0.8, 0.3, 0.1));
+        let (r_in, rec) = hit_at(0.7, false);
+        let mut rand = Rand::new_with_seed(4);
+        for _ in 0..1000 {
+            if let Some(srec) = plastic.sample(&r_in, &rec, &mut rand) {
+                assert!(!srec.transmission);
+                assert!(dot(srec.scattered.direction(), &rec.normal) > 0.0);
+            }
+        }
+        let albedo = check_consistency(&plastic, &r_in, &rec);
+        assert!(albedo.x() > 0.5, "{:?}", albedo);
+    }
+
+    #[test]
+    fn metallic_reflects_the_base_color() {
+        let mut metal = Principled::new(Color::new(0.9, 0.6, 0.2));
+        metal.set_metallic(SolidColor::scalar(1.0));
+        let lobes = metal.lobes(&front_hit());
+        assert_eq!(lobes.diffuse_weight, 0.0);
+        assert_eq!(lobes.transmission_weight, 0.0);
+        assert!((lobes.spec_color - Color::new(0.9, 0.6, 0.2)).length() < 1e-12);
+    }
+}
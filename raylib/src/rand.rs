@@ -1,20 +1,66 @@
+use crate::mlt::PrimarySamples;
+
 pub struct Rand {
     rng: oorandom::Rand64,
+    /// Numbers replayed and mutated by a Metropolis chain, given out in
+    /// place of fresh ones.
+    primary: Option<PrimarySamples>,
+}
+
+impl Default for Rand {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Rand {
     pub fn new() -> Self {
         Rand {
             rng: oorandom::Rand64::new(0xda942042e4dd58b5),
+            primary: None,
         }
     }
     pub fn new_with_seed(seed: u128) -> Self {
         Rand {
             rng: oorandom::Rand64::new(seed),
+            primary: None,
+        }
+    }
+
+    /// Numbers from the primary sample space of a Metropolis chain, drawn
+    /// from `seed` until the chain mutates them.
+    pub(crate) fn primary_sample_space(seed: u128, samples: PrimarySamples) -> Self {
+        Rand {
+            rng: oorandom::Rand64::new(seed),
+            primary: Some(samples),
+        }
+    }
+
+    /// Starts proposing a mutation of the chain's numbers.
+    pub(crate) fn start_iteration(&mut self) {
+        if let Some(primary) = &mut self.primary {
+            primary.start_iteration(&mut self.rng)
+        }
+    }
+
+    /// Keeps the proposed numbers.
+    pub(crate) fn accept(&mut self) {
+        if let Some(primary) = &mut self.primary {
+            primary.accept()
+        }
+    }
+
+    /// Returns to the numbers before the proposal.
+    pub(crate) fn reject(&mut self) {
+        if let Some(primary) = &mut self.primary {
+            primary.reject()
         }
     }
 
     pub fn random_double(&mut self) -> f64 {
-        self.rng.rand_float()
+        match &mut self.primary {
+            Some(primary) => primary.next(&mut self.rng),
+            None => self.rng.rand_float(),
+        }
     }
 }
@@ -4,6 +4,9 @@ use super::{vec3::Point3, Vec3};
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    /// Hero wavelength in nanometres of a spectral path, for materials
+    /// whose behavior depends on it.
+    wavelength: Option<f64>,
 }
 
 impl Ray {
@@ -13,11 +16,22 @@ impl Ray {
     pub fn direction(&self) -> &Vec3 {
         &self.direction
     }
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
 
     pub fn new(origin: Point3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_wavelength(self, wavelength: Option<f64>) -> Ray {
+        Ray { wavelength, ..self }
     }
 }
@@ -1,11 +1,20 @@
 use crate::{
+    aov::{Aov, AovBuffers, AovPixel, LightPasses},
+    bdpt::Bidirectional,
     camera::Camera,
-    hittable::DidHit,
-    material::{Dielectric, Lambertian, Metal},
+    debug::debug_color,
+    denoise::{Denoiser, Guide},
+    film::{encode_line, Film},
+    hittable::{DidHit, HitRecord},
+    light::BoundingSphere,
+    material::{Dielectric, InteriorEvent, Lambertian, Lobe, Metal, ScatterRecord},
+    medium::{HenyeyGreenstein, MediumEvent},
+    mlt::Metropolis,
+    photon::PhotonMapper,
     rand::Rand,
     scene::Scene,
+    spectrum::{SampledSpectrum, SampledWavelengths},
     util::random_double_in_range,
-    vec3::rgba_multisampled,
     Vec3,
 };
 
@@ -16,50 +25,483 @@ use super::{
     vec3::{dot, unit_vector, Color, Point3},
 };
 
-fn ray_color(r: &Ray, scene: &Scene, depth: i32, rand: &mut Rand) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+/// Scattering events followed inside one object before a path is given up.
+const MAX_WALK_STEPS: usize = 256;
+
+/// Follows light that reached `rec` through the inside of a material that
+/// scatters beneath its surface, until it reaches a surface to shade.
+/// Returns the ray arriving there, its hit and the walk's throughput, or
+/// `None` if the walk was lost.
+pub(crate) fn walk_interior(
+    r: &Ray,
+    rec: HitRecord,
+    scene: &Scene,
+    rand: &mut Rand,
+) -> Option<(Ray, HitRecord, Color)> {
+    let (mut r, mut rec) = (*r, rec);
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    for _ in 0..MAX_WALK_STEPS {
+        let material = scene.get_material(rec.material_id());
+        match material.sample_interior(&r, &rec, rand) {
+            InteriorEvent::Reached { attenuation } => {
+                return Some((r, rec, throughput * attenuation));
+            }
+            InteriorEvent::Scatter {
+                attenuation,
+                scattered,
+            } => {
+                throughput = throughput * attenuation;
+                r = scattered.with_wavelength(r.wavelength());
+                rec = match scene.hit(&r, 0.001, f64::INFINITY) {
+                    DidHit::Hit(rec) => rec,
+                    DidHit::Miss => return None,
+                };
+            }
+        }
+    }
+    None
+}
+
+/// Weight of a sample drawn with density `pdf` from one of two strategies
+/// that could both have produced it, by the power heuristic.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
     }
+}
+
+/// Light reaching `p` from a direction sampled on the background, when it
+/// is not in shadow, and scattered along `r` by `scatter`. `scatter` gives
+/// the scattering function times the cosine for a direction, and the
+/// density of sampling that direction from it, which the background sample
+/// is weighted against as `background_radiance` accounts for.
+fn sample_background(
+    r: &Ray,
+    p: &Point3,
+    scatter: &dyn Fn(&Vec3) -> (Color, f64),
+    scene: &Scene,
+    rand: &mut Rand,
+) -> Color {
+    let none = Color::new(0.0, 0.0, 0.0);
+    let sample = match scene.background().sample(rand) {
+        Some(sample) => sample,
+        None => return none,
+    };
+    let (f, pdf) = scatter(&sample.direction);
+    if f.near_zero() {
+        return none;
+    }
+
+    let shadow = Ray::new(*p, sample.direction).with_wavelength(r.wavelength());
+    if let DidHit::Hit(_) = scene.hit(&shadow, 0.001, f64::INFINITY) {
+        return none;
+    }
+    let transmittance = scene.transmittance(&shadow, 0.001, f64::INFINITY, rand);
+    let weight = power_heuristic(sample.pdf, pdf);
+    (transmittance * weight / sample.pdf) * f * sample.radiance
+}
+
+/// Light reaching `p` from each of the scene's lights that is not in
+/// shadow, scattered along `r` by `scatter` as in `sample_background`.
+fn sample_lights(
+    r: &Ray,
+    p: &Point3,
+    scatter: &dyn Fn(&Vec3) -> (Color, f64),
+    scene: &Scene,
+    rand: &mut Rand,
+) -> Color {
+    let mut total = Color::new(0.0, 0.0, 0.0);
+    for light in scene.lights() {
+        let sample = match light.sample(p, rand) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let (f, _) = scatter(&sample.direction);
+        if f.near_zero() {
+            continue;
+        }
 
-    match scene.hit(r, 0.001, f64::INFINITY) {
-        DidHit::Hit(rec) => {
+        let shadow = Ray::new(*p, sample.direction).with_wavelength(r.wavelength());
+        let t_light = sample.distance - 0.001;
+        if let DidHit::Hit(_) = scene.hit(&shadow, 0.001, t_light) {
+            continue;
+        }
+        let transmittance = scene.transmittance(&shadow, 0.001, t_light, rand);
+        total += transmittance * f * sample.radiance;
+    }
+    total
+}
+
+/// Light arriving at `rec` directly from the background and the lights.
+pub(crate) fn direct_light(r: &Ray, rec: &HitRecord, scene: &Scene, rand: &mut Rand) -> Color {
+    let material = scene.get_material(rec.material_id());
+    let normal = material.shading_normal(r, rec);
+    let scatter = |direction: &Vec3| {
+        let cos_theta = dot(direction, &normal).abs();
+        (
+            cos_theta * material.eval(r, rec, direction),
+            material.pdf(r, rec, direction),
+        )
+    };
+    sample_background(r, &rec.p, &scatter, scene, rand)
+        + sample_lights(r, &rec.p, &scatter, scene, rand)
+}
+
+/// Light arriving directly from the background and the lights at `p`,
+/// where `r` collided with a medium that scatters by `phase`.
+fn medium_direct_light(
+    r: &Ray,
+    p: &Point3,
+    phase: &HenyeyGreenstein,
+    scene: &Scene,
+    rand: &mut Rand,
+) -> Color {
+    let scatter = |direction: &Vec3| {
+        let f = phase.eval(r.direction(), direction);
+        (Color::new(f, f, f), phase.pdf(r.direction(), direction))
+    };
+    sample_background(r, p, &scatter, scene, rand) + sample_lights(r, p, &scatter, scene, rand)
+}
+
+/// The background seen along `r`. If `r` was sampled from a BSDF or phase
+/// function with density `bsdf_pdf`, rather than by a specular event, the
+/// background could also have been sampled directly, so it is weighted
+/// against that.
+pub(crate) fn background_radiance(r: &Ray, scene: &Scene, bsdf_pdf: f64) -> Color {
+    let background = scene.background();
+    let direction = unit_vector(r.direction());
+    let radiance = background.radiance(&direction);
+    if bsdf_pdf > 0.0 {
+        power_heuristic(bsdf_pdf, background.pdf(&direction)) * radiance
+    } else {
+        radiance
+    }
+}
+
+/// The BSDF density to weigh whatever a scattered ray finds against.
+fn mis_pdf(srec: &ScatterRecord) -> f64 {
+    if srec.is_specular() {
+        0.0
+    } else {
+        srec.pdf
+    }
+}
+
+/// Bounces of each kind a path has taken, to end it at the limits set in
+/// the options.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Bounces {
+    diffuse: u8,
+    specular: u8,
+    transmission: u8,
+}
+
+impl Bounces {
+    /// Counts the bounce sampled in `srec`, returning whether the path may
+    /// take it.
+    pub(crate) fn take(&mut self, srec: &ScatterRecord, options: &RaytracerOptions) -> bool {
+        let (count, limit) = if srec.transmission {
+            (&mut self.transmission, options.max_transmission_depth)
+        } else if srec.lobe == Lobe::Diffuse {
+            (&mut self.diffuse, options.max_diffuse_depth)
+        } else {
+            (&mut self.specular, options.max_specular_depth)
+        };
+        *count = count.saturating_add(1);
+        *count <= limit
+    }
+}
+
+/// Russian roulette: past the options' `roulette_depth`, ends paths whose
+/// throughput has dropped to `max_throughput` with probability
+/// `1 - max_throughput`. Returns the chance the path survived with, to
+/// divide the throughput by, or `None` if it was ended.
+pub(crate) fn roulette(
+    max_throughput: f64,
+    depth: usize,
+    options: &RaytracerOptions,
+    rand: &mut Rand,
+) -> Option<f64> {
+    if depth < options.roulette_depth as usize {
+        return Some(1.0);
+    }
+    let survival = max_throughput.min(1.0);
+    if survival <= 0.0 || rand.random_double() >= survival {
+        return None;
+    }
+    Some(survival)
+}
+
+pub(crate) fn max_component(color: &Color) -> f64 {
+    color.x().max(color.y()).max(color.z())
+}
+
+/// Radiance arriving at the camera along `r`, split by how it got there.
+fn ray_passes(r: &Ray, scene: &Scene, options: &RaytracerOptions, rand: &mut Rand) -> LightPasses {
+    let mut passes = LightPasses::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut r = *r;
+    // Density of the BSDF or phase function sample that produced `r`, or
+    // zero if it was not sampled from either.
+    let mut bsdf_pdf = 0.0;
+    let mut bounces = Bounces::default();
+    // Surfaces and media the path has scattered at, and whether the first
+    // of them scattered it diffusely.
+    let mut scatters = 0;
+    let mut diffuse = true;
+
+    for depth in 0..options.max_depth as usize {
+        let surface = scene.hit(&r, 0.001, f64::INFINITY);
+        let t_surface = match &surface {
+            DidHit::Hit(rec) => rec.t,
+            DidHit::Miss => f64::INFINITY,
+        };
+
+        let medium = scene.sample_media(&r, 0.001, t_surface, rand);
+        passes.add(scatters, diffuse, throughput * medium.emitted);
+        // Light sampled where the path scatters is weighted against the
+        // path going on to find it, so it is only gathered where the path
+        // may take another segment.
+        let last = depth + 1 == options.max_depth as usize;
+        if let MediumEvent::Scatter {
+            attenuation,
+            scattered,
+            phase,
+        } = medium.event
+        {
+            if last {
+                break;
+            }
+            throughput = throughput * attenuation;
+            scatters += 1;
+            let direct = medium_direct_light(&r, scattered.origin(), &phase, scene, rand);
+            passes.add(scatters, diffuse, throughput * direct);
+            bsdf_pdf = phase.pdf(r.direction(), scattered.direction());
+            r = scattered;
+        } else {
+            let rec = match surface {
+                DidHit::Hit(rec) => rec,
+                DidHit::Miss => {
+                    let background = background_radiance(&r, scene, bsdf_pdf);
+                    passes.add(scatters, diffuse, throughput * background);
+                    break;
+                }
+            };
+            if last {
+                break;
+            }
+            let (walked, rec, walk) = match walk_interior(&r, rec, scene, rand) {
+                Some(walk) => walk,
+                None => break,
+            };
+            throughput = throughput * walk;
+
+            // Light sampled here has scattered once more, by a lobe only
+            // known once the BSDF is sampled.
             let material = scene.get_material(rec.material_id());
-            let (hit, attenuation, scattered) = material.scatter(r, &rec, rand);
-            if hit {
-                attenuation * ray_color(&scattered, scene, depth - 1, rand)
-            } else {
-                Color::new(0.0, 0.0, 0.0)
+            let srec = match material.sample(&walked, &rec, rand) {
+                Some(srec) => srec,
+                None => {
+                    let direct = direct_light(&walked, &rec, scene, rand);
+                    passes.add(scatters + 1, diffuse, throughput * direct);
+                    break;
+                }
+            };
+            if scatters == 0 {
+                diffuse = srec.lobe == Lobe::Diffuse;
             }
-            // let target = rec.p + rec.normal + random_unit_vector();
-            // 0.5 * ray_color(&Ray::new(rec.p, target - rec.p), world, depth - 1)
+            scatters += 1;
+            if !bounces.take(&srec, options) {
+                break;
+            }
+            let direct = direct_light(&walked, &rec, scene, rand);
+            passes.add(scatters, diffuse, throughput * direct);
+            throughput = throughput * srec.attenuation;
+            bsdf_pdf = mis_pdf(&srec);
+            r = srec.scattered;
         }
-        DidHit::Miss => {
-            let unit_direction = unit_vector(r.direction());
-            let t = 0.5 * (unit_direction.y() + 1.0);
-            (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+
+        match roulette(max_component(&throughput), depth, options, rand) {
+            Some(survival) => throughput /= survival,
+            None => break,
         }
     }
+
+    passes
 }
 
-fn hit_sphere(center: &Point3, radius: f64, r: &Ray) -> f64 {
-    let oc = r.origin() - center;
-    let a = r.direction().length_squared();
-    let half_b = dot(&oc, r.direction());
-    let c = oc.length_squared() - radius * radius;
-    let discriminant = half_b * half_b - a * c;
-    if discriminant < 0.0 {
-        -1.0
-    } else {
-        (-half_b - discriminant.sqrt()) / a
+/// `ray_color` for the wavelengths of a spectral path. Colors are upsampled
+/// as they are met, and events that only hold for the hero wavelength drop
+/// the others.
+fn ray_spectrum(
+    r: &Ray,
+    scene: &Scene,
+    options: &RaytracerOptions,
+    lambda: &mut SampledWavelengths,
+    rand: &mut Rand,
+) -> SampledSpectrum {
+    let mut radiance = SampledSpectrum::constant(0.0);
+    let mut throughput = SampledSpectrum::constant(1.0);
+    let mut r = r.with_wavelength(Some(lambda.hero()));
+    let mut bsdf_pdf = 0.0;
+    let mut bounces = Bounces::default();
+
+    for depth in 0..options.max_depth as usize {
+        let surface = scene.hit(&r, 0.001, f64::INFINITY);
+        let t_surface = match &surface {
+            DidHit::Hit(rec) => rec.t,
+            DidHit::Miss => f64::INFINITY,
+        };
+
+        let medium = scene.sample_media(&r, 0.001, t_surface, rand);
+        radiance += throughput * SampledSpectrum::from_rgb(&medium.emitted, lambda);
+        let last = depth + 1 == options.max_depth as usize;
+        if let MediumEvent::Scatter {
+            attenuation,
+            scattered,
+            phase,
+        } = medium.event
+        {
+            if last {
+                break;
+            }
+            throughput = throughput * SampledSpectrum::from_rgb(&attenuation, lambda);
+            let direct = medium_direct_light(&r, scattered.origin(), &phase, scene, rand);
+            radiance += throughput * SampledSpectrum::from_rgb(&direct, lambda);
+            bsdf_pdf = phase.pdf(r.direction(), scattered.direction());
+            r = scattered;
+        } else {
+            let rec = match surface {
+                DidHit::Hit(rec) => rec,
+                DidHit::Miss => {
+                    let background = background_radiance(&r, scene, bsdf_pdf);
+                    radiance += throughput * SampledSpectrum::from_rgb(&background, lambda);
+                    break;
+                }
+            };
+            if last {
+                break;
+            }
+            let (walked, rec, walk) = match walk_interior(&r, rec, scene, rand) {
+                Some(walk) => walk,
+                None => break,
+            };
+            throughput = throughput * SampledSpectrum::from_rgb(&walk, lambda);
+
+            let material = scene.get_material(rec.material_id());
+            let srec = match material.sample(&walked, &rec, rand) {
+                Some(srec) => srec,
+                None => {
+                    let direct = direct_light(&walked, &rec, scene, rand);
+                    radiance += throughput * SampledSpectrum::from_rgb(&direct, lambda);
+                    break;
+                }
+            };
+            if !bounces.take(&srec, options) {
+                break;
+            }
+            let direct = direct_light(&walked, &rec, scene, rand);
+            radiance += throughput * SampledSpectrum::from_rgb(&direct, lambda);
+            if srec.dispersed {
+                lambda.terminate_secondary();
+            }
+            throughput = throughput * SampledSpectrum::from_rgb(&srec.attenuation, lambda);
+            bsdf_pdf = mis_pdf(&srec);
+            r = srec.scattered;
+        }
+        r = r.with_wavelength(Some(lambda.hero()));
+
+        match roulette(throughput.max_value(), depth, options, rand) {
+            Some(survival) => throughput = (1.0 / survival) * throughput,
+            None => break,
+        }
     }
+
+    radiance
+}
+
+/// How the light reaching the camera is estimated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    /// Paths from the camera, gathering light directly at every bounce.
+    PathTracer,
+    /// Paths from both the camera and the lights, joined in every way they
+    /// can be. Finds caustics that paths from the camera alone rarely do,
+    /// but traces RGB only.
+    Bidirectional,
+    /// Stochastic progressive photon mapping, for caustics seen directly
+    /// or in mirrors, which neither of the others resolves well. Renders
+    /// in passes: each call of `Raytracer::trace_photons` starts one, and
+    /// then `trace_line` takes one sample through each pixel of a line.
+    /// Lines traced before any pass was started start one themselves.
+    /// Traces RGB only.
+    PhotonMapping,
+    /// Primary sample space Metropolis light transport over the path
+    /// tracer, for light that reaches the scene through small openings or
+    /// behind glass. Each line traced runs one Markov chain whose samples
+    /// land all over the image, so only `Raytracer::developed_line` gives
+    /// the result once every line is traced. `samples_per_pixel` sets the
+    /// mutations made per pixel on average.
+    Metropolis,
+    /// White where a ray from the first surface seen escapes within the
+    /// options' `ao_radius`, black where it is occluded.
+    AmbientOcclusion,
+    /// Outward normals of the first surface seen, mapped from -1..1 to
+    /// 0..1 in each channel.
+    Normals,
+    /// Distance to the first surface seen, from white at the camera
+    /// through mid grey at the focus distance to black far away.
+    Depth,
+    /// A distinct color for the material of the first surface seen.
+    MaterialIds,
+    /// How many surface tests each camera ray takes, as a heatmap. There
+    /// is no bounding volume hierarchy, so this counts the objects whose
+    /// bounding boxes the ray crosses, as its leaves would.
+    TraversalCost,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct RaytracerOptions {
     pub image_width: u32,
     pub aspect_ratio: f64,
+    /// Most segments a path can have, counting medium scattering.
     pub max_depth: u8,
+    /// Most bounces off diffuse lobes.
+    pub max_diffuse_depth: u8,
+    /// Most bounces off glossy and specular reflection.
+    pub max_specular_depth: u8,
+    /// Most passes through surfaces.
+    pub max_transmission_depth: u8,
+    /// Bounces before Russian roulette can end a path.
+    pub roulette_depth: u8,
     pub samples_per_pixel: u32,
+    /// Trace wavelengths rather than RGB, for dispersion.
+    pub spectral: bool,
+    pub integrator: Integrator,
+    /// Photons traced in each photon mapping pass.
+    pub photons_per_pass: u32,
+    /// Distance around each pixel's first non-specular surface that
+    /// photons are first gathered from, in scene units. It shrinks over
+    /// the passes.
+    pub photon_radius: f64,
+    /// Paths traced to normalize the Metropolis integrator and start its
+    /// chains from.
+    pub bootstrap_samples: u32,
+    /// Chance of each Metropolis mutation drawing a new path outright
+    /// rather than perturbing the current one.
+    pub large_step_probability: f64,
+    /// Standard deviation of the perturbations of small Metropolis steps,
+    /// in primary sample space.
+    pub mutation_size: f64,
+    /// How far occluders are looked for by the ambient occlusion
+    /// integrator, in scene units.
+    pub ao_radius: f64,
+    /// Whether to record every `Aov` alongside the image, for
+    /// `Raytracer::aov_line`.
+    pub aovs: bool,
 }
 
 impl Default for RaytracerOptions {
@@ -68,7 +510,20 @@ impl Default for RaytracerOptions {
             image_width: 1200,
             aspect_ratio: 3.0 / 2.0,
             max_depth: 50,
+            max_diffuse_depth: 8,
+            max_specular_depth: 16,
+            max_transmission_depth: 16,
+            roulette_depth: 3,
             samples_per_pixel: 500,
+            spectral: false,
+            integrator: Integrator::PathTracer,
+            photons_per_pass: 100_000,
+            photon_radius: 0.1,
+            bootstrap_samples: 100_000,
+            large_step_probability: 0.3,
+            mutation_size: 0.01,
+            ao_radius: 1.0,
+            aovs: false,
         }
     }
 }
@@ -78,10 +533,15 @@ pub struct Raytracer {
     camera: Camera,
     options: RaytracerOptions,
     image_height: u32,
+    bounds: BoundingSphere,
+    film: Film,
+    photons: Option<PhotonMapper>,
+    metropolis: Option<Metropolis>,
+    aovs: Option<AovBuffers>,
 }
 
 impl Raytracer {
-    pub fn new<'a>(scene: Scene, options: &RaytracerOptions) -> Raytracer {
+    pub fn new(scene: Scene, options: &RaytracerOptions) -> Raytracer {
         let aspect_ratio = options.aspect_ratio;
         let image_width = options.image_width;
         let image_height = (image_width as f64 / aspect_ratio) as u32;
@@ -102,39 +562,204 @@ impl Raytracer {
         );
 
         Raytracer {
+            bounds: scene.bounding_sphere(),
             scene,
             camera,
-            options: options.clone(),
+            options: *options,
             image_height,
+            film: Film::new(image_width, image_height, options.samples_per_pixel),
+            photons: match options.integrator {
+                Integrator::PhotonMapping => {
+                    Some(PhotonMapper::new(image_width, image_height, options))
+                }
+                _ => None,
+            },
+            metropolis: match options.integrator {
+                Integrator::Metropolis => Some(Metropolis::new(
+                    options.bootstrap_samples,
+                    options.mutation_size,
+                    options.large_step_probability,
+                )),
+                _ => None,
+            },
+            aovs: options
+                .aovs
+                .then(|| AovBuffers::new(image_width, image_height)),
         }
     }
 
-    pub fn trace_line(&self, y: u32, rand: &mut Rand) -> Vec<u8> {
+    /// Light arriving along the camera ray `r`. The RGB path tracer also
+    /// splits it into `passes`, which other integrators leave alone.
+    fn sample(&self, r: &Ray, passes: &mut LightPasses, rand: &mut Rand) -> Color {
+        let options = &self.options;
+        if let Some(color) = debug_color(r, &self.scene, options, &self.camera, rand) {
+            color
+        } else if options.spectral {
+            let mut lambda = SampledWavelengths::sample(rand);
+            ray_spectrum(r, &self.scene, options, &mut lambda, rand).to_rgb(&lambda)
+        } else {
+            *passes = ray_passes(r, &self.scene, options, rand);
+            passes.total()
+        }
+    }
+
+    /// Film coordinates for a sample through pixel `i` of line `y`.
+    fn film_coordinates(&self, i: usize, y: u32, rand: &mut Rand) -> (f64, f64) {
         let (image_width_f, image_height_f) =
             (self.options.image_width as f64, self.image_height as f64);
+        let u = (i as f64 + rand.random_double()) / (image_width_f - 1.0);
+        let v = (y as f64 + rand.random_double()) / (image_height_f - 1.0);
+        (u, v)
+    }
 
-        let mut line = vec![0; (self.options.image_width as usize * 4)];
+    /// Starts a photon mapping pass by tracing photons from the lights.
+    /// Does nothing for other integrators.
+    pub fn trace_photons(&self, rand: &mut Rand) {
+        if let Some(photons) = &self.photons {
+            photons.trace_photons(&self.scene, &self.options, &self.bounds, rand);
+        }
+    }
+
+    /// Traces line `y` of the image, returning it as RGBA bytes. Light that
+    /// the bidirectional integrator carries from the lights onto other lines
+    /// is only included by `developed_line`.
+    pub fn trace_line(&self, y: u32, rand: &mut Rand) -> Vec<u8> {
+        if let Some(photons) = &self.photons {
+            photons.trace_first_photons(&self.scene, &self.options, &self.bounds, rand);
+            let film_coordinates = |i, rand: &mut Rand| self.film_coordinates(i, y, rand);
+            photons.trace_line(
+                y,
+                &self.scene,
+                &self.camera,
+                &self.options,
+                film_coordinates,
+                rand,
+            );
+            println!("Finished line {}", y);
+            return encode_line(&photons.developed_colors(y, &self.options), 1);
+        }
+
+        if let Some(metropolis) = &self.metropolis {
+            let (width, height) = (self.options.image_width as f64, self.image_height as f64);
+            // A path through anywhere in the image, with the film coordinates
+            // trace_line would give it.
+            let sample = |rand: &mut Rand| {
+                let u = rand.random_double() * width / (width - 1.0);
+                let v = rand.random_double() * height / (height - 1.0);
+                let r = self.camera.get_ray(u, v, rand);
+                let color = self.sample(&r, &mut LightPasses::default(), rand);
+                (color, (u, v))
+            };
+            let mutations =
+                self.options.image_width as usize * self.options.samples_per_pixel as usize;
+            metropolis.run_chain(sample, mutations, &self.film, rand);
+            println!("Finished line {}", y);
+            return self.developed_line(y);
+        }
+
+        let mut sums = Vec::with_capacity(self.options.image_width as usize);
+        let mut aov_line = vec![];
+        let mut splats = vec![];
         let camera = &self.camera;
+        let options = &self.options;
+        let bidirectional = match options.integrator {
+            Integrator::Bidirectional => Some(Bidirectional::new(
+                &self.scene,
+                camera,
+                options,
+                self.bounds,
+            )),
+            _ => None,
+        };
 
         for i in 0..(self.options.image_width as usize) {
             let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-            let (i_f, j_f) = (i as f64, y as f64);
+            let mut aov_pixel = AovPixel::default();
             for _s in 0..self.options.samples_per_pixel {
-                let u = (i_f + rand.random_double()) / (image_width_f - 1.0);
-                let v = (j_f + rand.random_double()) / (image_height_f - 1.0);
+                let (u, v) = self.film_coordinates(i, y, rand);
                 let r = camera.get_ray(u, v, rand);
-
-                pixel_color += ray_color(&r, &self.scene, self.options.max_depth as i32, rand);
+                let mut passes = LightPasses::default();
+                pixel_color += match &bidirectional {
+                    Some(bidirectional) => bidirectional.sample(&r, rand, &mut splats),
+                    None => self.sample(&r, &mut passes, rand),
+                };
+                if self.aovs.is_some() {
+                    aov_pixel.add_sample(&r, &self.scene, &passes);
+                }
             }
+            sums.push(pixel_color);
+            aov_line.push(aov_pixel.average());
+        }
 
-            let rgba = rgba_multisampled(&pixel_color, self.options.samples_per_pixel);
-            line[4 * i] = rgba.0;
-            line[4 * i + 1] = rgba.1;
-            line[4 * i + 2] = rgba.2;
-            line[4 * i + 3] = rgba.3;
+        self.film.set_line(y, &sums);
+        if let Some(aovs) = &self.aovs {
+            aovs.set_line(y, &aov_line);
         }
+        self.film.add_splats(&splats);
         println!("Finished line {}", y);
-        line
+        encode_line(&sums, self.options.samples_per_pixel)
+    }
+
+    /// Line `y` of the finished image as RGBA bytes, including the light
+    /// carried onto it from every traced line. Call once all lines are
+    /// traced.
+    pub fn developed_line(&self, y: u32) -> Vec<u8> {
+        encode_line(&self.developed_colors(y), 1)
+    }
+
+    /// Line `y` of the finished image, linear.
+    fn developed_colors(&self, y: u32) -> Vec<Color> {
+        if let Some(photons) = &self.photons {
+            return photons.developed_colors(y, &self.options);
+        }
+        let (width, height) = (self.options.image_width as f64, self.image_height as f64);
+        let splat_scale = match &self.metropolis {
+            // Chains visit paths in proportion to their brightness.
+            Some(metropolis) => metropolis.brightness(),
+            // Every sample leaves one light path, and each pixel covers
+            // 1 / ((width - 1) * (height - 1)) of the film that the camera's
+            // importance is normalized over.
+            None => (width - 1.0) * (height - 1.0) / (width * height),
+        };
+        self.film.developed_colors(y, splat_scale)
+    }
+
+    /// Line `y` of the finished image as RGBA bytes, with `denoiser`
+    /// applied. The albedo, normal and depth AOVs guide it when the
+    /// options' `aovs` is set; otherwise it can only tell edges by color.
+    /// Call once all lines are traced.
+    pub fn denoised_line(&self, denoiser: &Denoiser, y: u32) -> Vec<u8> {
+        let height = self.image_height;
+        let reach = denoiser.reach();
+        let first = y.saturating_sub(reach);
+        let rows: Vec<Vec<Guide>> = (first..(y + reach + 1).min(height))
+            .map(|row| {
+                let colors = self.developed_colors(row);
+                let features = self.aovs.as_ref().map(|aovs| {
+                    [Aov::Albedo, Aov::Normal, Aov::Depth].map(|aov| aovs.line(aov, row))
+                });
+                colors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, color)| match &features {
+                        Some([albedo, normal, depth]) => {
+                            Guide::new(*color, albedo[i], normal[i], depth[i].x())
+                        }
+                        None => Guide::from_color(*color),
+                    })
+                    .collect()
+            })
+            .collect();
+        let line = denoiser.filter_line(&rows, (y - first) as usize);
+        encode_line(&line, 1)
+    }
+
+    /// `aov` for each pixel of line `y`, linear and unclamped, or `None`
+    /// unless the options' `aovs` is set. Only integrators that trace
+    /// samples through each pixel record AOVs, and only the RGB path tracer
+    /// splits its light into the light AOVs.
+    pub fn aov_line(&self, aov: Aov, y: u32) -> Option<Vec<Color>> {
+        self.aovs.as_ref().map(|aovs| aovs.line(aov, y))
     }
 }
 
@@ -203,3 +828,132 @@ pub fn random_scene(rand: &mut Rand) -> Scene {
 
     scene
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{background::SkyGradient, material::Material, sphere::Sphere};
+
+    fn srec(lobe: Lobe, transmission: bool) -> ScatterRecord {
+        ScatterRecord {
+            scattered: Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+            attenuation: Color::new(1.0, 1.0, 1.0),
+            pdf: 0.0,
+            lobe,
+            transmission,
+            dispersed: false,
+        }
+    }
+
+    #[test]
+    fn bounces_are_limited_per_kind() {
+        let options = RaytracerOptions {
+            max_diffuse_depth: 2,
+            max_specular_depth: 1,
+            max_transmission_depth: 3,
+            ..Default::default()
+        };
+        let mut bounces = Bounces::default();
+        assert!(bounces.take(&srec(Lobe::Diffuse, false), &options));
+        assert!(bounces.take(&srec(Lobe::Diffuse, false), &options));
+        assert!(bounces.take(&srec(Lobe::Glossy, false), &options));
+        // Transmission is counted on its own whatever the lobe.
+        for _ in 0..3 {
+            assert!(bounces.take(&srec(Lobe::Specular, true), &options));
+        }
+        assert!(!bounces.take(&srec(Lobe::Diffuse, true), &options));
+        assert!(!bounces.take(&srec(Lobe::Diffuse, false), &options));
+        assert!(!bounces.take(&srec(Lobe::Specular, false), &options));
+    }
+
+    #[test]
+    fn roulette_only_starts_at_its_depth() {
+        let options = RaytracerOptions {
+            roulette_depth: 3,
+            ..Default::default()
+        };
+        let mut rand = Rand::new_with_seed(1);
+        assert_eq!(roulette(0.0, 2, &options, &mut rand), Some(1.0));
+        assert_eq!(roulette(0.0, 3, &options, &mut rand), None);
+        assert_eq!(roulette(2.0, 3, &options, &mut rand), Some(1.0));
+
+        let n = 10_000;
+        let survived = (0..n)
+            .filter_map(|_| roulette(0.25, 5, &options, &mut rand))
+            .inspect(|&survival| assert_eq!(survival, 0.25))
+            .count();
+        assert!((survived as f64 / n as f64 - 0.25).abs() < 0.02);
+    }
+
+    /// Mean radiance of rays at a sphere of `material` under a uniform
+    /// white sky. The sphere cannot see itself, so light reflects off it
+    /// once.
+    fn furnace(material: Box<dyn Material + Send + Sync>, options: &RaytracerOptions) -> Color {
+        let mut scene = Scene::new();
+        let white = Color::new(1.0, 1.0, 1.0);
+        scene.set_background(Box::new(SkyGradient::new(white, white)));
+        let id = scene.add_material(material);
+        scene.add_object(Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, id)));
+
+        let mut rand = Rand::new_with_seed(2);
+        let n = 20_000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let y = -0.9 + 1.8 * (i as f64 + 0.5) / n as f64;
+            let r = Ray::new(Point3::new(-5.0, y, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            total += ray_passes(&r, &scene, options, &mut rand).total();
+        }
+        total / n as f64
+    }
+
+    #[test]
+    fn roulette_leaves_the_furnace_unbiased() {
+        let options = RaytracerOptions {
+            roulette_depth: 0,
+            ..Default::default()
+        };
+        let grey = furnace(
+            Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            &options,
+        );
+        assert!((grey.x() - 0.5).abs() < 0.02, "{:?}", grey);
+        // Clear glass loses nothing however often light bounces inside.
+        let glass = furnace(Box::new(Dielectric::new(1.5)), &options);
+        assert!((glass.x() - 1.0).abs() < 1e-9, "{:?}", glass);
+    }
+
+    #[test]
+    fn depth_limits_end_paths() {
+        let options = RaytracerOptions {
+            max_transmission_depth: 1,
+            ..Default::default()
+        };
+        // Light cannot leave the glass after entering it, so only rays
+        // reflected off it see the sky.
+        let glass = furnace(Box::new(Dielectric::new(1.5)), &options);
+        assert!(glass.x() > 0.02 && glass.x() < 0.2, "{:?}", glass);
+    }
+
+    #[test]
+    fn photon_mapping_starts_a_pass_when_none_was() {
+        let mut scene = Scene::new();
+        let white = Color::new(1.0, 1.0, 1.0);
+        scene.set_background(Box::new(SkyGradient::new(white, white)));
+        let grey = scene.add_material(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        scene.add_object(Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, grey)));
+        let raytracer = Raytracer::new(
+            scene,
+            &RaytracerOptions {
+                image_width: 8,
+                aspect_ratio: 1.0,
+                integrator: Integrator::PhotonMapping,
+                photons_per_pass: 10_000,
+                ..Default::default()
+            },
+        );
+        let mut rand = Rand::new_with_seed(5);
+        // The sky is seen past the sphere, and its photons light the sphere.
+        let line = raytracer.trace_line(4, &mut rand);
+        assert!(line.chunks(4).all(|rgba| rgba[0] > 0), "{:?}", line);
+    }
+}
@@ -1,21 +1,42 @@
 use crate::{
+    aabb::Aabb,
+    background::{Background, SkyGradient},
     hittable::{DidHit, HitRecord, Hittable},
-    hittable_list::HittableList,
+    hittable_list::surrounding_box,
+    light::{BoundingSphere, Light},
     material::Material,
+    medium::{Medium, MediumEvent, MediumSample},
+    rand::Rand,
     ray::Ray,
+    vec3::Point3,
 };
 
 pub struct Scene {
     materials: Vec<Box<dyn Material + Sync + Send>>,
     objects: Vec<Box<dyn Hittable + Sync + Send>>,
+    media: Vec<Box<dyn Medium + Sync + Send>>,
+    background: Box<dyn Background + Sync + Send>,
+    lights: Vec<Box<dyn Light + Sync + Send>>,
 }
 pub type MaterialId = i32;
 
+/// How far past a cut out hit the search for the next surface resumes.
+const CUTOUT_SKIP: f64 = 1e-6;
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Scene {
     pub fn new() -> Self {
         Scene {
             materials: vec![],
             objects: vec![],
+            media: vec![],
+            background: Box::new(SkyGradient::default()),
+            lights: vec![],
         }
     }
 
@@ -28,36 +49,152 @@ impl Scene {
         self.objects.push(object)
     }
 
-    pub fn get_material<'a>(
-        &'a self,
-        material_id: MaterialId,
-    ) -> &'a Box<dyn Material + Send + Sync> {
-        let material_id = TryInto::<usize>::try_into(material_id).unwrap();
-        self.materials.get(material_id).unwrap()
+    /// Adds a participating medium. Media are assumed not to overlap.
+    pub fn add_medium(&mut self, medium: Box<dyn Medium + Sync + Send>) {
+        self.media.push(medium)
     }
-}
 
-impl Hittable for Scene {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
-        let mut temp_rec = HitRecord::default();
-        let mut hit_anything = false;
+    /// Adds a light that is not part of the scene's geometry.
+    pub fn add_light(&mut self, light: Box<dyn Light + Sync + Send>) {
+        self.lights.push(light)
+    }
+
+    pub fn lights(&self) -> &[Box<dyn Light + Sync + Send>] {
+        &self.lights
+    }
+
+    /// Replaces the default sky gradient seen by rays leaving the scene.
+    pub fn set_background(&mut self, background: Box<dyn Background + Sync + Send>) {
+        self.background = background
+    }
+
+    pub fn background(&self) -> &(dyn Background + Send + Sync) {
+        self.background.as_ref()
+    }
+
+    /// A sphere around all the objects, or a large one about the origin if
+    /// some are unbounded.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        match self.bounding_box() {
+            Some(aabb) => BoundingSphere {
+                centre: 0.5 * (aabb.min() + aabb.max()),
+                radius: 0.5 * (aabb.max() - aabb.min()).length(),
+            },
+            None => BoundingSphere {
+                centre: Point3::new(0.0, 0.0, 0.0),
+                radius: 1e4,
+            },
+        }
+    }
+
+    /// The nearest hit along `r`, with the index of the object hit in the
+    /// order objects were added.
+    pub(crate) fn hit_object(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(usize, HitRecord)> {
+        self.nearest_hit(r, t_min, t_max).0
+    }
+
+    pub(crate) fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// How many surface tests finding the nearest hit along `r` takes,
+    /// counting the retests past cut out hits.
+    pub(crate) fn hit_tests(&self, r: &Ray, t_min: f64, t_max: f64) -> usize {
+        self.nearest_hit(r, t_min, t_max).1
+    }
+
+    /// The nearest hit along `r` and the index of the object hit, with the
+    /// number of surface tests it took. Only objects whose bounding boxes
+    /// the ray crosses are tested.
+    fn nearest_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> (Option<(usize, HitRecord)>, usize) {
+        let mut nearest = None;
         let mut closest_so_far = t_max;
+        let mut tests = 0;
 
-        for obj in &self.objects {
-            match obj.hit(r, t_min, closest_so_far) {
-                DidHit::Hit(rec) => {
-                    hit_anything = true;
-                    temp_rec = rec;
-                    closest_so_far = temp_rec.t;
+        for (index, obj) in self.objects.iter().enumerate() {
+            if let Some(bbox) = obj.bounding_box() {
+                if !bbox.hit(r, t_min, closest_so_far) {
+                    continue;
+                }
+            }
+            // Hits on cut out parts of a surface are skipped by searching
+            // the same object again just beyond them.
+            let mut t_from = t_min;
+            loop {
+                tests += 1;
+                let rec = match obj.hit(r, t_from, closest_so_far) {
+                    DidHit::Hit(rec) => rec,
+                    DidHit::Miss => break,
+                };
+                if self.get_material(rec.material_id()).is_cut_out(r, &rec) {
+                    t_from = rec.t + CUTOUT_SKIP;
+                    continue;
                 }
-                _ => {}
+                closest_so_far = rec.t;
+                nearest = Some((index, rec));
+                break;
+            }
+        }
+
+        (nearest, tests)
+    }
+
+    pub fn get_material(&self, material_id: MaterialId) -> &(dyn Material + Send + Sync) {
+        let material_id = TryInto::<usize>::try_into(material_id).unwrap();
+        self.materials.get(material_id).unwrap().as_ref()
+    }
+
+    /// Tracks `r` through every medium it crosses within `t_min..t_max`,
+    /// nearest first, stopping at the first real collision.
+    pub fn sample_media(&self, r: &Ray, t_min: f64, t_max: f64, rand: &mut Rand) -> MediumSample {
+        if self.media.is_empty() {
+            return MediumSample::passed();
+        }
+
+        let mut crossed: Vec<(f64, &(dyn Medium + Sync + Send))> = self
+            .media
+            .iter()
+            .filter_map(|m| m.interval(r, t_min, t_max).map(|(t0, _)| (t0, m.as_ref())))
+            .collect();
+        crossed.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut emitted = MediumSample::passed().emitted;
+        for (_, medium) in crossed {
+            let sample = medium.sample(r, t_min, t_max, rand);
+            emitted += sample.emitted;
+            if let MediumEvent::Scatter { .. } = sample.event {
+                return MediumSample {
+                    emitted,
+                    event: sample.event,
+                };
             }
         }
 
-        if hit_anything {
-            DidHit::Hit(temp_rec)
-        } else {
-            DidHit::Miss
+        MediumSample {
+            emitted,
+            event: MediumEvent::Passed,
+        }
+    }
+
+    /// Estimates the fraction of light that survives travelling along `r`
+    /// through every medium within `t_min..t_max`.
+    pub fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64, rand: &mut Rand) -> f64 {
+        self.media
+            .iter()
+            .map(|m| m.transmittance(r, t_min, t_max, rand))
+            .product()
+    }
+}
+
+impl Hittable for Scene {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        match self.hit_object(r, t_min, t_max) {
+            Some((_, rec)) => DidHit::Hit(rec),
+            None => DidHit::Miss,
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        surrounding_box(&self.objects)
+    }
 }
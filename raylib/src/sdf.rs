@@ -0,0 +1,450 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{DidHit, HitRecord, Hittable},
+    ray::Ray,
+    scene::MaterialId,
+    vec3::{dot, unit_vector, Point3},
+    Vec3,
+};
+
+/// A signed distance field: negative inside, positive outside, zero on the
+/// surface. Distances may underestimate but must never overestimate the
+/// true distance, or sphere tracing will step through the surface.
+pub trait Sdf {
+    fn distance(&self, p: &Point3) -> f64;
+}
+
+pub type BoxedSdf = Box<dyn Sdf + Send + Sync>;
+
+fn abs(v: &Vec3) -> Vec3 {
+    Vec3::new(v.x().abs(), v.y().abs(), v.z().abs())
+}
+
+fn max0(v: &Vec3) -> Vec3 {
+    Vec3::new(v.x().max(0.0), v.y().max(0.0), v.z().max(0.0))
+}
+
+fn mix(a: f64, b: f64, h: f64) -> f64 {
+    a * (1.0 - h) + b * h
+}
+
+/// Sphere centered at the origin.
+pub struct SdfSphere {
+    radius: f64,
+}
+
+impl SdfSphere {
+    pub fn new(radius: f64) -> Self {
+        SdfSphere { radius }
+    }
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &Point3) -> f64 {
+        p.length() - self.radius
+    }
+}
+
+/// Axis-aligned box centered at the origin.
+pub struct SdfBox {
+    half_extents: Vec3,
+}
+
+impl SdfBox {
+    pub fn new(half_extents: Vec3) -> Self {
+        SdfBox { half_extents }
+    }
+}
+
+fn box_distance(p: &Point3, half_extents: &Vec3) -> f64 {
+    let q = abs(p) - half_extents;
+    max0(&q).length() + q.x().max(q.y()).max(q.z()).min(0.0)
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: &Point3) -> f64 {
+        box_distance(p, &self.half_extents)
+    }
+}
+
+/// Box centered at the origin whose edges are rounded by `radius`. The
+/// overall size still matches `half_extents`.
+pub struct SdfRoundedBox {
+    half_extents: Vec3,
+    radius: f64,
+}
+
+impl SdfRoundedBox {
+    pub fn new(half_extents: Vec3, radius: f64) -> Self {
+        SdfRoundedBox {
+            half_extents,
+            radius,
+        }
+    }
+}
+
+impl Sdf for SdfRoundedBox {
+    fn distance(&self, p: &Point3) -> f64 {
+        let inner = self.half_extents - Vec3::new(self.radius, self.radius, self.radius);
+        box_distance(p, &inner) - self.radius
+    }
+}
+
+/// Torus centered at the origin lying in the xz plane.
+pub struct SdfTorus {
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+impl SdfTorus {
+    pub fn new(major_radius: f64, minor_radius: f64) -> Self {
+        SdfTorus {
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: &Point3) -> f64 {
+        let qx = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (qx * qx + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+}
+
+/// Capsule around the segment from `a` to `b`.
+pub struct SdfCapsule {
+    a: Point3,
+    b: Point3,
+    radius: f64,
+}
+
+impl SdfCapsule {
+    pub fn new(a: Point3, b: Point3, radius: f64) -> Self {
+        SdfCapsule { a, b, radius }
+    }
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: &Point3) -> f64 {
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let h = (dot(&pa, &ba) / dot(&ba, &ba)).clamp(0.0, 1.0);
+        (pa - ba * h).length() - self.radius
+    }
+}
+
+/// Moves a field so its origin sits at `offset`.
+pub struct Translate {
+    sdf: BoxedSdf,
+    offset: Vec3,
+}
+
+impl Translate {
+    pub fn new(sdf: BoxedSdf, offset: Vec3) -> Self {
+        Translate { sdf, offset }
+    }
+}
+
+impl Sdf for Translate {
+    fn distance(&self, p: &Point3) -> f64 {
+        self.sdf.distance(&(p - self.offset))
+    }
+}
+
+/// Union of two fields blended over a distance of roughly `k`.
+pub struct SmoothUnion {
+    a: BoxedSdf,
+    b: BoxedSdf,
+    k: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(a: BoxedSdf, b: BoxedSdf, k: f64) -> Self {
+        SmoothUnion { a, b, k }
+    }
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: &Point3) -> f64 {
+        let (d1, d2) = (self.a.distance(p), self.b.distance(p));
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+        mix(d2, d1, h) - self.k * h * (1.0 - h)
+    }
+}
+
+/// Carves `cut` out of `base`, blending the edge over roughly `k`.
+pub struct SmoothSubtraction {
+    base: BoxedSdf,
+    cut: BoxedSdf,
+    k: f64,
+}
+
+impl SmoothSubtraction {
+    pub fn new(base: BoxedSdf, cut: BoxedSdf, k: f64) -> Self {
+        SmoothSubtraction { base, cut, k }
+    }
+}
+
+impl Sdf for SmoothSubtraction {
+    fn distance(&self, p: &Point3) -> f64 {
+        let (d1, d2) = (self.cut.distance(p), self.base.distance(p));
+        let h = (0.5 - 0.5 * (d2 + d1) / self.k).clamp(0.0, 1.0);
+        mix(d2, -d1, h) + self.k * h * (1.0 - h)
+    }
+}
+
+/// Infinitely repeats a field with the given period along each axis. An
+/// axis with a period of zero is not repeated.
+pub struct Repeat {
+    sdf: BoxedSdf,
+    period: Vec3,
+}
+
+impl Repeat {
+    pub fn new(sdf: BoxedSdf, period: Vec3) -> Self {
+        Repeat { sdf, period }
+    }
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: &Point3) -> f64 {
+        let wrap = |x: f64, c: f64| if c > 0.0 { x - c * (x / c).round() } else { x };
+        let q = Vec3::new(
+            wrap(p.x(), self.period.x()),
+            wrap(p.y(), self.period.y()),
+            wrap(p.z(), self.period.z()),
+        );
+        self.sdf.distance(&q)
+    }
+}
+
+/// Twists a field around the y axis by `k` radians per unit of height.
+///
+/// Twisting stretches space, so the result can overestimate distances;
+/// lower the step scale of the `SdfObject` when rendering strong twists.
+pub struct Twist {
+    sdf: BoxedSdf,
+    k: f64,
+}
+
+impl Twist {
+    pub fn new(sdf: BoxedSdf, k: f64) -> Self {
+        Twist { sdf, k }
+    }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: &Point3) -> f64 {
+        let (s, c) = (self.k * p.y()).sin_cos();
+        let q = Vec3::new(c * p.x() - s * p.z(), p.y(), s * p.x() + c * p.z());
+        self.sdf.distance(&q)
+    }
+}
+
+/// Renders an `Sdf` as a `Hittable` by sphere tracing.
+pub struct SdfObject {
+    sdf: BoxedSdf,
+    /// Box the surface lies within, to which marching is limited.
+    bounds: Aabb,
+    material_id: MaterialId,
+    /// Distance from the surface at which a step counts as a hit.
+    epsilon: f64,
+    max_steps: u32,
+    /// Rays that travel further than this without hitting are misses.
+    max_distance: f64,
+    /// Fraction of the field distance taken per step, for fields that
+    /// overestimate distances.
+    step_scale: f64,
+}
+
+impl SdfObject {
+    /// `bounds` must enclose the whole surface; fields that repeat or
+    /// extend forever are cut off at it.
+    pub fn new(sdf: BoxedSdf, bounds: Aabb, material_id: MaterialId) -> Self {
+        SdfObject {
+            sdf,
+            bounds,
+            material_id,
+            epsilon: 1e-4,
+            max_steps: 256,
+            max_distance: 1000.0,
+            step_scale: 1.0,
+        }
+    }
+
+    pub fn set_epsilon(&mut self, epsilon: f64) {
+        self.epsilon = epsilon
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps
+    }
+
+    pub fn set_max_distance(&mut self, max_distance: f64) {
+        self.max_distance = max_distance
+    }
+
+    pub fn set_step_scale(&mut self, step_scale: f64) {
+        self.step_scale = step_scale
+    }
+
+    /// Gradient of the field by central differences on a tetrahedron.
+    fn normal(&self, p: &Point3) -> Vec3 {
+        let h = self.epsilon;
+        let k = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        let n = k.iter().fold(Vec3::new(0.0, 0.0, 0.0), |n, k| {
+            n + k * self.sdf.distance(&(p + h * k))
+        });
+        unit_vector(&n)
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        let ray_length = r.direction().length();
+        let t_max = t_max.min(self.max_distance / ray_length);
+        let (mut t, t_max) = match self.bounds.intersect(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return DidHit::Miss,
+        };
+        // A ray leaving a surface starts within epsilon of it, and must get
+        // clear of it before a hit can count.
+        let mut leaving = t <= t_min;
+
+        for _ in 0..self.max_steps {
+            let p = r.at(t);
+            // Rays starting inside the surface march outward the same way.
+            let d = self.sdf.distance(&p).abs();
+            if d < self.epsilon && !leaving {
+                let mut rec = HitRecord {
+                    t,
+                    p,
+                    ..Default::default()
+                };
+                rec.set_face_normal(r, &self.normal(&p));
+                rec.set_material_id(self.material_id);
+                return DidHit::Hit(rec);
+            }
+            if leaving {
+                // Steps of the distance alone would stall at the surface.
+                t += d.max(self.epsilon) / ray_length;
+                leaving = d < self.epsilon;
+            } else {
+                t += self.step_scale * d / ray_length;
+            }
+            if t > t_max {
+                break;
+            }
+        }
+
+        DidHit::Miss
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_sphere() -> SdfObject {
+        let bounds = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        SdfObject::new(Box::new(SdfSphere::new(1.0)), bounds, 3)
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let p = Point3::new(3.0, 0.0, 0.0);
+        assert!((SdfSphere::new(1.0).distance(&p) - 2.0).abs() < 1e-12);
+        let cube = SdfBox::new(Vec3::new(1.0, 1.0, 1.0));
+        assert!((cube.distance(&p) - 2.0).abs() < 1e-12);
+        assert!((cube.distance(&Point3::new(0.0, 0.0, 0.0)) + 1.0).abs() < 1e-12);
+        // Corners are as far as the Euclidean distance to them.
+        let corner = Point3::new(2.0, 2.0, 2.0);
+        assert!((cube.distance(&corner) - 3f64.sqrt()).abs() < 1e-12);
+        let torus = SdfTorus::new(2.0, 0.5);
+        assert!(torus.distance(&Point3::new(2.0, 0.0, 0.0)) + 0.5 < 1e-12);
+        assert!((torus.distance(&Point3::new(0.0, 0.0, 0.0)) - 1.5).abs() < 1e-12);
+        let capsule = SdfCapsule::new(Point3::new(0.0, -1.0, 0.0), Point3::new(0.0, 1.0, 0.0), 0.5);
+        assert!((capsule.distance(&Point3::new(0.0, 3.0, 0.0)) - 1.5).abs() < 1e-12);
+        assert!((capsule.distance(&Point3::new(2.0, 0.0, 0.0)) - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn operators_move_and_repeat_fields() {
+        let moved = Translate::new(Box::new(SdfSphere::new(1.0)), Vec3::new(5.0, 0.0, 0.0));
+        assert!((moved.distance(&Point3::new(5.0, 0.0, 0.0)) + 1.0).abs() < 1e-12);
+        let repeated = Repeat::new(Box::new(SdfSphere::new(1.0)), Vec3::new(4.0, 0.0, 0.0));
+        let here = repeated.distance(&Point3::new(0.5, 0.0, 0.0));
+        let there = repeated.distance(&Point3::new(8.5, 0.0, 0.0));
+        assert!((here - there).abs() < 1e-12);
+        // A smooth union is never further than either field.
+        let union = SmoothUnion::new(
+            Box::new(SdfSphere::new(1.0)),
+            Box::new(Translate::new(
+                Box::new(SdfSphere::new(1.0)),
+                Vec3::new(1.5, 0.0, 0.0),
+            )),
+            0.5,
+        );
+        let p = Point3::new(0.75, 1.0, 0.0);
+        assert!(union.distance(&p) <= SdfSphere::new(1.0).distance(&p));
+    }
+
+    #[test]
+    fn sphere_tracing_finds_the_surface() {
+        let sphere = unit_sphere();
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        match sphere.hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                assert!((rec.t - 2.0).abs() < 1e-3);
+                assert!((rec.normal.x() + 1.0).abs() < 1e-3);
+                assert!(rec.front_face);
+                assert_eq!(rec.material_id(), 3);
+            }
+            DidHit::Miss => panic!("missed the sphere"),
+        }
+    }
+
+    #[test]
+    fn sphere_tracing_misses() {
+        let sphere = unit_sphere();
+        let beside = Ray::new(Point3::new(-5.0, 1.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(matches!(
+            sphere.hit(&beside, 0.001, f64::INFINITY),
+            DidHit::Miss
+        ));
+        let short = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(matches!(sphere.hit(&short, 0.001, 3.0), DidHit::Miss));
+    }
+
+    #[test]
+    fn rays_leaving_the_surface_find_the_far_side() {
+        let sphere = unit_sphere();
+        // Starting on the near side and heading through the sphere.
+        let r = Ray::new(Point3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        match sphere.hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => {
+                assert!((rec.t - 2.0).abs() < 1e-3);
+                assert!(!rec.front_face);
+            }
+            DidHit::Miss => panic!("missed the far side"),
+        }
+    }
+
+    #[test]
+    fn rays_grazing_away_from_the_surface_escape() {
+        let sphere = unit_sphere();
+        // Nearly tangent and outward, with a direction far from unit length.
+        let r = Ray::new(Point3::new(-1.0, 0.0, 0.0), Vec3::new(-0.5, 10.0, 0.0));
+        assert!(matches!(sphere.hit(&r, 1e-5, f64::INFINITY), DidHit::Miss));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    background::{Background, BackgroundSample},
+    onb::Onb,
+    rand::Rand,
+    spectrum::xyz_to_linear_srgb,
+    util::PI,
+    vec3::{dot, random_cone_direction, unit_vector, Color},
+    Vec3,
+};
+
+/// Radiance per kcd/m² of luminance in the sky model.
+const SKY_SCALE: f64 = 0.04;
+
+/// Luminance of the sun above the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+/// Angular radius of the sun's disk in radians.
+const SUN_RADIUS: f64 = 0.00465;
+
+/// Wavelengths in micrometres standing in for the red, green and blue
+/// channels when attenuating sunlight.
+const CHANNEL_WAVELENGTHS: [f64; 3] = [0.630, 0.532, 0.465];
+
+/// Perez's luminance distribution, in terms of the angle from the zenith
+/// and the angle from the sun.
+#[derive(Debug, Clone, Copy)]
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / cos_theta.max(1e-3)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+/// A clear sky by the analytic model of Preetham, Shirley and Smits
+/// (1999), with the sun's disk as a light that is sampled directly. The
+/// ground below the horizon is a diffuse plane lit by both.
+pub struct PhysicalSky {
+    /// Unit vector towards the sun.
+    sun: Vec3,
+    ground_albedo: Color,
+    /// Perez distributions for luminance and the two chromaticities.
+    perez: [Perez; 3],
+    /// Luminance and chromaticity at the zenith, divided by the Perez
+    /// distributions there.
+    zenith: [f64; 3],
+    sun_radiance: Color,
+    /// Irradiance of an upward facing plane from the sky and sun.
+    ground_irradiance: Color,
+}
+
+impl PhysicalSky {
+    /// A sky with the sun towards `sun_direction` seen through air of the
+    /// given `turbidity`, from 2 for a very clear day to about 10 for haze.
+    pub fn new(sun_direction: Vec3, turbidity: f64) -> Self {
+        let sun = unit_vector(&sun_direction);
+        let t = turbidity.clamp(2.0, 10.0);
+        // The model is only fitted for the sun above the horizon.
+        let theta_s = sun.y().clamp(0.0, 1.0).acos().min(0.5 * PI - 1e-3);
+
+        let perez = [
+            Perez([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            Perez([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            Perez([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let ts = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f64; 4]| (0..4).map(|i| r[i] * ts[i]).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let zenith = [zenith_luminance.max(0.0), zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|i| zenith[i] / perez[i].eval(1.0, theta_s));
+
+        let mut sky = PhysicalSky {
+            sun,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            perez,
+            zenith,
+            sun_radiance: sun_radiance(theta_s, t),
+            ground_irradiance: Color::new(0.0, 0.0, 0.0),
+        };
+        if sun.y() <= 0.0 {
+            sky.sun_radiance = Color::new(0.0, 0.0, 0.0);
+        }
+        sky.ground_irradiance = sky.irradiance();
+        sky
+    }
+
+    /// Color of the diffuse ground below the horizon.
+    pub fn set_ground_albedo(&mut self, albedo: Color) {
+        self.ground_albedo = albedo
+    }
+
+    fn cos_sun_radius() -> f64 {
+        SUN_RADIUS.cos()
+    }
+
+    /// Radiance of the sky alone along the unit vector `direction`, which
+    /// must be above the horizon.
+    fn sky(&self, direction: &Vec3) -> Color {
+        let gamma = dot(direction, &self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * self.perez[i].eval(direction.y(), gamma));
+        if y <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let luminance = SKY_SCALE * luminance;
+        let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = xyz_to_linear_srgb(&xyz);
+        Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    /// Irradiance of an upward facing plane, integrating the sky
+    /// numerically and adding the sun.
+    fn irradiance(&self) -> Color {
+        const STEPS: usize = 64;
+        let d_theta = 0.5 * PI / STEPS as f64;
+        let d_phi = 2.0 * PI / (2 * STEPS) as f64;
+        let mut irradiance = Color::new(0.0, 0.0, 0.0);
+        for i in 0..STEPS {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..2 * STEPS {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let weight = theta.cos() * theta.sin() * d_theta * d_phi;
+                irradiance += weight * self.sky(&direction);
+            }
+        }
+        let sun_solid_angle = 2.0 * PI * (1.0 - PhysicalSky::cos_sun_radius());
+        irradiance + (sun_solid_angle * self.sun.y().max(0.0)) * self.sun_radiance
+    }
+}
+
+/// Sunlight after extinction by Rayleigh and aerosol scattering along the
+/// path through the atmosphere at zenith angle `theta_s`.
+fn sun_radiance(theta_s: f64, turbidity: f64) -> Color {
+    // Kasten's relative optical air mass.
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = CHANNEL_WAVELENGTHS.map(|lambda| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    });
+    let [r, g, b] = transmittance.map(|t| SKY_SCALE * SUN_LUMINANCE * t);
+    Color::new(r, g, b)
+}
+
+impl Background for PhysicalSky {
+    fn radiance(&self, direction: &Vec3) -> Color {
+        let direction = unit_vector(direction);
+        if direction.y() <= 0.0 {
+            return self.ground_albedo * self.ground_irradiance / PI;
+        }
+        let mut radiance = self.sky(&direction);
+        if dot(&direction, &self.sun) >= PhysicalSky::cos_sun_radius() {
+            radiance += self.sun_radiance;
+        }
+        radiance
+    }
+
+    fn sample(&self, rand: &mut Rand) -> Option<BackgroundSample> {
+        if self.sun.y() <= 0.0 {
+            return None;
+        }
+        // Uniformly within the cone subtended by the sun.
+        let local = random_cone_direction(PhysicalSky::cos_sun_radius(), rand);
+        let direction = Onb::build_from_w(&self.sun).local(&local);
+        Some(BackgroundSample {
+            direction,
+            radiance: self.radiance(&direction),
+            pdf: self.pdf(&direction),
+        })
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let cos_max = PhysicalSky::cos_sun_radius();
+        if self.sun.y() <= 0.0 || dot(&unit_vector(direction), &self.sun) < cos_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - cos_max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn afternoon() -> PhysicalSky {
+        PhysicalSky::new(Vec3::new(1.0, 1.0, 0.5), 3.0)
+    }
+
+    #[test]
+    fn samples_land_on_the_sun() {
+        let sky = afternoon();
+        let mut rand = Rand::new_with_seed(1);
+        let cone = 1.0 / (2.0 * PI * (1.0 - PhysicalSky::cos_sun_radius()));
+        for _ in 0..100 {
+            let sample = sky.sample(&mut rand).unwrap();
+            assert!(dot(&sample.direction, &sky.sun) >= PhysicalSky::cos_sun_radius() - 1e-12);
+            assert_eq!(sample.pdf, cone);
+            // The sun outshines the sky around it.
+            assert!(sample.radiance.y() > 100.0 * sky.sky(&sample.direction).y());
+        }
+        assert_eq!(sky.pdf(&Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn sky_is_bluer_and_brighter_towards_the_sun() {
+        let sky = afternoon();
+        let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z() > zenith.x(), "{:?}", zenith);
+        let near_sun = sky.radiance(&unit_vector(&Vec3::new(1.0, 0.9, 0.5)));
+        let away = sky.radiance(&unit_vector(&Vec3::new(-1.0, 0.9, -0.5)));
+        assert!(near_sun.y() > away.y());
+    }
+
+    #[test]
+    fn ground_reflects_the_sky_and_sun() {
+        let mut sky = afternoon();
+        sky.set_ground_albedo(Color::new(0.5, 0.5, 0.5));
+        let ground = sky.radiance(&Vec3::new(0.0, -1.0, 0.0));
+        let expected = 0.5 * sky.ground_irradiance / PI;
+        assert!((ground - expected).length() < 1e-12);
+        assert!(ground.y() > 0.0);
+    }
+
+    #[test]
+    fn sun_below_the_horizon_is_not_sampled() {
+        let sky = PhysicalSky::new(Vec3::new(1.0, -0.2, 0.0), 3.0);
+        let mut rand = Rand::new_with_seed(2);
+        assert!(sky.sample(&mut rand).is_none());
+        assert_eq!(sky.sun_radiance.y(), 0.0);
+    }
+}
@@ -0,0 +1,363 @@
+//! Spectral rendering with hero wavelength sampling. Each camera path
+//! carries a handful of wavelengths spread evenly over the visible range,
+//! RGB colors are upsampled to spectra where they are used and the film
+//! converts the result back through CIE XYZ to sRGB.
+
+use overload::overload;
+use std::ops;
+
+use crate::{rand::Rand, vec3::Color, Vec3};
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Wavelengths traced together along a path.
+pub const SPECTRUM_SAMPLES: usize = 4;
+
+/// The wavelengths of a path in nanometres, the first being the hero
+/// wavelength, with the density each was sampled with.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    lambda: [f64; SPECTRUM_SAMPLES],
+    pdf: [f64; SPECTRUM_SAMPLES],
+}
+
+impl SampledWavelengths {
+    /// Picks a uniform hero wavelength and rotates it through the range
+    /// for the others.
+    pub fn sample(rand: &mut Rand) -> Self {
+        let u = rand.random_double();
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; SPECTRUM_SAMPLES];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f64 / SPECTRUM_SAMPLES as f64).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+        SampledWavelengths {
+            lambda,
+            pdf: [1.0 / range; SPECTRUM_SAMPLES],
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn get(&self, i: usize) -> f64 {
+        self.lambda[i]
+    }
+
+    /// Keeps only the hero wavelength, after an event such as dispersion
+    /// whose outcome was only valid for it.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        self.pdf[0] /= SPECTRUM_SAMPLES as f64;
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+}
+
+/// Radiance or reflectance at each of a path's wavelengths.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledSpectrum {
+    values: [f64; SPECTRUM_SAMPLES],
+}
+
+impl SampledSpectrum {
+    pub fn constant(value: f64) -> Self {
+        SampledSpectrum {
+            values: [value; SPECTRUM_SAMPLES],
+        }
+    }
+
+    /// Upsamples an RGB color to a smooth spectrum using Smits' method,
+    /// building it from white and the most saturated spectra that round
+    /// trip to each primary and secondary color. Light sources go through
+    /// the same conversion, so a color of (1, 1, 1) is a flat spectrum that
+    /// the film balances back to white.
+    pub fn from_rgb(color: &Color, lambda: &SampledWavelengths) -> Self {
+        let mut values = [0.0; SPECTRUM_SAMPLES];
+        for (i, v) in values.iter_mut().enumerate() {
+            *v = smits(color, lambda.get(i));
+        }
+        SampledSpectrum { values }
+    }
+
+    pub fn get(&self, i: usize) -> f64 {
+        self.values[i]
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.values.iter().fold(f64::NEG_INFINITY, |m, &v| m.max(v))
+    }
+
+    /// Monte Carlo estimate of the CIE XYZ tristimulus values, unnormalized.
+    pub fn to_xyz(&self, lambda: &SampledWavelengths) -> Vec3 {
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..SPECTRUM_SAMPLES {
+            if lambda.pdf[i] != 0.0 {
+                xyz += (self.values[i] / lambda.pdf[i]) * cie_xyz(lambda.get(i));
+            }
+        }
+        xyz / SPECTRUM_SAMPLES as f64
+    }
+
+    /// Linear sRGB, white balanced so that a flat spectrum of 1 is white.
+    pub fn to_rgb(&self, lambda: &SampledWavelengths) -> Color {
+        let rgb = xyz_to_linear_srgb(&self.to_xyz(lambda));
+        let white = *EQUAL_ENERGY_WHITE;
+        Color::new(
+            rgb.x() / white.x(),
+            rgb.y() / white.y(),
+            rgb.z() / white.z(),
+        )
+    }
+}
+
+overload!((a: ?SampledSpectrum) + (b: ?SampledSpectrum) -> SampledSpectrum {
+    let mut values = a.values;
+    for (v, b) in values.iter_mut().zip(b.values) {
+        *v += b;
+    }
+    SampledSpectrum { values }
+});
+overload!((a: ?SampledSpectrum) * (b: ?SampledSpectrum) -> SampledSpectrum {
+    let mut values = a.values;
+    for (v, b) in values.iter_mut().zip(b.values) {
+        *v *= b;
+    }
+    SampledSpectrum { values }
+});
+overload!((a: f64) * (b: ?SampledSpectrum) -> SampledSpectrum {
+    SampledSpectrum { values: b.values.map(|v| a * v) }
+});
+
+impl ops::AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// Wavelength-dependent index of refraction for dispersive dielectrics.
+/// Coefficients are for wavelengths in micrometres, as they are usually
+/// tabulated.
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    /// `n = a + b / λ²`
+    Cauchy { a: f64, b: f64 },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7 borosilicate crown glass.
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    /// Schott SF11 dense flint glass, which disperses strongly.
+    pub fn dense_flint() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    /// Index of refraction at `wavelength` nanometres.
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+/// The helium d-line, where glasses quote their index of refraction.
+pub const D_LINE: f64 = 587.56;
+
+fn piecewise_gaussian(lambda: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2° color matching functions, using the multi-lobe fit of
+/// Wyman, Sloan and Shirley (2013).
+fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu, s1, s2| piecewise_gaussian(lambda, mu, s1, s2);
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub(crate) fn xyz_to_linear_srgb(xyz: &Vec3) -> Color {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Color::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+
+lazy_static::lazy_static! {
+    /// Linear sRGB of a flat spectrum integrated in the same units as
+    /// `SampledSpectrum::to_xyz`.
+    static ref EQUAL_ENERGY_WHITE: Color = {
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            xyz += cie_xyz(lambda);
+            lambda += 1.0;
+        }
+        xyz_to_linear_srgb(&xyz)
+    };
+}
+
+// Smits' basis spectra, tabulated in ten equal bins from 380nm to 720nm.
+const SMITS_MIN: f64 = 380.0;
+const SMITS_MAX: f64 = 720.0;
+#[rustfmt::skip]
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+#[rustfmt::skip]
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+#[rustfmt::skip]
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+#[rustfmt::skip]
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+#[rustfmt::skip]
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+#[rustfmt::skip]
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+#[rustfmt::skip]
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+fn smits(color: &Color, lambda: f64) -> f64 {
+    let bin = ((lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * 10.0).clamp(0.0, 9.0) as usize;
+    let (r, g, b) = (color.x(), color.y(), color.z());
+    let [white, cyan, magenta, yellow, red, green, blue] = [
+        SMITS_WHITE,
+        SMITS_CYAN,
+        SMITS_MAGENTA,
+        SMITS_YELLOW,
+        SMITS_RED,
+        SMITS_GREEN,
+        SMITS_BLUE,
+    ]
+    .map(|s| s[bin]);
+
+    // Start from the white shared by all channels, then add the secondary
+    // color shared by the two largest, then the largest primary.
+    if r <= g && r <= b {
+        r * white
+            + if g <= b {
+                (g - r) * cyan + (b - g) * blue
+            } else {
+                (b - r) * cyan + (g - b) * green
+            }
+    } else if g <= r && g <= b {
+        g * white
+            + if r <= b {
+                (r - g) * magenta + (b - r) * blue
+            } else {
+                (b - g) * magenta + (r - b) * red
+            }
+    } else {
+        b * white
+            + if r <= g {
+                (r - b) * yellow + (g - r) * green
+            } else {
+                (g - b) * yellow + (r - g) * red
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glasses_match_their_catalogue_index() {
+        assert!((Dispersion::bk7().ior(D_LINE) - 1.5168).abs() < 1e-4);
+        assert!((Dispersion::dense_flint().ior(D_LINE) - 1.7847).abs() < 1e-4);
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.01 };
+        assert!((cauchy.ior(500.0) - 1.54).abs() < 1e-12);
+        // Blue light bends more than red.
+        for glass in [Dispersion::bk7(), Dispersion::dense_flint(), cauchy] {
+            assert!(glass.ior(450.0) > glass.ior(650.0));
+        }
+    }
+
+    #[test]
+    fn wavelengths_are_spread_over_the_range() {
+        let mut rand = Rand::new_with_seed(1);
+        let lambda = SampledWavelengths::sample(&mut rand);
+        let spacing = (LAMBDA_MAX - LAMBDA_MIN) / SPECTRUM_SAMPLES as f64;
+        let mut sorted: Vec<f64> = (0..SPECTRUM_SAMPLES).map(|i| lambda.get(i)).collect();
+        sorted.sort_by(f64::total_cmp);
+        for pair in sorted.windows(2) {
+            assert!((pair[1] - pair[0] - spacing).abs() < 1e-9);
+        }
+        assert!(sorted.iter().all(|l| (LAMBDA_MIN..LAMBDA_MAX).contains(l)));
+    }
+
+    /// Averages the film response to the upsampled `color`, optionally
+    /// keeping only the hero wavelength.
+    fn film_response(color: &Color, hero_only: bool) -> Color {
+        let mut rand = Rand::new_with_seed(2);
+        let n = 50_000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let mut lambda = SampledWavelengths::sample(&mut rand);
+            if hero_only {
+                lambda.terminate_secondary();
+            }
+            total += SampledSpectrum::from_rgb(color, &lambda).to_rgb(&lambda);
+        }
+        total / n as f64
+    }
+
+    #[test]
+    fn white_round_trips_to_white() {
+        let white = film_response(&Color::new(1.0, 1.0, 1.0), false);
+        assert!(
+            (white - Color::new(1.0, 1.0, 1.0)).length() < 0.02,
+            "{:?}",
+            white
+        );
+        // Dropping the secondary wavelengths adds noise but no bias.
+        let hero = film_response(&Color::new(1.0, 1.0, 1.0), true);
+        assert!((hero - white).length() < 0.05, "{:?}", hero);
+    }
+
+    #[test]
+    fn primaries_keep_their_hue() {
+        let red = film_response(&Color::new(1.0, 0.0, 0.0), false);
+        assert!(
+            red.x() > 5.0 * red.y().abs() && red.x() > 5.0 * red.z().abs(),
+            "{:?}",
+            red
+        );
+        let cyan = film_response(&Color::new(0.0, 1.0, 1.0), false);
+        assert!(cyan.y() > 5.0 * cyan.x().abs() && cyan.z() > 5.0 * cyan.x().abs());
+    }
+}
@@ -1,9 +1,7 @@
-use std::sync::Arc;
-
-use crate::{hittable::DidHit, material::Material, scene::MaterialId};
+use crate::{aabb::Aabb, hittable::DidHit, scene::MaterialId, util::PI, Vec3};
 
 use super::{
-    hittable::{HitRecord, Hittable},
+    hittable::{HitInterval, HitRecord, Hittable},
     ray::Ray,
     vec3::{dot, Point3},
 };
@@ -24,11 +22,47 @@ impl Sphere {
     }
 }
 
+impl Sphere {
+    fn record_at(&self, r: &Ray, t: f64) -> HitRecord {
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            ..Default::default()
+        };
+        let outward_normal = (rec.p - self.center) / self.radius;
+        rec.set_face_normal(r, &outward_normal);
+        (rec.u, rec.v) = sphere_uv(&outward_normal);
+        (rec.dpdu, rec.dpdv) = sphere_tangents(&outward_normal, self.radius);
+        rec.set_material_id(self.material_id);
+        rec
+    }
+}
+
+/// Texture coordinates of a point `p` on the unit sphere, with `u` running
+/// around the y axis from x = -1 and `v` from the south to the north pole.
+pub(crate) fn sphere_uv(p: &Point3) -> (f64, f64) {
+    let theta = (-p.y()).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+/// Derivatives of the point on a sphere of `radius` along the `sphere_uv`
+/// parameterization, at unit normal `n`. Both vanish at the poles.
+fn sphere_tangents(n: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    let ring = (n.x() * n.x() + n.z() * n.z()).sqrt();
+    if ring < 1e-9 {
+        return (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+    }
+    let dpdu = 2.0 * PI * radius * Vec3::new(n.z(), 0.0, -n.x());
+    let dpdv = PI * radius * Vec3::new(-n.y() * n.x() / ring, ring, -n.y() * n.z() / ring);
+    (dpdu, dpdv)
+}
+
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
-        let oc = r.origin() - &self.center;
+        let oc = r.origin() - self.center;
         let a = r.direction().length_squared();
-        let half_b = dot(&oc, &r.direction());
+        let half_b = dot(&oc, r.direction());
         let c = oc.length_squared() - self.radius * self.radius;
 
         let discriminant = half_b * half_b - a * c;
@@ -45,14 +79,29 @@ impl Hittable for Sphere {
                     return DidHit::Miss;
                 }
             }
-            let mut rec = HitRecord::default();
-            rec.t = root;
-            rec.p = r.at(rec.t);
-            let outward_normal = (rec.p - self.center) / self.radius;
-            rec.set_face_normal(r, &outward_normal);
-            rec.set_material_id(self.material_id);
-
-            return DidHit::Hit(rec);
+            DidHit::Hit(self.record_at(r, root))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        let oc = r.origin() - self.center;
+        let a = r.direction().length_squared();
+        let half_b = dot(&oc, r.direction());
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant <= 0.0 {
+            return vec![];
         }
+        let sqrtd = discriminant.sqrt();
+        vec![HitInterval {
+            enter: self.record_at(r, (-half_b - sqrtd) / a),
+            exit: self.record_at(r, (-half_b + sqrtd) / a),
+        }]
     }
 }
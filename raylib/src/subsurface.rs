@@ -0,0 +1,242 @@
+use crate::{
+    fresnel::fresnel_dielectric,
+    hittable::HitRecord,
+    material::{InteriorEvent, Lobe, Material, ScatterRecord},
+    medium::HenyeyGreenstein,
+    onb::Onb,
+    rand::Rand,
+    ray::Ray,
+    util::PI,
+    vec3::{dot, random_cosine_direction, reflect, refract, unit_vector, Color},
+    Vec3,
+};
+
+/// Subsurface scattering by a random walk through the inside of a closed
+/// object, for skin, wax, marble and milk. Light crosses the boundary into
+/// a homogeneous scattering medium and bounces around inside it until it
+/// finds its way out again, so it must be applied to closed surfaces.
+pub struct Subsurface {
+    /// Chance of a collision scattering rather than absorbing, per channel.
+    albedo: Color,
+    /// Average distance between collisions, per channel.
+    mean_free_path: Color,
+    phase: HenyeyGreenstein,
+    /// Index of refraction of the boundary, where 1.0 has no Fresnel
+    /// reflection.
+    ior: f64,
+    /// Fraction of light crossing the boundary that refracts through it
+    /// rather than being scattered diffusely.
+    refraction: f64,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Color, mean_free_path: Color) -> Self {
+        Subsurface {
+            albedo,
+            mean_free_path,
+            phase: HenyeyGreenstein::new(0.0),
+            ior: 1.0,
+            refraction: 0.0,
+        }
+    }
+
+    /// Anisotropy of scattering inside, see `HenyeyGreenstein`.
+    pub fn set_anisotropy(&mut self, g: f64) {
+        self.phase = HenyeyGreenstein::new(g)
+    }
+
+    /// Gives the object a smooth dielectric boundary of index `ior`, which
+    /// reflects by the Fresnel term and refracts `blend` of the light
+    /// crossing it. The rest still crosses diffusely, which keeps the
+    /// surface soft.
+    pub fn set_dielectric_boundary(&mut self, ior: f64, blend: f64) {
+        self.ior = ior.max(1.0);
+        self.refraction = blend.clamp(0.0, 1.0);
+    }
+
+    fn sigma_t(&self) -> [f64; 3] {
+        let mfp = self.mean_free_path;
+        [mfp.x(), mfp.y(), mfp.z()].map(|d| 1.0 / d.max(1e-9))
+    }
+
+    /// Fresnel reflectance of the boundary for light arriving from `r_in`.
+    fn boundary_reflectance(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let eta = if rec.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        };
+        let cos_theta = dot(&-unit_vector(r_in.direction()), &rec.normal);
+        fresnel_dielectric(cos_theta, eta)
+    }
+
+    /// Share of light leaving the boundary diffusely into the other side.
+    fn diffuse_weight(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        (1.0 - self.boundary_reflectance(r_in, rec)) * (1.0 - self.refraction)
+    }
+}
+
+impl Material for Subsurface {
+    fn sample(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> Option<ScatterRecord> {
+        let unit_direction = unit_vector(r_in.direction());
+        let reflectance = self.boundary_reflectance(r_in, rec);
+
+        if rand.random_double() < reflectance {
+            return Some(ScatterRecord {
+                scattered: Ray::new(rec.p, reflect(&unit_direction, &rec.normal)),
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                pdf: 0.0,
+                lobe: Lobe::Specular,
+                transmission: false,
+                dispersed: false,
+            });
+        }
+
+        let (direction, lobe, pdf) = if rand.random_double() < self.refraction {
+            let eta = if rec.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            };
+            let direction = refract(&unit_direction, &rec.normal, 1.0 / eta);
+            (direction, Lobe::Specular, 0.0)
+        } else {
+            let frame = Onb::build_from_w(&-rec.normal);
+            let direction = frame.local(&random_cosine_direction(rand));
+            (direction, Lobe::Diffuse, self.pdf(r_in, rec, &direction))
+        };
+
+        Some(ScatterRecord {
+            scattered: Ray::new(rec.p, direction),
+            attenuation: Color::new(1.0, 1.0, 1.0),
+            pdf,
+            lobe,
+            transmission: true,
+            dispersed: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> Color {
+        if dot(wi, &rec.normal) >= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let f = self.diffuse_weight(r_in, rec) / PI;
+        Color::new(f, f, f)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: &Vec3) -> f64 {
+        let cos_theta = -dot(&unit_vector(wi), &rec.normal);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        self.diffuse_weight(r_in, rec) * cos_theta / PI
+    }
+
+    fn non_specular_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        self.diffuse_weight(r_in, rec)
+    }
+
+    fn sample_interior(&self, r_in: &Ray, rec: &HitRecord, rand: &mut Rand) -> InteriorEvent {
+        if rec.front_face {
+            return InteriorEvent::Reached {
+                attenuation: Color::new(1.0, 1.0, 1.0),
+            };
+        }
+
+        // Sample a free flight distance for one channel, weighting by the
+        // average density over all three so every channel stays unbiased.
+        let sigma_t = self.sigma_t();
+        let channel = ((rand.random_double() * 3.0) as usize).min(2);
+        let distance = -(1.0 - rand.random_double()).ln() / sigma_t[channel];
+        let t_surface = rec.t * r_in.direction().length();
+
+        let flight = distance.min(t_surface);
+        let transmittance = sigma_t.map(|s| (-s * flight).exp());
+        let as_color = |c: [f64; 3]| Color::new(c[0], c[1], c[2]);
+
+        if distance >= t_surface {
+            let probability = transmittance.iter().sum::<f64>() / 3.0;
+            return InteriorEvent::Reached {
+                attenuation: as_color(transmittance) / probability,
+            };
+        }
+
+        let pdf = (0..3).map(|c| sigma_t[c] * transmittance[c]).sum::<f64>() / 3.0;
+        let sigma_s = self.albedo * as_color(sigma_t);
+        let unit_direction = unit_vector(r_in.direction());
+        InteriorEvent::Scatter {
+            attenuation: sigma_s * as_color(transmittance) / pdf,
+            scattered: Ray::new(
+                r_in.origin() + distance * unit_direction,
+                self.phase.sample(&unit_direction, rand),
+            ),
+        }
+    }
+
+    fn albedo(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::*;
+
+    fn wax() -> Subsurface {
+        Subsurface::new(Color::new(0.9, 0.7, 0.5), Color::new(0.5, 0.5, 0.5))
+    }
+
+    #[test]
+    fn boundary_is_consistent() {
+        let mut glossy = wax();
+        glossy.set_dielectric_boundary(1.4, 0.5);
+        for material in [wax(), glossy] {
+            for front_face in [true, false] {
+                let (r_in, rec) = hit_at(0.7, front_face);
+                let albedo = check_consistency(&material, &r_in, &rec);
+                // The boundary itself neither absorbs nor tints.
+                assert!((albedo - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn flights_follow_beer_lambert() {
+        let material = wax();
+        // A ray inside the object reaching its surface after one unit.
+        let (r_in, rec) = hit_at(1.0, false);
+        let mut rand = Rand::new_with_seed(5);
+        let n = 20_000;
+        let mut reached = 0;
+        for _ in 0..n {
+            match material.sample_interior(&r_in, &rec, &mut rand) {
+                InteriorEvent::Reached { attenuation } => {
+                    assert!((attenuation - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+                    reached += 1;
+                }
+                InteriorEvent::Scatter {
+                    attenuation,
+                    scattered,
+                } => {
+                    assert!((attenuation - material.albedo).length() < 1e-9);
+                    // The ray started one unit below the surface at z = 0.
+                    let z = scattered.origin().z();
+                    assert!((-1.0..0.0).contains(&z), "{}", z);
+                }
+            }
+        }
+        let fraction = reached as f64 / n as f64;
+        assert!((fraction - (-2.0f64).exp()).abs() < 0.01, "{}", fraction);
+    }
+
+    #[test]
+    fn rays_from_outside_reach_the_surface() {
+        let (r_in, rec) = hit_at(1.0, true);
+        let mut rand = Rand::new_with_seed(6);
+        match wax().sample_interior(&r_in, &rec, &mut rand) {
+            InteriorEvent::Reached { attenuation } => assert_eq!(attenuation.x(), 1.0),
+            InteriorEvent::Scatter { .. } => panic!("scattered outside the object"),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{fmt, sync::Arc};
+
+use crate::vec3::{Color, Point3};
+
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+/// Textures are shared between materials and parameters, so they are
+/// reference counted rather than boxed.
+pub type TextureRef = Arc<dyn Texture + Send + Sync>;
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        SolidColor { color }
+    }
+
+    /// A shared constant color texture.
+    pub fn shared(color: Color) -> TextureRef {
+        Arc::new(SolidColor::new(color))
+    }
+
+    /// A shared constant texture for scalar parameters, which read the
+    /// first channel.
+    pub fn scalar(value: f64) -> TextureRef {
+        SolidColor::shared(Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+/// 3D checkerboard alternating between two textures every `1 / scale`
+/// units.
+pub struct CheckerTexture {
+    even: TextureRef,
+    odd: TextureRef,
+    scale: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(even: TextureRef, odd: TextureRef, scale: f64) -> Self {
+        CheckerTexture { even, odd, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let sines =
+            (self.scale * p.x()).sin() * (self.scale * p.y()).sin() * (self.scale * p.z()).sin();
+        if sines < 0.0 {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    /// The image has a zero width or height.
+    EmptyImage,
+    /// The number of pixels differs from the width times the height.
+    WrongLength,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::EmptyImage => write!(f, "texture image is empty"),
+            TextureError::WrongLength => write!(f, "texture data does not match its size"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+/// Bilinearly filtered image, repeating outside `0..1` texture coordinates.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Linear colors, row by row from the top of the image.
+    data: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, data: Vec<Color>) -> Result<Self, TextureError> {
+        if width == 0 || height == 0 {
+            return Err(TextureError::EmptyImage);
+        }
+        if width.checked_mul(height) != Some(data.len()) {
+            return Err(TextureError::WrongLength);
+        }
+        Ok(ImageTexture {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Builds a texture from packed 8-bit RGB pixels. Color images are
+    /// usually sRGB encoded and should set `srgb`; data such as normal and
+    /// height maps is stored linearly.
+    pub fn from_rgb8(
+        width: usize,
+        height: usize,
+        pixels: &[u8],
+        srgb: bool,
+    ) -> Result<Self, TextureError> {
+        // Trailing bytes short of a whole pixel would otherwise be dropped.
+        if !pixels.len().is_multiple_of(3) {
+            return Err(TextureError::WrongLength);
+        }
+        let decode = |c: u8| {
+            let c = c as f64 / 255.0;
+            if srgb {
+                srgb_to_linear(c)
+            } else {
+                c
+            }
+        };
+        let data = pixels
+            .chunks_exact(3)
+            .map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        ImageTexture::new(width, height, data)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.data[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        // v runs up the image while rows run down it.
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        (1.0 - fx) * (1.0 - fy) * self.texel(x0, y0)
+            + fx * (1.0 - fy) * self.texel(x0 + 1, y0)
+            + (1.0 - fx) * fy * self.texel(x0, y0 + 1)
+            + fx * fy * self.texel(x0 + 1, y0 + 1)
+    }
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::PI;
+
+    fn sample(texture: &dyn Texture, u: f64, v: f64) -> Color {
+        texture.value(u, v, &Point3::new(0.0, 0.0, 0.0))
+    }
+
+    fn assert_near(a: Color, b: Color) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn image_filters_between_texel_centers() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let grey = Color::new(0.5, 0.5, 0.5);
+        let texture = ImageTexture::new(2, 1, vec![black, white]).unwrap();
+        // Texel centers sit at u = 0.25 and 0.75.
+        assert_near(sample(&texture, 0.25, 0.5), black);
+        assert_near(sample(&texture, 0.75, 0.5), white);
+        assert_near(sample(&texture, 0.5, 0.5), grey);
+        // The image repeats, so the left edge blends with the right.
+        assert_near(sample(&texture, 0.0, 0.5), grey);
+        assert_near(sample(&texture, 1.25, 0.5), black);
+    }
+
+    #[test]
+    fn image_rows_run_down() {
+        let top = Color::new(1.0, 0.0, 0.0);
+        let bottom = Color::new(0.0, 0.0, 1.0);
+        let texture = ImageTexture::new(1, 2, vec![top, bottom]).unwrap();
+        assert_near(sample(&texture, 0.5, 0.75), top);
+        assert_near(sample(&texture, 0.5, 0.25), bottom);
+    }
+
+    #[test]
+    fn decodes_srgb() {
+        let pixels = [0, 128, 255];
+        let linear = ImageTexture::from_rgb8(1, 1, &pixels, false).unwrap();
+        let srgb = ImageTexture::from_rgb8(1, 1, &pixels, true).unwrap();
+        assert_near(
+            sample(&linear, 0.5, 0.5),
+            Color::new(0.0, 128.0 / 255.0, 1.0),
+        );
+        assert_near(
+            sample(&srgb, 0.5, 0.5),
+            Color::new(0.0, 0.21586050011389923, 1.0),
+        );
+    }
+
+    #[test]
+    fn checker_alternates() {
+        // A scale of pi makes the cells one unit wide.
+        let checker = CheckerTexture::new(SolidColor::scalar(1.0), SolidColor::scalar(0.0), PI);
+        let at = |x: f64| checker.value(0.0, 0.0, &Point3::new(x, 0.5, 0.5)).x();
+        assert_eq!(at(0.5), 1.0);
+        assert_eq!(at(1.5), 0.0);
+        assert_eq!(at(2.5), 1.0);
+    }
+
+    #[test]
+    fn rejects_images_that_do_not_fit() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        assert!(matches!(
+            ImageTexture::new(0, 1, vec![]),
+            Err(TextureError::EmptyImage)
+        ));
+        assert!(matches!(
+            ImageTexture::new(2, 2, vec![white; 3]),
+            Err(TextureError::WrongLength)
+        ));
+        assert!(matches!(
+            ImageTexture::from_rgb8(1, 1, &[255; 4], true),
+            Err(TextureError::WrongLength)
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    fresnel::fresnel_thin_film,
+    hittable::HitRecord,
+    texture::{SolidColor, TextureRef},
+    vec3::Color,
+};
+
+/// Wavelengths in nanometres standing in for the red, green and blue
+/// channels when rendering in RGB.
+const CHANNEL_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+/// A transparent coating a few hundred nanometres thick, whose internal
+/// reflections interfere to give soap bubbles and oil slicks their colors.
+pub struct ThinFilm {
+    /// Thickness in nanometres, from the first channel.
+    thickness: TextureRef,
+    ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        ThinFilm {
+            thickness: SolidColor::scalar(thickness),
+            ior,
+        }
+    }
+
+    /// Varies the thickness over the surface, in nanometres.
+    pub fn set_thickness(&mut self, texture: TextureRef) {
+        self.thickness = texture
+    }
+
+    /// Reflectance of the coated surface at `rec`, where `outside` is the
+    /// index of the medium the light arrives from and the substrate's
+    /// complex index is given per channel. A spectral path evaluates
+    /// `wavelength` alone, giving a gray result that only holds for it.
+    pub(crate) fn reflectance(
+        &self,
+        rec: &HitRecord,
+        cos_theta_i: f64,
+        outside: f64,
+        substrate_eta: &Color,
+        substrate_k: &Color,
+        wavelength: Option<f64>,
+    ) -> Color {
+        let thickness = self.thickness.value(rec.u, rec.v, &rec.p).x().max(0.0);
+        let at = |wavelength: f64, eta: f64, k: f64| {
+            fresnel_thin_film(
+                cos_theta_i,
+                outside,
+                self.ior,
+                eta,
+                k,
+                thickness,
+                wavelength,
+            )
+        };
+
+        match wavelength {
+            Some(wavelength) => {
+                let r = at(
+                    wavelength,
+                    channel_at(substrate_eta, wavelength),
+                    channel_at(substrate_k, wavelength),
+                );
+                Color::new(r, r, r)
+            }
+            None => Color::new(
+                at(CHANNEL_WAVELENGTHS[0], substrate_eta.x(), substrate_k.x()),
+                at(CHANNEL_WAVELENGTHS[1], substrate_eta.y(), substrate_k.y()),
+                at(CHANNEL_WAVELENGTHS[2], substrate_eta.z(), substrate_k.z()),
+            ),
+        }
+    }
+}
+
+/// Interpolates a per-channel quantity between the channel wavelengths.
+fn channel_at(color: &Color, wavelength: f64) -> f64 {
+    let [red, green, blue] = CHANNEL_WAVELENGTHS;
+    if wavelength >= red {
+        color.x()
+    } else if wavelength >= green {
+        let t = (wavelength - green) / (red - green);
+        (1.0 - t) * color.y() + t * color.x()
+    } else if wavelength >= blue {
+        let t = (wavelength - blue) / (green - blue);
+        (1.0 - t) * color.z() + t * color.y()
+    } else {
+        color.z()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{tests::*, Conductor, Dielectric, Material},
+        rand::Rand,
+    };
+
+    #[test]
+    fn channels_interpolate_between_their_wavelengths() {
+        let color = Color::new(1.0, 2.0, 3.0);
+        assert_eq!(channel_at(&color, 700.0), 1.0);
+        assert_eq!(channel_at(&color, 532.0), 2.0);
+        assert_eq!(channel_at(&color, 400.0), 3.0);
+        assert!((channel_at(&color, 581.0) - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reflectance_varies_with_thickness() {
+        let (_, rec) = hit_at(1.0, true);
+        let glass = Color::new(1.5, 1.5, 1.5);
+        let none = Color::new(0.0, 0.0, 0.0);
+        let colors: Vec<Color> = [250.0, 300.0, 350.0]
+            .iter()
+            .map(|&t| ThinFilm::new(t, 1.33).reflectance(&rec, 1.0, 1.0, &glass, &none, None))
+            .collect();
+        assert!((colors[0] - colors[1]).length() > 0.01);
+        assert!((colors[1] - colors[2]).length() > 0.01);
+
+        // A spectral path sees one gray value for its wavelength.
+        let r = ThinFilm::new(300.0, 1.33).reflectance(&rec, 1.0, 1.0, &glass, &none, Some(532.0));
+        assert_eq!(r.x(), r.y());
+        assert!((r.y() - colors[1].y()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn coated_conductor_is_consistent() {
+        let mut material = Conductor::gold(0.4);
+        material.set_thin_film(ThinFilm::new(400.0, 1.4));
+        for cos_theta in [0.3, 0.9] {
+            let (r_in, rec) = hit_at(cos_theta, true);
+            check_consistency(&material, &r_in, &rec);
+        }
+    }
+
+    #[test]
+    fn coated_dielectric_splits_by_film_reflectance() {
+        // A soap bubble: a film of water with air on both sides.
+        let mut material = Dielectric::new(1.0);
+        material.set_thin_film(ThinFilm::new(300.0, 1.33));
+        let (r_in, rec) = hit_at(1.0, true);
+        let mut rand = Rand::new_with_seed(1);
+        let n = 20_000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            total += material.sample(&r_in, &rec, &mut rand).unwrap().attenuation;
+        }
+        // Reflected and transmitted light together are all of it.
+        let mean = total / n as f64;
+        assert!(
+            (mean - Color::new(1.0, 1.0, 1.0)).length() < 0.03,
+            "{:?}",
+            mean
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{nearest_crossing, Crossing, DidHit, HitInterval, Hittable},
+    onb::Onb,
+    poly::solve_quartic,
+    ray::Ray,
+    scene::MaterialId,
+    util::PI,
+    vec3::{dot, unit_vector, Point3},
+    Vec3,
+};
+
+/// Torus around `center`, lying in the plane perpendicular to `axis`.
+/// `major_radius` is the distance from the center to the middle of the
+/// tube and `minor_radius` the radius of the tube itself.
+pub struct Torus {
+    center: Point3,
+    frame: Onb,
+    major_radius: f64,
+    minor_radius: f64,
+    material_id: MaterialId,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material_id: MaterialId,
+    ) -> Self {
+        Torus {
+            center,
+            frame: Onb::build_from_w(&axis),
+            major_radius,
+            minor_radius,
+            material_id,
+        }
+    }
+
+    /// Every crossing of the ray's line with the surface, in order.
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        let o = self.frame.to_local(&(r.origin() - self.center));
+        // Solving with a unit direction keeps the quartic well conditioned.
+        let length = r.direction().length();
+        let d = self.frame.to_local(r.direction()) / length;
+
+        // Skip the quartic when the line misses the bounding sphere.
+        let bound = self.major_radius + self.minor_radius;
+        let f = dot(&o, &d);
+        if o.length_squared() - f * f > bound * bound {
+            return vec![];
+        }
+
+        let r2 = self.major_radius * self.major_radius;
+        let four_r2 = 4.0 * r2;
+        let e = o.length_squared() - r2 - self.minor_radius * self.minor_radius;
+
+        let mut crossings: Vec<Crossing> = solve_quartic([
+            e * e - four_r2 * (self.minor_radius * self.minor_radius - o.z() * o.z()),
+            4.0 * f * e + 2.0 * four_r2 * o.z() * d.z(),
+            2.0 * e + 4.0 * f * f + four_r2 * d.z() * d.z(),
+            4.0 * f,
+            1.0,
+        ])
+        .into_iter()
+        .map(|t| {
+            let p = o + t * d;
+            let ring = (p.x() * p.x() + p.y() * p.y()).sqrt();
+            let tube_center = Vec3::new(p.x(), p.y(), 0.0) * (self.major_radius / ring);
+            let phi = p.y().atan2(p.x());
+            let psi = p.z().atan2(ring - self.major_radius);
+            let dpdv = 2.0
+                * PI
+                * self.minor_radius
+                * Vec3::new(-psi.sin() * phi.cos(), -psi.sin() * phi.sin(), psi.cos());
+            Crossing {
+                t: t / length,
+                outward_normal: self.frame.local(&unit_vector(&(p - tube_center))),
+                u: (phi + PI) / (2.0 * PI),
+                v: (psi + PI) / (2.0 * PI),
+                dpdu: self
+                    .frame
+                    .local(&(2.0 * PI * Vec3::new(-p.y(), p.x(), 0.0))),
+                dpdv: self.frame.local(&dpdv),
+            }
+        })
+        .collect();
+
+        crossings.sort_by(|a, b| a.t.total_cmp(&b.t));
+        crossings
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> DidHit {
+        nearest_crossing(r, &self.crossings(r), t_min, t_max, self.material_id)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let w = self.frame.w();
+        let extent = |a: f64| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius;
+        let e = Vec3::new(extent(w.x()), extent(w.y()), extent(w.z()));
+        Some(Aabb::new(self.center - e, self.center + e))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        let crossings = self.crossings(r);
+        // A tangent ray can produce an odd root count; treat it as a miss.
+        if !crossings.len().is_multiple_of(2) {
+            return vec![];
+        }
+        crossings
+            .chunks_exact(2)
+            .map(|pair| HitInterval {
+                enter: pair[0].to_record(r, self.material_id),
+                exit: pair[1].to_record(r, self.material_id),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn torus() -> Torus {
+        Torus::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            0.5,
+            0,
+        )
+    }
+
+    fn span(r: &Ray) -> Vec<(f64, f64)> {
+        torus()
+            .intervals(r)
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect()
+    }
+
+    fn assert_spans(actual: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.0 - e.0).abs() < 1e-6 && (a.1 - e.1).abs() < 1e-6,
+                "{:?}",
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn crosses_the_tube_twice_through_the_hole() {
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_spans(span(&r), &[(2.5, 3.5), (6.5, 7.5)]);
+        match torus().hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.normal - Vec3::new(-1.0, 0.0, 0.0)).near_zero()),
+            DidHit::Miss => panic!("missed the tube"),
+        }
+    }
+
+    #[test]
+    fn crosses_the_tube_along_the_axis() {
+        let r = Ray::new(Point3::new(2.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_spans(span(&r), &[(4.5, 5.5)]);
+        match torus().hit(&r, 0.001, f64::INFINITY) {
+            DidHit::Hit(rec) => assert!((rec.normal - Vec3::new(0.0, -1.0, 0.0)).near_zero()),
+            DidHit::Miss => panic!("missed the tube"),
+        }
+    }
+
+    #[test]
+    fn misses_through_the_hole() {
+        let r = Ray::new(Point3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(matches!(
+            torus().hit(&r, 0.001, f64::INFINITY),
+            DidHit::Miss
+        ));
+        assert!(span(&r).is_empty());
+    }
+}
@@ -1,8 +1,8 @@
-use std::{ops::Range, sync::Mutex};
+use std::ops::Range;
 
 use crate::rand::Rand;
 
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
@@ -4,7 +4,7 @@ use overload::overload;
 
 use crate::{
     rand::Rand,
-    util::{clamp, random_double_in_range},
+    util::{clamp, random_double_in_range, PI},
 };
 
 pub type Point3 = Vec3;
@@ -77,6 +77,14 @@ impl Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        &self.e[i]
+    }
+}
+
 impl ops::AddAssign for Vec3 {
     fn add_assign(&mut self, other: Self) {
         *self = Self::new(
@@ -184,6 +192,24 @@ pub fn random_unit_vector(rand: &mut Rand) -> Vec3 {
     unit_vector(&Vec3::random_in_unit_sphere(rand))
 }
 
+/// Cosine-weighted direction on the hemisphere around +z.
+pub fn random_cosine_direction(rand: &mut Rand) -> Vec3 {
+    let r1 = rand.random_double();
+    let r2 = rand.random_double();
+    let phi = 2.0 * PI * r1;
+    let r = r2.sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r2).sqrt())
+}
+
+/// Uniformly distributed direction within the cone around +z whose edge
+/// is at `cos_max`, covering the whole sphere at -1.
+pub fn random_cone_direction(cos_max: f64, rand: &mut Rand) -> Vec3 {
+    let cos_theta = 1.0 - rand.random_double() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rand.random_double();
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+}
+
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     v - 2.0 * dot(v, n) * n
 }
@@ -195,13 +221,9 @@ pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
     r_out_perp + r_out_parallel
 }
 
-pub fn rgba(color: &Color) -> (u8, u8, u8, u8) {
-    (
-        (255.999 * color.x()) as u8,
-        (255.999 * color.y()) as u8,
-        (255.999 * color.z()) as u8,
-        255,
-    )
+/// Relative luminance of a linear Rec. 709 color.
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
 }
 
 pub fn rgba_multisampled(color: &Color, samples_per_pixel: u32) -> (u8, u8, u8, u8) {